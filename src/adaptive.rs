@@ -0,0 +1,241 @@
+//! Adaptive number of connections: the download starts with a few connections, and opens more
+//! while the total throughput keeps rising. The number of connections is halved when a server
+//! asks to slow down (429, 503) or resets the connections.
+
+use std::cmp::min;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use Bytes;
+
+/// Number of connections opened at the beginning of an adaptive download
+pub const INITIAL_CONNECTIONS: usize = 2;
+
+/// Maximum number of connections of an adaptive download, when the number of connections per
+/// server is not limited
+pub const DEFAULT_MAX_CONNECTIONS: usize = 32;
+
+/// Interval between two measures of the throughput, in milliseconds
+const MEASURE_INTERVAL_MILLIS: u64 = 1000;
+
+/// Minimum relative increase of the throughput to open one more connection
+const GROWTH_THRESHOLD: f64 = 0.05;
+
+/// Number of measures to skip after a back off, before opening new connections again
+const BACKOFF_COOLDOWN_MEASURES: usize = 5;
+
+/// State of the controller
+struct ControllerState {
+    /// Number of connections allowed
+    limit: usize,
+    /// Number of connections in use
+    active: usize,
+    /// Best throughput measured so far, in bytes per second
+    best_throughput: f64,
+    /// Number of measures to skip before opening new connections again
+    cooldown: usize,
+}
+
+/// Controller of the number of simultaneous connections of a download.
+/// Each connection has to be acquired before being used, and released after.
+pub struct ConnectionController {
+    state: Mutex<ControllerState>,
+    released: Condvar,
+    max_connections: usize,
+    downloaded_bytes: AtomicUsize,
+    finished: AtomicBool,
+}
+
+/// Connection acquired from a controller - the connection is released when dropped
+pub struct ConnectionPermit<'a> {
+    controller: &'a ConnectionController,
+}
+
+impl<'a> Drop for ConnectionPermit<'a> {
+    fn drop(&mut self) {
+        let mut state = self.controller.state.lock().unwrap();
+        state.active -= 1;
+        self.controller.released.notify_all();
+    }
+}
+
+impl ConnectionController {
+    /// Returns a controller that allows `INITIAL_CONNECTIONS` connections at first, and at most
+    /// `max_connections` connections
+    pub fn new(max_connections: usize) -> ConnectionController {
+        let max_connections = if max_connections == 0 { 1 } else { max_connections };
+        ConnectionController {
+            state: Mutex::new(ControllerState {
+                limit: min(INITIAL_CONNECTIONS, max_connections),
+                active: 0,
+                best_throughput: 0.,
+                cooldown: 0,
+            }),
+            released: Condvar::new(),
+            max_connections,
+            downloaded_bytes: AtomicUsize::new(0),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the maximum number of connections
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Returns the number of connections currently allowed
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// Wait until a connection is allowed, and take it
+    pub fn acquire(&self) -> ConnectionPermit<'_> {
+        let mut state = self.state.lock().unwrap();
+        while state.active >= state.limit {
+            state = self.released.wait(state).unwrap();
+        }
+        state.active += 1;
+        ConnectionPermit { controller: self }
+    }
+
+    /// Record that some bytes have been downloaded, to measure the throughput
+    pub fn add_bytes(&self, bytes: Bytes) {
+        self.downloaded_bytes.fetch_add(bytes as usize, Ordering::Relaxed);
+    }
+
+    /// Take into account a new measure of the total throughput (in bytes per second): one more
+    /// connection is allowed if the throughput is still rising
+    pub fn adjust(&self, throughput: f64) {
+        let mut state = self.state.lock().unwrap();
+        if state.cooldown > 0 {
+            state.cooldown -= 1;
+            return;
+        }
+        if throughput > state.best_throughput * (1. + GROWTH_THRESHOLD) {
+            state.best_throughput = throughput;
+            if state.limit < self.max_connections {
+                state.limit += 1;
+                self.released.notify_all();
+            }
+        }
+    }
+
+    /// Halve the number of connections allowed - the server asked to slow down.
+    /// The best throughput is scaled down along with the number of connections, so the
+    /// connections are opened again once the throughput rises from its new level.
+    pub fn back_off(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.cooldown > 0 {
+            // Already backing off, due to another connection
+            return;
+        }
+        let previous_limit = state.limit;
+        state.limit = if state.limit > 1 { state.limit / 2 } else { 1 };
+        state.best_throughput *= state.limit as f64 / previous_limit as f64;
+        state.cooldown = BACKOFF_COOLDOWN_MEASURES;
+        warning!(&format!(
+            "The server asks to slow down: using {} connection(s)",
+            state.limit
+        ));
+    }
+
+    /// Stop measuring the throughput
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+    }
+
+    /// Measure the throughput periodically, in order to adjust the number of connections,
+    /// until `finish` is called
+    pub fn monitor(controller: Arc<ConnectionController>) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let interval = Duration::from_millis(MEASURE_INTERVAL_MILLIS);
+            let mut last_measure = Instant::now();
+            while !controller.finished.load(Ordering::SeqCst) {
+                thread::sleep(interval);
+                let bytes = controller.downloaded_bytes.swap(0, Ordering::Relaxed);
+                let elapsed = last_measure.elapsed();
+                last_measure = Instant::now();
+                let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+                if seconds > 0. {
+                    controller.adjust(bytes as f64 / seconds);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_adaptive {
+
+    use super::{ConnectionController, BACKOFF_COOLDOWN_MEASURES, INITIAL_CONNECTIONS};
+
+    #[test]
+    fn rising_throughput_should_open_connections() {
+        let controller = ConnectionController::new(4);
+        assert_eq!(INITIAL_CONNECTIONS, controller.limit());
+        controller.adjust(1000.);
+        controller.adjust(2000.);
+        assert_eq!(4, controller.limit());
+        // The maximum number of connections is respected
+        controller.adjust(3000.);
+        assert_eq!(4, controller.limit());
+    }
+
+    #[test]
+    fn steady_throughput_should_not_open_connections() {
+        let controller = ConnectionController::new(32);
+        controller.adjust(1000.);
+        controller.adjust(1010.);
+        controller.adjust(990.);
+        assert_eq!(INITIAL_CONNECTIONS + 1, controller.limit());
+    }
+
+    #[test]
+    fn back_off_should_halve_the_connections() {
+        let controller = ConnectionController::new(32);
+        for throughput in 1..9 {
+            controller.adjust(f64::from(throughput) * 1000.);
+        }
+        assert_eq!(10, controller.limit());
+        controller.back_off();
+        controller.back_off();
+        assert_eq!(5, controller.limit());
+        // No new connection during the cooldown
+        for _ in 0..BACKOFF_COOLDOWN_MEASURES {
+            controller.adjust(1e9);
+        }
+        assert_eq!(5, controller.limit());
+        controller.adjust(1e9);
+        assert_eq!(6, controller.limit());
+    }
+
+    #[test]
+    fn throughput_should_grow_again_after_a_back_off() {
+        let controller = ConnectionController::new(32);
+        for throughput in 1..9 {
+            controller.adjust(f64::from(throughput) * 1000.);
+        }
+        assert_eq!(10, controller.limit());
+        controller.back_off();
+        for _ in 0..BACKOFF_COOLDOWN_MEASURES {
+            controller.adjust(4000.);
+        }
+        // The throughput of the remaining connections is below the previous peak (8000), but
+        // rises from its new level
+        controller.adjust(4500.);
+        assert_eq!(6, controller.limit());
+        controller.adjust(5000.);
+        assert_eq!(7, controller.limit());
+    }
+
+    #[test]
+    fn permits_should_be_limited() {
+        let controller = ConnectionController::new(1);
+        let permit = controller.acquire();
+        assert_eq!(1, controller.limit());
+        drop(permit);
+        let _permit = controller.acquire();
+    }
+
+}
@@ -0,0 +1,250 @@
+//! Allocation of the local file, before downloading the remote content: the free space of the
+//! file system is checked first, then the file is extended (sparse file) or really allocated
+//! (`fallocate`).
+
+use filesize::StringFileSize;
+use std::error;
+use std::fmt;
+use std::fs::{metadata, File};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use Bytes;
+
+/// The ways to allocate the local file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileAllocation {
+    /// The file grows while the chunks are written
+    None,
+    /// The file is extended to its final length, without allocating its blocks (sparse file)
+    Trunc,
+    /// The blocks of the file are allocated before the download (`fallocate`)
+    Falloc,
+}
+
+impl FromStr for FileAllocation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<FileAllocation, ()> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(FileAllocation::None),
+            "trunc" => Ok(FileAllocation::Trunc),
+            "falloc" => Ok(FileAllocation::Falloc),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for FileAllocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileAllocation::None => write!(f, "none"),
+            FileAllocation::Trunc => write!(f, "trunc"),
+            FileAllocation::Falloc => write!(f, "falloc"),
+        }
+    }
+}
+
+/// Some enumeration to display accurate allocation errors
+#[derive(Debug)]
+pub enum AllocationError {
+    /// Error throwed when the file system cannot be queried, or the file cannot be allocated
+    Io(io::Error),
+    /// Error throwed when the file system does not have enough free space for the file
+    NotEnoughSpace { needed: Bytes, available: Bytes },
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AllocationError::Io(ref err) => write!(f, "{}", err),
+            AllocationError::NotEnoughSpace { needed, available } => write!(
+                f,
+                "not enough free space on the disk: {} needed, {} available",
+                StringFileSize::from(needed),
+                StringFileSize::from(available)
+            ),
+        }
+    }
+}
+
+impl error::Error for AllocationError {
+    fn description(&self) -> &str {
+        match *self {
+            AllocationError::Io(_) => "I/O error",
+            AllocationError::NotEnoughSpace { .. } => "Not enough free space",
+        }
+    }
+}
+
+impl From<io::Error> for AllocationError {
+    fn from(err: io::Error) -> AllocationError {
+        AllocationError::Io(err)
+    }
+}
+
+/// Returns the number of bytes available to the user on the file system of the given path
+#[cfg(unix)]
+pub fn get_available_space(path: &Path) -> io::Result<Bytes> {
+    use std::ffi::CString;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut stats: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stats.f_bavail as Bytes * stats.f_frsize as Bytes)
+}
+
+#[cfg(not(unix))]
+pub fn get_available_space(_path: &Path) -> io::Result<Bytes> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "the free space cannot be checked on this platform",
+    ))
+}
+
+/// Check that the file system has enough free space to store `length` bytes at the given
+/// path - if a file already exists at this path, it is going to be replaced, so its space is
+/// counted as free.
+/// If the free space cannot be checked on this platform, this function returns Ok.
+pub fn check_free_space(path: &Path, length: Bytes) -> Result<(), AllocationError> {
+    if cfg!(not(unix)) {
+        return Ok(());
+    }
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let available = get_available_space(directory)?;
+    let replaced_length = match metadata(path) {
+        Ok(ref metadata) if metadata.is_file() => metadata.len(),
+        _ => 0,
+    };
+    let needed = length.saturating_sub(replaced_length);
+    if needed > available {
+        return Err(AllocationError::NotEnoughSpace { needed, available });
+    }
+    Ok(())
+}
+
+/// Allocate the blocks of the file, up to `length` bytes
+#[cfg(target_os = "linux")]
+fn fallocate(file: &File, length: Bytes) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // posix_fallocate returns the error, instead of setting errno
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, length as libc::off_t) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fallocate(file: &File, length: Bytes) -> io::Result<()> {
+    file.set_len(length)
+}
+
+/// Allocate the local file, to store `length` bytes
+pub fn allocate(file: &File, length: Bytes, allocation: FileAllocation) -> Result<(), AllocationError> {
+    let result = match allocation {
+        FileAllocation::None => return Ok(()),
+        FileAllocation::Trunc => file.set_len(length),
+        FileAllocation::Falloc => fallocate(file, length),
+    };
+    match result {
+        Err(ref err) if err.raw_os_error() == Some(libc::ENOSPC) => {
+            Err(AllocationError::NotEnoughSpace {
+                needed: length,
+                available: 0,
+            })
+        }
+        Err(err) => Err(AllocationError::Io(err)),
+        Ok(()) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test_allocation {
+
+    use super::{allocate, check_free_space, AllocationError, FileAllocation};
+    use std::env;
+    use std::fs::{remove_file, File};
+    use std::process;
+
+    #[test]
+    fn allocation_modes_should_be_parsed() {
+        assert_eq!(Ok(FileAllocation::None), "none".parse());
+        assert_eq!(Ok(FileAllocation::Trunc), "trunc".parse());
+        assert_eq!(Ok(FileAllocation::Falloc), "FALLOC".parse());
+        assert!("prealloc".parse::<FileAllocation>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn huge_file_should_not_fit_on_the_disk() {
+        let path = env::temp_dir().join("zou-huge-file");
+        match check_free_space(&path, u64::MAX) {
+            Err(AllocationError::NotEnoughSpace { needed, available }) => {
+                assert_eq!(u64::MAX, needed);
+                assert!(available < needed);
+            }
+            _ => panic!("the lack of free space should be detected"),
+        }
+        assert!(check_free_space(&path, 0).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn existing_file_should_be_counted_as_free_space() {
+        use super::get_available_space;
+
+        // e.g. the temporary file of a resumed download
+        let path = env::temp_dir().join(format!("zou-existing-file-{}", process::id()));
+        let existing_length = 64 * 1_048_576;
+        File::create(&path).unwrap().set_len(existing_length).unwrap();
+        let available = get_available_space(&env::temp_dir()).unwrap();
+        let results = (
+            check_free_space(&path, available + existing_length / 2),
+            check_free_space(&path, available + 2 * existing_length),
+        );
+        remove_file(&path).unwrap();
+        assert!(results.0.is_ok());
+        match results.1 {
+            Err(AllocationError::NotEnoughSpace { .. }) => {}
+            _ => panic!("only the length of the existing file should be counted as free"),
+        }
+    }
+
+    #[test]
+    fn files_should_be_allocated() {
+        for allocation in &[FileAllocation::None, FileAllocation::Trunc, FileAllocation::Falloc] {
+            let path = env::temp_dir().join(format!("zou-allocation-{}-{}", allocation, process::id()));
+            let file = File::create(&path).unwrap();
+            allocate(&file, 100_000, *allocation).unwrap();
+            let length = file.metadata().unwrap().len();
+            remove_file(&path).unwrap();
+            if *allocation == FileAllocation::None {
+                assert_eq!(0, length);
+            } else {
+                assert_eq!(100_000, length);
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn falloc_should_allocate_the_blocks() {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = env::temp_dir().join(format!("zou-falloc-{}", process::id()));
+        let file = File::create(&path).unwrap();
+        allocate(&file, 1_000_000, FileAllocation::Falloc).unwrap();
+        let blocks = file.metadata().unwrap().blocks();
+        remove_file(&path).unwrap();
+        assert!(blocks * 512 >= 1_000_000);
+    }
+}
@@ -0,0 +1,441 @@
+//! Pool of the connections of the asynchronous HTTP backend. Like the pool of the Hyper 0.10
+//! clients (with `HostLimitedConnector`), it limits the number of simultaneous connections per
+//! host, and a connection is given back to the pool as soon as the body of its response has
+//! been received, so that the next request to the host reuses it.
+//! When a server negotiates HTTP/2 (ALPN), its single connection is shared: the requests are
+//! multiplexed as streams of the connection.
+
+use connector::get_host_key;
+use http::{Request, Response, Uri};
+use http_body_util::Empty;
+use hyper1::body::{Body, Bytes as BodyBytes, Frame, Incoming, SizeHint};
+use hyper1::client::conn::{http1, http2};
+use hyper_rustls::{HttpsConnector, MaybeHttpsStream};
+use hyper_util::client::legacy::connect::{Connection, HttpConnector};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio;
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+/// Body of the requests - zou never sends content
+pub type RequestBody = Empty<BodyBytes>;
+
+/// Error of a connection
+pub type ConnectError = Box<dyn Error + Send + Sync>;
+
+/// Connector to the servers, through HTTP or HTTPS - picked for each connection, using the
+/// scheme of the URI
+pub type AsyncConnector = HttpsConnector<HttpConnector>;
+
+/// Stream of a connection to a server
+type Io = MaybeHttpsStream<TokioIo<TcpStream>>;
+
+/// Future run by the runtime of the backend
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Result of the handshake of a new HTTP/1 connection: the sender of the requests, and the
+/// connection to run
+type Http1Handshake = ::hyper1::Result<(http1::SendRequest<RequestBody>, http1::Connection<Io, RequestBody>)>;
+
+/// Result of the handshake of a new HTTP/2 connection
+type Http2Handshake =
+    ::hyper1::Result<(http2::SendRequest<RequestBody>, http2::Connection<Io, RequestBody, TokioExecutor>)>;
+
+/// Connections to a host (and port)
+#[derive(Default)]
+struct Host {
+    /// HTTP/1 connections kept alive, that wait for a request
+    idle: Vec<http1::SendRequest<RequestBody>>,
+    /// HTTP/2 connection, shared by the requests
+    shared: Option<http2::SendRequest<RequestBody>>,
+    /// The host has negotiated HTTP/2, so a single connection is opened
+    http2: bool,
+    /// Number of connections that are open, or being opened
+    open: usize,
+    /// Tasks that wait for a connection to the host
+    waiting: Vec<Waker>,
+}
+
+impl Host {
+    /// Wake up the tasks that wait for a connection to the host
+    fn wake_all(&mut self) {
+        for waker in self.waiting.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Connections of the pool, per host
+type Hosts = Arc<Mutex<HashMap<String, Host>>>;
+
+/// Returns the key of the connections to the host of the URI
+fn get_uri_key(uri: &Uri) -> String {
+    let default_port = if uri.scheme_str() == Some("https") { 443 } else { 80 };
+    get_host_key(uri.host().unwrap_or(""), uri.port_u16().unwrap_or(default_port))
+}
+
+/// Pool of connections, that opens at most `limit` connections per host - 0 means no limit.
+/// When the limit is reached, a request waits for a connection to the host to be given back
+/// to the pool, or to be closed.
+#[derive(Clone)]
+pub struct AsyncConnectionPool {
+    connector: AsyncConnector,
+    limit: usize,
+    max_idle: usize,
+    hosts: Hosts,
+}
+
+impl AsyncConnectionPool {
+    /// Returns a pool that opens at most `limit` connections per host, and keeps alive at most
+    /// `max_idle` of them while they wait for a request
+    pub fn new(connector: AsyncConnector, limit: usize, max_idle: usize) -> AsyncConnectionPool {
+        AsyncConnectionPool {
+            connector,
+            limit,
+            max_idle,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the future of a connection to the host of the URI: a connection kept alive if
+    /// any, otherwise a new connection once the limit of the host allows it.
+    /// The future must be run by a tokio runtime, that runs the connection once it is open.
+    pub fn checkout(&self, uri: &Uri) -> Checkout {
+        Checkout {
+            pool: self.clone(),
+            key: get_uri_key(uri),
+            uri: uri.clone(),
+            connecting: None,
+        }
+    }
+
+    /// Keep alive the connection, for the next request to the host
+    fn release(&self, key: &str, sender: http1::SendRequest<RequestBody>) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.entry(String::from(key)).or_default();
+        // The connection is closed when the sender is dropped
+        if host.idle.len() < self.max_idle && !sender.is_closed() {
+            host.idle.push(sender);
+        }
+        host.wake_all();
+    }
+}
+
+/// Future of a connection to a host - see `AsyncConnectionPool::checkout`
+pub struct Checkout {
+    pool: AsyncConnectionPool,
+    key: String,
+    uri: Uri,
+    connecting: Option<Connecting>,
+}
+
+impl Future for Checkout {
+    type Output = Result<PooledConnection, ConnectError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if this.connecting.is_none() {
+            let mut hosts = this.pool.hosts.lock().unwrap();
+            let host = hosts.entry(this.key.clone()).or_default();
+            if let Some(shared) = host.shared.take() {
+                if !shared.is_closed() {
+                    host.shared = Some(shared.clone());
+                    return Poll::Ready(Ok(this.get_connection(Sender::Http2(shared), true)));
+                }
+            }
+            // The connections closed by the server while they were idle are dropped
+            while let Some(sender) = host.idle.pop() {
+                if !sender.is_closed() {
+                    return Poll::Ready(Ok(this.get_connection(Sender::Http1(sender), true)));
+                }
+            }
+            // A host that multiplexes the requests gets a single connection, even without limit
+            let limit = if host.http2 { 1 } else { this.pool.limit };
+            if limit != 0 && host.open >= limit {
+                host.waiting.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            host.open += 1;
+            // The HTTP connector is always ready, so the service is called right away
+            this.connecting = Some(Connecting {
+                connecting: Some(this.pool.connector.clone().call(this.uri.clone())),
+                handshaking: None,
+                open: Some(OpenConnection {
+                    hosts: this.pool.hosts.clone(),
+                    key: this.key.clone(),
+                }),
+            });
+        }
+        let connecting = this
+            .connecting
+            .as_mut()
+            .expect("The connection is opened when no connection is available");
+        let sender = match Pin::new(connecting).poll(cx) {
+            Poll::Ready(sender) => sender,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.connecting = None;
+        Poll::Ready(Ok(this.get_connection(sender?, false)))
+    }
+}
+
+impl Checkout {
+    /// Returns the connection taken from the pool
+    fn get_connection(&self, sender: Sender, reused: bool) -> PooledConnection {
+        PooledConnection {
+            pool: self.pool.clone(),
+            key: self.key.clone(),
+            sender,
+            reused,
+        }
+    }
+}
+
+/// Connection counted by its host, until it is dropped
+struct OpenConnection {
+    hosts: Hosts,
+    key: String,
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(host) = hosts.get_mut(&self.key) {
+            host.open -= 1;
+            host.wake_all();
+        }
+    }
+}
+
+/// Handshake of a new connection, using the protocol negotiated with the server
+enum Handshaking {
+    Http1(BoxFuture<Http1Handshake>),
+    Http2(BoxFuture<Http2Handshake>),
+}
+
+/// Sender of the requests through a connection
+enum Sender {
+    Http1(http1::SendRequest<RequestBody>),
+    Http2(http2::SendRequest<RequestBody>),
+}
+
+/// Future of a new connection: the connector opens the stream (and negotiates TLS and the
+/// protocol), then the HTTP connection is run by the runtime
+struct Connecting {
+    connecting: Option<<AsyncConnector as Service<Uri>>::Future>,
+    handshaking: Option<Handshaking>,
+    open: Option<OpenConnection>,
+}
+
+impl Connecting {
+    /// Run the connection until it is closed - it is counted by its host until then
+    fn run<C>(&mut self, connection: C) -> Result<(), ConnectError>
+    where
+        C: Future + Send + Unpin + 'static,
+    {
+        match self.open.take() {
+            Some(open) => {
+                tokio::spawn(RunConnection {
+                    connection,
+                    _open: open,
+                });
+                Ok(())
+            }
+            None => Err(ConnectError::from("the connection is already open")),
+        }
+    }
+
+    /// Share the new HTTP/2 connection with the next requests to its host
+    fn share(&self, sender: &http2::SendRequest<RequestBody>) {
+        if let Some(ref open) = self.open {
+            let mut hosts = open.hosts.lock().unwrap();
+            if let Some(host) = hosts.get_mut(&open.key) {
+                host.shared = Some(sender.clone());
+                host.http2 = true;
+                host.wake_all();
+            }
+        }
+    }
+}
+
+impl Future for Connecting {
+    type Output = Result<Sender, ConnectError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Some(ref mut connecting) = this.connecting {
+            let io = match Pin::new(connecting).poll(cx) {
+                Poll::Ready(io) => io?,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.connecting = None;
+            this.handshaking = Some(if io.connected().is_negotiated_h2() {
+                Handshaking::Http2(Box::pin(http2::handshake(TokioExecutor::new(), io)))
+            } else {
+                Handshaking::Http1(Box::pin(http1::handshake(io)))
+            });
+        }
+        let sender = match this.handshaking {
+            Some(Handshaking::Http1(ref mut handshaking)) => match handshaking.as_mut().poll(cx) {
+                Poll::Ready(handshake) => {
+                    let (sender, connection) = handshake?;
+                    this.run(connection)?;
+                    Sender::Http1(sender)
+                }
+                Poll::Pending => return Poll::Pending,
+            },
+            Some(Handshaking::Http2(ref mut handshaking)) => match handshaking.as_mut().poll(cx) {
+                Poll::Ready(handshake) => {
+                    let (sender, connection) = handshake?;
+                    this.share(&sender);
+                    this.run(connection)?;
+                    Sender::Http2(sender)
+                }
+                Poll::Pending => return Poll::Pending,
+            },
+            None => return Poll::Ready(Err(ConnectError::from("the connection is already open"))),
+        };
+        this.handshaking = None;
+        Poll::Ready(Ok(sender))
+    }
+}
+
+/// Future of a connection, until it is closed: its errors are received by the requests
+struct RunConnection<C> {
+    connection: C,
+    _open: OpenConnection,
+}
+
+impl<C: Future + Unpin> Future for RunConnection<C> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        Pin::new(&mut self.connection).poll(cx).map(|_| ())
+    }
+}
+
+/// Connection taken from the pool, to send a request
+pub struct PooledConnection {
+    pool: AsyncConnectionPool,
+    key: String,
+    sender: Sender,
+    reused: bool,
+}
+
+impl PooledConnection {
+    /// Returns true if the connection has already been used by another request - the server may
+    /// have closed it in the meantime
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Returns true if the requests are multiplexed as streams of the connection (HTTP/2)
+    pub fn is_http2(&self) -> bool {
+        match self.sender {
+            Sender::Http1(_) => false,
+            Sender::Http2(_) => true,
+        }
+    }
+
+    /// Poll the connection until it is ready to send the request: a connection given back to
+    /// the pool may still be receiving the end of the previous response
+    pub fn poll_ready(&mut self, cx: &mut Context) -> Poll<::hyper1::Result<()>> {
+        match self.sender {
+            Sender::Http1(ref mut sender) => sender.poll_ready(cx),
+            Sender::Http2(ref mut sender) => sender.poll_ready(cx),
+        }
+    }
+
+    /// Returns the future of the response to the request, sent once the connection is ready
+    pub fn send_request(
+        &mut self,
+        request: Request<RequestBody>,
+    ) -> BoxFuture<::hyper1::Result<Response<Incoming>>> {
+        match self.sender {
+            Sender::Http1(ref mut sender) => Box::pin(sender.send_request(request)),
+            Sender::Http2(ref mut sender) => Box::pin(sender.send_request(request)),
+        }
+    }
+
+    /// Give back the connection to the pool, once the response has been received - an HTTP/2
+    /// connection stays shared anyway
+    fn release(self) {
+        if let Sender::Http1(sender) = self.sender {
+            self.pool.release(&self.key, sender);
+        }
+    }
+}
+
+/// Body of a response, that gives back its connection to the pool once it has been received.
+/// If the body is dropped before, its connection is closed.
+pub struct ResponseBody {
+    body: Incoming,
+    connection: Option<PooledConnection>,
+}
+
+impl ResponseBody {
+    /// Returns the body of a response received through the connection
+    pub fn new(body: Incoming, connection: PooledConnection) -> ResponseBody {
+        let mut body = ResponseBody {
+            body,
+            connection: Some(connection),
+        };
+        // Empty bodies (HEAD requests, `304 Not Modified`...) are received along with the headers
+        if body.body.is_end_stream() {
+            body.release();
+        }
+        body
+    }
+
+    fn release(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            connection.release();
+        }
+    }
+}
+
+impl Body for ResponseBody {
+    type Data = BodyBytes;
+    type Error = ::hyper1::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<BodyBytes>, ::hyper1::Error>>> {
+        let this = self.get_mut();
+        let frame = Pin::new(&mut this.body).poll_frame(cx);
+        if let Poll::Ready(None) = frame {
+            this.release();
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test_async_pool {
+
+    use super::get_uri_key;
+    use http::Uri;
+
+    #[test]
+    fn uri_key_should_use_the_default_port_of_the_scheme() {
+        let uri: Uri = "https://example.com/file".parse().unwrap();
+        assert_eq!("example.com:443", get_uri_key(&uri));
+        let uri: Uri = "http://example.com:8080/file".parse().unwrap();
+        assert_eq!("example.com:8080", get_uri_key(&uri));
+    }
+}
@@ -0,0 +1,722 @@
+//! Asynchronous HTTP backend, built on hyper 1 and tokio (cargo feature `async-http`).
+//! The backend owns a tokio runtime: its `Backend` implementation is a blocking facade over
+//! the asynchronous requests, for the existing callers, while `send` and
+//! `download_range_async` return futures, so that many downloads can share a few threads.
+
+use async_pool::{AsyncConnectionPool, Checkout, ConnectError, PooledConnection, RequestBody,
+                 ResponseBody};
+use authorization::{AuthorizationHeaderFactory, GetAuthorizationType};
+use backend::http::{check_range_response, get_content_length_header, get_probe, get_probe_header,
+                    get_range_request_header};
+use backend::{Backend, BackendError, BackendResult, Capabilities, Probe, RangeRequest, Validators};
+use client::{get_redirection_url, get_secure_url, Config, MAX_REDIRECTIONS};
+use contentlength::GetContentLength;
+use hsts::HstsStore;
+use http_version::ValidateHttpVersion;
+use http::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, HOST};
+use http::{Method, Request, Response, Uri, Version};
+use http_body_util::Empty;
+use hyper;
+use hyper::header::Headers;
+use hyper::status::StatusCode;
+use hyper::version::HttpVersion;
+use hyper::Url;
+use hyper1::body::{Body, Bytes as BodyBytes, Incoming};
+use hyper_rustls::HttpsConnectorBuilder;
+use std::cmp;
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tls::{get_async_tls_config, TlsError};
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::time::{sleep, Instant, Sleep};
+use write::OutputChunkWriter;
+use {Bytes, RangeBytes, URL};
+
+/// Future of the response to a request sent through a connection
+type ResponseFuture = Pin<Box<dyn Future<Output = ::hyper1::Result<Response<Incoming>>> + Send>>;
+
+/// Backend to access remote contents through HTTP or HTTPS, using hyper 1.
+/// Like `HttpBackend`, the backend owns a single pool of connections, so every request reuses
+/// the connections kept alive, and it honours the client configuration (TLS settings,
+/// connections per host, HSTS store, read timeout).
+/// The blocking functions must not be called from the tasks of a tokio runtime.
+pub struct AsyncHttpBackend {
+    /// Runtime of the connections - it is shut down without waiting for them, when dropped
+    runtime: Option<Runtime>,
+    pool: AsyncConnectionPool,
+    hsts: Option<Arc<HstsStore>>,
+    read_timeout: Option<Duration>,
+}
+
+impl AsyncHttpBackend {
+    /// Returns an HTTP backend, using the default TLS settings
+    pub fn new() -> AsyncHttpBackend {
+        AsyncHttpBackend::with_config(Config::default()).expect("Cannot initialize the SSL client")
+    }
+
+    /// Returns an HTTP backend, using a custom client configuration.
+    /// This function returns an error if the TLS settings cannot be applied.
+    pub fn with_config(config: Config) -> Result<AsyncHttpBackend, TlsError> {
+        let connector = HttpsConnectorBuilder::new()
+            .with_tls_config(get_async_tls_config(&config.tls)?)
+            .https_or_http()
+            .enable_http1()
+            .enable_http2()
+            .build();
+        let pool = AsyncConnectionPool::new(
+            connector,
+            config.max_connections_per_host,
+            config.get_max_idle_connections(),
+        );
+        let runtime = Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("zou-http")
+            .build()
+            .expect("Cannot start the runtime of the HTTP backend");
+        Ok(AsyncHttpBackend {
+            runtime: Some(runtime),
+            pool,
+            hsts: config.hsts,
+            read_timeout: config.read_timeout,
+        })
+    }
+
+    /// Returns the handle of the runtime of the backend, to run its futures
+    pub fn handle(&self) -> &Handle {
+        self.runtime
+            .as_ref()
+            .expect("The runtime is shut down only when dropping the backend")
+            .handle()
+    }
+
+    /// Returns the future of the response to a request, that follows the redirections: the
+    /// HTTP URLs of known HSTS hosts (the given one and each redirection) are upgraded to HTTPS,
+    /// and the HSTS policies sent back by the servers are recorded
+    pub fn send(&self, method: Method, url: URL, headers: &Headers) -> SendRequest {
+        SendRequest {
+            pool: self.pool.clone(),
+            hsts: self.hsts.clone(),
+            method,
+            next_url: Some(get_secure_url(url, self.hsts.as_deref())),
+            header_map: get_header_map(headers),
+            url: None,
+            request: None,
+            checkout: None,
+            connection: None,
+            response: None,
+            redirections: Vec::new(),
+            deadline: ReadDeadline::new(self.read_timeout),
+        }
+    }
+
+    /// Returns the future of the download of the requested range into the chunk writer.
+    /// The checks and the errors are the ones of `Backend::download_range`, and the chunk
+    /// writer is flushed once the range is downloaded.
+    /// The future must be run by a tokio runtime with the time driver enabled, e.g. the
+    /// runtime of the backend (`handle`).
+    pub fn download_range_async(
+        &self,
+        request: &RangeRequest,
+        chunk_writer: OutputChunkWriter,
+    ) -> RangeDownload {
+        let (http_header, has_if_range) = get_range_request_header(request);
+        RangeDownload {
+            url: String::from(request.url),
+            range: request.range.clone(),
+            partial: request.partial,
+            validators: request.validators.cloned(),
+            has_if_range,
+            sending: Some(self.send(Method::GET, request.url, &http_header)),
+            body: None,
+            deadline: ReadDeadline::new(self.read_timeout),
+            chunk_writer,
+            sum_bytes: 0,
+        }
+    }
+
+    /// Probe the remote content - if validators are given, the request is conditional, and this
+    /// function returns None if the remote content has not been modified
+    fn probe_with_validators(
+        &self,
+        url: URL,
+        auth: Option<&AuthorizationHeaderFactory>,
+        validators: Option<&Validators>,
+    ) -> BackendResult<Option<Probe>> {
+        let http_header = get_probe_header(auth, validators);
+        let response = self.handle().block_on(self.send(Method::HEAD, url, &http_header))?;
+        let auth_type = response.headers.get_authorization_type();
+
+        // The remote content length is useless if the server asks for an authorization
+        if auth_type.is_some() {
+            return Ok(Some(Probe {
+                auth_type,
+                ..Probe::default()
+            }));
+        }
+        if validators.is_some() && response.status == StatusCode::NotModified {
+            return Ok(None);
+        }
+        // The remote content is not available (not found, server error...)
+        if !response.status.is_success() {
+            return Err(BackendError::Status(response.status.to_u16()));
+        }
+
+        let probe = Probe {
+            multiplexed: response.version.is_multiplexed(),
+            ..get_probe(&response.url, &response.headers, &response.redirections)
+        };
+        // The connection of the HEAD response must be given back before sending another
+        // request, as the number of connections per host may be limited to a single one
+        drop(response);
+
+        let content_length = match probe.content_length {
+            Some(remote_content_length) => Some(remote_content_length),
+            None => {
+                warning!(
+                    "Cannot get the remote content length, using an \
+                                     HEADER request."
+                );
+                warning!(
+                    "Trying to send an HTTP request, to get the remote \
+                                     content length..."
+                );
+                let custom_http_header = get_content_length_header(auth);
+                let response = self
+                    .handle()
+                    .block_on(self.send(Method::GET, url, &custom_http_header))?;
+                response.headers.get_content_length()
+            }
+        };
+
+        Ok(Some(Probe {
+            content_length,
+            ..probe
+        }))
+    }
+}
+
+impl Default for AsyncHttpBackend {
+    fn default() -> AsyncHttpBackend {
+        AsyncHttpBackend::new()
+    }
+}
+
+impl Drop for AsyncHttpBackend {
+    fn drop(&mut self) {
+        // Dropping a runtime waits for its threads, which is forbidden from an asynchronous
+        // context - where the last reference to the backend may be dropped
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl Backend for AsyncHttpBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranges: true,
+            multiplexing: true,
+        }
+    }
+
+    fn probe(&self, url: URL, auth: Option<&AuthorizationHeaderFactory>) -> BackendResult<Probe> {
+        Ok(self.probe_with_validators(url, auth, None)?.unwrap_or_default())
+    }
+
+    /// The request sent to the server is conditional (`If-None-Match` and `If-Modified-Since`),
+    /// and the server sends back `304 Not Modified` if the remote content has not been modified.
+    fn probe_if_modified(
+        &self,
+        url: URL,
+        auth: Option<&AuthorizationHeaderFactory>,
+        validators: &Validators,
+    ) -> BackendResult<Option<Probe>> {
+        self.probe_with_validators(url, auth, Some(validators))
+    }
+
+    fn open_range(&self, request: &RangeRequest) -> BackendResult<Box<dyn Read + Send>> {
+        let (http_header, has_if_range) = get_range_request_header(request);
+        let response = self
+            .handle()
+            .block_on(self.send(Method::GET, request.url, &http_header))?;
+        check_range_response(request, has_if_range, response.status, &response.headers)?;
+        Ok(Box::new(BodyReader {
+            handle: self.handle().clone(),
+            body: response.body,
+            deadline: ReadDeadline::new(self.read_timeout),
+            data: BodyBytes::new(),
+        }))
+    }
+}
+
+/// Returns the headers to send with hyper 1, from the headers built for hyper 0.10
+fn get_header_map(headers: &Headers) -> HeaderMap {
+    let mut header_map = HeaderMap::new();
+    for header in headers.iter() {
+        let name = HeaderName::from_bytes(header.name().as_bytes());
+        let value = HeaderValue::from_str(&header.value_string());
+        if let (Ok(name), Ok(value)) = (name, value) {
+            header_map.append(name, value);
+        }
+    }
+    header_map
+}
+
+/// Returns the error of a request that cannot be sent as is
+fn get_invalid_input_error<E: Into<Box<dyn Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+/// Returns the request to send to the URI - HTTP/2 requests keep the whole URI
+fn get_request(method: &Method, uri: &Uri, header_map: &HeaderMap) -> Request<RequestBody> {
+    let mut request = Request::new(Empty::new());
+    *request.method_mut() = method.clone();
+    *request.uri_mut() = uri.clone();
+    *request.headers_mut() = header_map.clone();
+    request
+}
+
+/// Function to send the request through an HTTP/1 connection to the host: the target of the
+/// request is only the path (and the query) of the URI, and the host is sent in the `Host`
+/// header
+fn set_http1_target(request: &mut Request<RequestBody>) -> io::Result<()> {
+    let host = match request.uri().host() {
+        Some(host) => host,
+        None => return Err(get_invalid_input_error("the URL has no host")),
+    };
+    let host = match request.uri().port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => String::from(host),
+    };
+    let target = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |target| target.as_str())
+        .parse()
+        .map_err(get_invalid_input_error)?;
+    let host = HeaderValue::from_str(&host).map_err(get_invalid_input_error)?;
+    *request.uri_mut() = target;
+    request.headers_mut().insert(HOST, host);
+    Ok(())
+}
+
+/// Returns the headers received with hyper 1, as hyper 0.10 headers - the functions that read
+/// the headers are shared with `HttpBackend`
+fn get_headers(header_map: &HeaderMap) -> Headers {
+    let mut headers = Headers::new();
+    for (name, value) in header_map {
+        headers.append_raw(String::from(name.as_str()), value.as_bytes().to_vec());
+    }
+    headers
+}
+
+/// Returns the kind of the I/O error behind an error, if any
+fn get_io_error_kind(err: &(dyn Error + 'static)) -> io::ErrorKind {
+    let mut source = Some(err);
+    while let Some(cause) = source {
+        if let Some(io_error) = cause.downcast_ref::<io::Error>() {
+            return io_error.kind();
+        }
+        source = cause.source();
+    }
+    io::ErrorKind::Other
+}
+
+/// Returns the HTTP version of a response received with hyper 1, as the hyper 0.10 version
+fn get_http_version(version: Version) -> HttpVersion {
+    match version {
+        Version::HTTP_09 => HttpVersion::Http09,
+        Version::HTTP_10 => HttpVersion::Http10,
+        Version::HTTP_2 => HttpVersion::Http20,
+        _ => HttpVersion::Http11,
+    }
+}
+
+/// Returns the I/O error behind an error of hyper, so that the resets of the connections are
+/// still recognized (`BackendError::is_throttling`)
+fn get_io_error(err: ::hyper1::Error) -> io::Error {
+    io::Error::new(get_io_error_kind(&err), err)
+}
+
+/// Returns the I/O error behind an error of the connector, so that the refused connections are
+/// still recognized
+fn get_connect_error(err: ConnectError) -> io::Error {
+    io::Error::new(get_io_error_kind(&*err), err)
+}
+
+/// Returns the error of a request that did not receive anything during the read timeout
+fn get_timeout_error() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "the read timeout has expired")
+}
+
+/// Read timeout of a request: the timer restarts each time something is received
+struct ReadDeadline {
+    timeout: Option<Duration>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl ReadDeadline {
+    fn new(timeout: Option<Duration>) -> ReadDeadline {
+        ReadDeadline {
+            timeout,
+            sleep: None,
+        }
+    }
+
+    /// Restart the timer
+    fn reset(&mut self) {
+        if let (Some(timeout), Some(sleep)) = (self.timeout, self.sleep.as_mut()) {
+            sleep.as_mut().reset(Instant::now() + timeout);
+        }
+    }
+
+    /// Returns true if nothing has been received during the read timeout - otherwise, the task
+    /// is woken up when the timeout expires.
+    /// The timer is started by the first call, from the runtime.
+    fn is_expired(&mut self, cx: &mut Context) -> bool {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return false,
+        };
+        self.sleep
+            .get_or_insert_with(|| Box::pin(sleep(timeout)))
+            .as_mut()
+            .poll(cx)
+            .is_ready()
+    }
+}
+
+/// Poll the next bytes of a body - None at the end of the body
+fn poll_data(
+    body: &mut ResponseBody,
+    deadline: &mut ReadDeadline,
+    cx: &mut Context,
+) -> Poll<io::Result<Option<BodyBytes>>> {
+    loop {
+        match Pin::new(&mut *body).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                deadline.reset();
+                // The trailers are ignored
+                if let Ok(data) = frame.into_data() {
+                    return Poll::Ready(Ok(Some(data)));
+                }
+            }
+            Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(get_io_error(err))),
+            Poll::Ready(None) => return Poll::Ready(Ok(None)),
+            Poll::Pending if deadline.is_expired(cx) => return Poll::Ready(Err(get_timeout_error())),
+            Poll::Pending => return Poll::Pending,
+        }
+    }
+}
+
+/// Response sent back by a server, with the URL and the headers of each redirection that has
+/// been followed to get it
+pub struct AsyncResponse {
+    /// The URL of the response, after the redirections
+    pub url: Url,
+    pub status: StatusCode,
+    /// The HTTP version negotiated with the server
+    pub version: HttpVersion,
+    pub headers: Headers,
+    pub redirections: Vec<(Url, Headers)>,
+    pub body: ResponseBody,
+}
+
+/// Future of the response to a request, that follows the redirections like
+/// `send_following_redirections` - see `AsyncHttpBackend::send`
+pub struct SendRequest {
+    pool: AsyncConnectionPool,
+    hsts: Option<Arc<HstsStore>>,
+    method: Method,
+    /// URL of the next request to send, if any
+    next_url: Option<String>,
+    header_map: HeaderMap,
+    /// URL of the request in progress
+    url: Option<Url>,
+    /// Request waiting for a connection
+    request: Option<Request<RequestBody>>,
+    checkout: Option<Checkout>,
+    connection: Option<PooledConnection>,
+    response: Option<ResponseFuture>,
+    redirections: Vec<(Url, Headers)>,
+    deadline: ReadDeadline,
+}
+
+impl SendRequest {
+    /// Send the request to the next URL, through a connection of the pool
+    fn send_next(&mut self, next_url: &str) -> BackendResult<()> {
+        if self.redirections.len() > MAX_REDIRECTIONS {
+            return Err(BackendError::Io(io::Error::other("too many redirections")));
+        }
+        let url = Url::parse(next_url).map_err(hyper::Error::Uri)?;
+        let uri = next_url.parse::<Uri>().map_err(get_invalid_input_error)?;
+        self.checkout = Some(self.pool.checkout(&uri));
+        self.request = Some(get_request(&self.method, &uri, &self.header_map));
+        self.url = Some(url);
+        Ok(())
+    }
+
+    /// Poll the connection of the request until the request is sent
+    fn poll_send(&mut self, cx: &mut Context) -> Poll<BackendResult<()>> {
+        loop {
+            if let Some(ref mut checkout) = self.checkout {
+                let connection = match Pin::new(checkout).poll(cx) {
+                    Poll::Ready(connection) => connection.map_err(get_connect_error)?,
+                    Poll::Pending => return Poll::Pending,
+                };
+                self.checkout = None;
+                self.connection = Some(connection);
+            }
+            let connection = self
+                .connection
+                .as_mut()
+                .expect("The request is sent once a connection is available");
+            match connection.poll_ready(cx) {
+                Poll::Ready(Ok(())) => {}
+                // The server may have closed a connection kept alive in the meantime
+                Poll::Ready(Err(_)) if connection.is_reused() => {
+                    let request = self.request.as_ref().expect("The request is sent only once");
+                    self.connection = None;
+                    self.checkout = Some(self.pool.checkout(request.uri()));
+                    continue;
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(BackendError::Io(get_io_error(err)))),
+                Poll::Pending => return Poll::Pending,
+            }
+            let mut request = self.request.take().expect("The request is sent only once");
+            if !connection.is_http2() {
+                set_http1_target(&mut request)?;
+            }
+            self.response = Some(connection.send_request(request));
+            self.deadline.reset();
+            return Poll::Ready(Ok(()));
+        }
+    }
+}
+
+impl Future for SendRequest {
+    type Output = BackendResult<AsyncResponse>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<BackendResult<AsyncResponse>> {
+        let this = &mut *self;
+        loop {
+            if let Some(next_url) = this.next_url.take() {
+                this.send_next(&next_url)?;
+            }
+            if this.request.is_some() {
+                match this.poll_send(cx) {
+                    Poll::Ready(sent) => sent?,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            let response = match this.response.as_mut() {
+                Some(response) => match response.as_mut().poll(cx) {
+                    Poll::Ready(response) => response,
+                    Poll::Pending if this.deadline.is_expired(cx) => {
+                        return Poll::Ready(Err(BackendError::Io(get_timeout_error())))
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                None => {
+                    let err = io::Error::other("the response has already been received");
+                    return Poll::Ready(Err(BackendError::Io(err)));
+                }
+            };
+            this.response = None;
+            let (parts, body) = response.map_err(get_io_error)?.into_parts();
+            let connection = this
+                .connection
+                .take()
+                .expect("The connection is kept along with the response");
+            let body = ResponseBody::new(body, connection);
+            let url = this
+                .url
+                .take()
+                .expect("The URL is set along with the request");
+            let status = StatusCode::from_u16(parts.status.as_u16());
+            let headers = get_headers(&parts.headers);
+            if let Some(ref hsts) = this.hsts {
+                hsts.record(&url, &headers);
+            }
+            let next_url = match get_redirection_url(&url, status, &headers) {
+                Some(next_url) => get_secure_url(&next_url?, this.hsts.as_deref()),
+                None => {
+                    return Poll::Ready(Ok(AsyncResponse {
+                        url,
+                        status,
+                        version: get_http_version(parts.version),
+                        headers,
+                        redirections: mem::take(&mut this.redirections),
+                        body,
+                    }))
+                }
+            };
+            // Do not send the credentials to another host
+            if url.host_str() != Url::parse(&next_url).ok().as_ref().and_then(Url::host_str) {
+                this.header_map.remove(AUTHORIZATION);
+            }
+            if status == StatusCode::SeeOther && this.method != Method::HEAD {
+                this.method = Method::GET;
+            }
+            this.redirections.push((url, headers));
+            this.next_url = Some(next_url);
+        }
+    }
+}
+
+/// Future of the download of a range into a chunk writer - see
+/// `AsyncHttpBackend::download_range_async`.
+/// It returns the number of bytes that have been downloaded, i.e. the length of the range.
+pub struct RangeDownload {
+    url: String,
+    range: RangeBytes,
+    partial: bool,
+    validators: Option<Validators>,
+    has_if_range: bool,
+    sending: Option<SendRequest>,
+    body: Option<ResponseBody>,
+    deadline: ReadDeadline,
+    chunk_writer: OutputChunkWriter,
+    sum_bytes: Bytes,
+}
+
+impl Future for RangeDownload {
+    type Output = BackendResult<Bytes>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<BackendResult<Bytes>> {
+        let this = &mut *self;
+        if let Some(ref mut sending) = this.sending {
+            let response = match Pin::new(sending).poll(cx) {
+                Poll::Ready(response) => response,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.sending = None;
+            let response = response?;
+            let request = RangeRequest {
+                url: &this.url,
+                range: this.range.clone(),
+                auth: None,
+                partial: this.partial,
+                validators: this.validators.as_ref(),
+            };
+            check_range_response(&request, this.has_if_range, response.status, &response.headers)?;
+            this.body = Some(response.body);
+        }
+
+        let chunk_length = this.range.length();
+        if let Some(ref mut body) = this.body {
+            // Once the range is received, the end of the body is received too, so that the
+            // connection is kept alive
+            while this.sum_bytes < chunk_length || body.is_end_stream() {
+                let data = match poll_data(body, &mut this.deadline, cx) {
+                    Poll::Ready(data) => data?,
+                    Poll::Pending => return Poll::Pending,
+                };
+                let data = match data {
+                    Some(data) => data,
+                    None => break,
+                };
+                // The bytes after the end of the range are never written: they belong to
+                // another chunk
+                let n = cmp::min(data.len() as u64, chunk_length - this.sum_bytes) as usize;
+                if n > 0 {
+                    this.chunk_writer
+                        .write(this.sum_bytes, &data[..n])
+                        .map_err(BackendError::Write)?;
+                    this.sum_bytes += n as u64;
+                }
+            }
+        }
+        this.body = None;
+        this.chunk_writer.flush().map_err(BackendError::Write)?;
+        if this.sum_bytes < chunk_length {
+            return Poll::Ready(Err(BackendError::Truncated {
+                expected: chunk_length,
+                received: this.sum_bytes,
+            }));
+        }
+        Poll::Ready(Ok(this.sum_bytes))
+    }
+}
+
+/// Blocking reader of the body of a response, received by the runtime of the backend
+struct BodyReader {
+    handle: Handle,
+    body: ResponseBody,
+    deadline: ReadDeadline,
+    /// Bytes received, but not read yet
+    data: BodyBytes,
+}
+
+/// Future of the next bytes of a body - None at the end of the body
+struct NextData<'a> {
+    body: &'a mut ResponseBody,
+    deadline: &'a mut ReadDeadline,
+}
+
+impl<'a> Future for NextData<'a> {
+    type Output = io::Result<Option<BodyBytes>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<Option<BodyBytes>>> {
+        let this = &mut *self;
+        poll_data(this.body, this.deadline, cx)
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.data.is_empty() {
+            let next_data = NextData {
+                body: &mut self.body,
+                deadline: &mut self.deadline,
+            };
+            match self.handle.block_on(next_data)? {
+                Some(data) => self.data = data,
+                None => return Ok(0),
+            }
+        }
+        let n = cmp::min(buf.len(), self.data.len());
+        buf[..n].copy_from_slice(&self.data.split_to(n));
+        // The connection is kept alive only if the end of the body is received before the
+        // reader is dropped - the bytes are already read, so its errors do not matter
+        if self.data.is_empty() && self.body.is_end_stream() {
+            let end = NextData {
+                body: &mut self.body,
+                deadline: &mut self.deadline,
+            };
+            let _ = self.handle.block_on(end);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test_headers {
+
+    use super::{get_header_map, get_headers};
+    use hyper::header::{ByteRangeSpec, ContentLength, ETag, EntityTag, Headers, Range};
+
+    #[test]
+    fn headers_should_be_converted_both_ways() {
+        let mut headers = Headers::new();
+        headers.set(Range::Bytes(vec![ByteRangeSpec::FromTo(750, 997)]));
+        headers.set(ETag(EntityTag::strong(String::from("zou"))));
+        let header_map = get_header_map(&headers);
+        assert_eq!("bytes=750-997", header_map["range"]);
+        assert_eq!("\"zou\"", header_map["etag"]);
+
+        let mut header_map = header_map;
+        header_map.insert("content-length", "248".parse().unwrap());
+        let converted = get_headers(&header_map);
+        assert_eq!(headers.get::<Range>(), converted.get::<Range>());
+        assert_eq!(headers.get::<ETag>(), converted.get::<ETag>());
+        assert_eq!(Some(&ContentLength(248)), converted.get::<ContentLength>());
+    }
+}
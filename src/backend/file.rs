@@ -0,0 +1,160 @@
+use authorization::AuthorizationHeaderFactory;
+use backend::{Backend, BackendError, BackendResult, Capabilities, Probe, RangeRequest, Validators};
+use protocol::get_local_path;
+use std::cmp::min;
+use std::fs::{metadata, File};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use write::OutputChunkWriter;
+use {Bytes, URL};
+
+/// Constant to represent the length of each copy, for local files
+const COPY_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// Backend to access local files, using `file://` URLs
+pub struct FileBackend;
+
+/// Returns the local path targeted by the URL, or an error if the URL does not target a local file
+fn get_path(url: URL) -> io::Result<PathBuf> {
+    get_local_path(url).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "the URL does not target a local file")
+    })
+}
+
+/// Open the local file of the request, and check that it has not been modified since the
+/// download started
+fn open_source(request: &RangeRequest) -> BackendResult<File> {
+    let source = File::open(get_path(request.url)?)?;
+    request.check_validators(&Validators {
+        etag: None,
+        last_modified: source.metadata()?.modified().ok(),
+    })?;
+    Ok(source)
+}
+
+impl Backend for FileBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranges: true,
+            multiplexing: false,
+        }
+    }
+
+    fn probe(&self, url: URL, _: Option<&AuthorizationHeaderFactory>) -> BackendResult<Probe> {
+        let local_metadata = metadata(get_path(url)?)?;
+        if !local_metadata.is_file() {
+            return Err(From::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the targeted path is not a regular file",
+            )));
+        }
+        Ok(Probe {
+            content_length: Some(local_metadata.len()),
+            auth_type: None,
+            etag: None,
+            last_modified: local_metadata.modified().ok(),
+            ..Probe::default()
+        })
+    }
+
+    fn open_range(&self, request: &RangeRequest) -> BackendResult<Box<dyn Read + Send>> {
+        let mut source = open_source(request)?;
+        source.seek(SeekFrom::Start(request.range.0))?;
+        Ok(Box::new(source.take(request.range.length())))
+    }
+
+    /// On Linux, the range is copied using `copy_file_range` - if the file systems do not
+    /// support it, the range is copied using a classic read/write loop.
+    /// The other errors of the copy (e.g. no space left on the device) are reported as
+    /// `BackendError::Write`.
+    fn download_range(
+        &self,
+        request: &RangeRequest,
+        chunk_writer: &mut OutputChunkWriter,
+        progress: &mut dyn FnMut(Bytes),
+    ) -> BackendResult<Bytes> {
+        let mut source = open_source(request)?;
+        let (chunk_offset, chunk_length) = (request.range.0, request.range.length());
+        let mut bytes_buffer = vec![0; COPY_BUFFER_BYTES];
+        let mut sum_bytes = 0;
+        let mut in_kernel_copy = cfg!(target_os = "linux");
+
+        while sum_bytes < chunk_length {
+            let len = min(chunk_length - sum_bytes, COPY_BUFFER_BYTES as Bytes) as usize;
+
+            let mut n = 0;
+            if in_kernel_copy {
+                match copy_range(chunk_writer, sum_bytes, &source, chunk_offset + sum_bytes, len) {
+                    Ok(copied) => n = copied,
+                    // The file systems do not support in-kernel copies: fallback to read/write
+                    Err(ref err) if is_unsupported_copy(err) => in_kernel_copy = false,
+                    Err(err) => return Err(BackendError::Write(err)),
+                }
+            }
+            if !in_kernel_copy {
+                source.seek(SeekFrom::Start(chunk_offset + sum_bytes))?;
+                n = source.read(&mut bytes_buffer[0..len])?;
+                chunk_writer
+                    .write(sum_bytes, &bytes_buffer[0..n])
+                    .map_err(BackendError::Write)?;
+            }
+            // The local file has been truncated
+            if n == 0 {
+                return Err(BackendError::Truncated {
+                    expected: chunk_length,
+                    received: sum_bytes,
+                });
+            }
+            sum_bytes += n as u64;
+            progress(sum_bytes);
+        }
+        Ok(sum_bytes)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn copy_range(
+    chunk_writer: &mut OutputChunkWriter,
+    done_offset: Bytes,
+    source: &File,
+    source_offset: Bytes,
+    len: usize,
+) -> io::Result<usize> {
+    chunk_writer.copy_from(done_offset, source, source_offset, len)
+}
+
+/// Returns true if the in-kernel copy failed because the files do not support it: a copy
+/// between file systems, a kernel without `copy_file_range`, or an output that is not a file
+#[cfg(target_os = "linux")]
+fn is_unsupported_copy(err: &io::Error) -> bool {
+    if err.kind() == io::ErrorKind::Unsupported {
+        return true;
+    }
+    match err.raw_os_error() {
+        Some(code) => {
+            code == libc::EXDEV || code == libc::ENOSYS || code == libc::EINVAL
+                || code == libc::EOPNOTSUPP
+        }
+        None => false,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_unsupported_copy(_err: &io::Error) -> bool {
+    true
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_range(
+    _chunk_writer: &mut OutputChunkWriter,
+    _done_offset: Bytes,
+    _source: &File,
+    _source_offset: Bytes,
+    _len: usize,
+) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "in-kernel copies are not supported on this platform",
+    ))
+}
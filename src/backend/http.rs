@@ -0,0 +1,424 @@
+use authorization::{AuthorizationHeaderFactory, GetAuthorizationType};
+use backend::{Backend, BackendError, BackendResult, Capabilities, Probe, RangeRequest, Validators};
+use client::{send_following_redirections, Config};
+use contentlength::GetContentLength;
+use hsts::HstsStore;
+use hyper;
+use hyper::client::Client;
+use hyper::client::response::Response;
+use hyper::method::Method;
+use hyper::Url;
+use hyper::header::{ByteRangeSpec, ContentRange, ContentRangeSpec, ETag, Headers, IfModifiedSince,
+                    IfNoneMatch, IfRange, LastModified, Range};
+use hyper::status::StatusCode;
+use metalink::{get_digests, get_duplicates};
+use std::io::Read;
+use std::sync::Arc;
+use std::time::SystemTime;
+use timestamping::{get_http_date, get_system_time};
+use tls::TlsError;
+use {RangeBytes, URL};
+
+/// Backend to access remote contents through HTTP or HTTPS.
+/// The backend owns a single Hyper client, so every request (probes, benchmarks and chunks)
+/// reuses the connections kept alive in its pool.
+/// The same backend handles HTTP and HTTPS URLs.
+pub struct HttpBackend {
+    hyper_client: Client,
+    hsts: Option<Arc<HstsStore>>,
+}
+
+impl HttpBackend {
+    /// Returns an HTTP backend, using the default TLS settings
+    pub fn new() -> HttpBackend {
+        HttpBackend::with_config(Config::default()).expect("Cannot initialize the SSL client")
+    }
+
+    /// Returns an HTTP backend, using a custom client configuration.
+    /// This function returns an error if the TLS settings cannot be applied.
+    pub fn with_config(config: Config) -> Result<HttpBackend, TlsError> {
+        Ok(HttpBackend {
+            hyper_client: config.get_hyper_client()?,
+            hsts: config.hsts,
+        })
+    }
+
+    /// Send a request, following the redirections: the HTTP URLs of known HSTS hosts (the
+    /// given one and each redirection) are upgraded to HTTPS, and the HSTS policies sent back
+    /// by the servers are recorded
+    fn send(
+        &self,
+        method: Method,
+        url: URL,
+        header: Headers,
+        redirections: &mut Vec<(Url, Headers)>,
+    ) -> Result<Response, hyper::Error> {
+        send_following_redirections(
+            &self.hyper_client,
+            method,
+            url,
+            header,
+            self.hsts.as_deref(),
+            redirections,
+        )
+    }
+}
+
+impl Default for HttpBackend {
+    fn default() -> HttpBackend {
+        HttpBackend::new()
+    }
+}
+
+/// Function to get the HTTP header to send to the file server, for a range
+fn get_range_header(range: &RangeBytes) -> Headers {
+    let mut header = Headers::new();
+    header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(range.0, range.1)]));
+    header
+}
+
+/// Function to get the range sent back by the server, if any
+fn get_content_range(headers: &Headers) -> Option<RangeBytes> {
+    match headers.get::<ContentRange>() {
+        Some(&ContentRange(ContentRangeSpec::Bytes {
+            range: Some((begin, end)),
+            ..
+        })) => Some(RangeBytes(begin, end)),
+        _ => None,
+    }
+}
+
+/// Function to check that the range sent back by the server is the requested one
+fn check_content_range(range: &RangeBytes, headers: &Headers) -> BackendResult<()> {
+    let content_range = get_content_range(headers);
+    if content_range.as_ref() != Some(range) {
+        return Err(BackendError::RangeMismatch {
+            expected: range.clone(),
+            found: content_range,
+        });
+    }
+    Ok(())
+}
+
+/// Function to get the last modification date sent back by the server, if any
+fn get_last_modified(headers: &Headers) -> Option<SystemTime> {
+    get_system_time(&headers.get::<LastModified>()?.0)
+}
+
+/// Function to get the HTTP headers to send to the file server, to get a response only if the
+/// remote content has been modified since the given validators
+fn get_conditional_header(validators: &Validators) -> Headers {
+    let mut header = Headers::new();
+    if let Some(ref etag) = validators.etag {
+        header.set(IfNoneMatch::Items(vec![etag.clone()]));
+    }
+    if let Some(date) = validators.last_modified.and_then(get_http_date) {
+        header.set(IfModifiedSince(date));
+    }
+    header
+}
+
+/// Function to get the `If-Range` HTTP header to send to the file server, so that it sends back
+/// the requested range only if the remote content has not changed.
+/// `If-Range` needs a strong entity tag, or a date.
+fn get_if_range_header(validators: &Validators) -> Option<IfRange> {
+    match validators.etag {
+        Some(ref etag) if !etag.weak => Some(IfRange::EntityTag(etag.clone())),
+        _ => validators
+            .last_modified
+            .and_then(get_http_date)
+            .map(IfRange::Date),
+    }
+}
+
+/// Function to get the validators of the remote content sent back by the server
+fn get_validators(headers: &Headers) -> Validators {
+    Validators {
+        etag: headers.get::<ETag>().map(|etag| etag.0.clone()),
+        last_modified: get_last_modified(headers),
+    }
+}
+
+/// Function to get the HTTP header that contains the authorization, if any
+fn get_auth_header(auth: Option<&AuthorizationHeaderFactory>) -> Headers {
+    let mut header = Headers::new();
+    if let Some(auth_header_factory) = auth {
+        header.set(auth_header_factory.build_header());
+    }
+    header
+}
+
+/// Function to get the HTTP headers of a probe request - if validators are given, the server
+/// sends back `304 Not Modified` if the remote content has not been modified since them
+pub fn get_probe_header(
+    auth: Option<&AuthorizationHeaderFactory>,
+    validators: Option<&Validators>,
+) -> Headers {
+    let mut http_header = get_auth_header(auth);
+    if let Some(validators) = validators {
+        http_header.extend(get_conditional_header(validators).iter());
+    }
+    http_header
+}
+
+/// Function to get the HTTP headers of a request for the whole remote content, so that the
+/// server sends back its length
+pub fn get_content_length_header(auth: Option<&AuthorizationHeaderFactory>) -> Headers {
+    let mut http_header = get_auth_header(auth);
+    http_header.set(Range::Bytes(vec![ByteRangeSpec::AllFrom(0)]));
+    http_header
+}
+
+/// Function to get the informations of the remote content, from the successful response to
+/// a probe and from the redirections followed to get it.
+/// The content length is None if the response does not give it.
+pub fn get_probe(url: &Url, headers: &Headers, redirections: &[(Url, Headers)]) -> Probe {
+    let Validators { etag, last_modified } = get_validators(headers);
+    // Metalink/HTTP headers are usually sent by redirectors, along with the redirection
+    let mut responses: Vec<(&Url, &Headers)> = redirections
+        .iter()
+        .map(|(url, headers)| (url, headers))
+        .collect();
+    responses.push((url, headers));
+    let duplicates = get_duplicates(&responses);
+    let mut digests = Vec::new();
+    for &(_, headers) in &responses {
+        for digest in get_digests(headers) {
+            if !digests.contains(&digest) {
+                digests.push(digest);
+            }
+        }
+    }
+    Probe {
+        content_length: headers.get_content_length(),
+        auth_type: None,
+        etag,
+        last_modified,
+        duplicates,
+        digests,
+        multiplexed: false,
+    }
+}
+
+/// Function to get the HTTP headers of a range request, and whether they contain `If-Range`
+pub fn get_range_request_header(request: &RangeRequest) -> (Headers, bool) {
+    let mut http_header = get_range_header(&request.range);
+    if let Some(auth_header_factory) = request.auth {
+        http_header.set(auth_header_factory.build_header());
+    }
+    let if_range = match request.validators {
+        Some(validators) if request.partial => get_if_range_header(validators),
+        _ => None,
+    };
+    let has_if_range = if_range.is_some();
+    if let Some(if_range) = if_range {
+        http_header.set(if_range);
+    }
+    (http_header, has_if_range)
+}
+
+/// Function to check the response to a range request: the status, the range sent back by the
+/// server, and the validators of the remote content
+pub fn check_range_response(
+    request: &RangeRequest,
+    has_if_range: bool,
+    status: StatusCode,
+    headers: &Headers,
+) -> BackendResult<()> {
+    if request.partial && status != StatusCode::PartialContent {
+        // With If-Range, the server sends back the whole content if it has changed
+        if has_if_range && status == StatusCode::Ok {
+            return Err(BackendError::ContentChanged);
+        }
+        return Err(BackendError::Status(status.to_u16()));
+    }
+    if request.partial {
+        check_content_range(&request.range, headers)?;
+    }
+    request.check_validators(&get_validators(headers))
+}
+
+impl HttpBackend {
+    /// Probe the remote content - if validators are given, the request is conditional, and this
+    /// function returns None if the remote content has not been modified
+    fn probe_with_validators(
+        &self,
+        url: URL,
+        auth: Option<&AuthorizationHeaderFactory>,
+        validators: Option<&Validators>,
+    ) -> BackendResult<Option<Probe>> {
+        let http_header = get_probe_header(auth, validators);
+        let mut redirections = Vec::new();
+        let client_response = self.send(Method::Head, url, http_header, &mut redirections)?;
+        let auth_type = client_response.headers.get_authorization_type();
+
+        // The remote content length is useless if the server asks for an authorization
+        if auth_type.is_some() {
+            return Ok(Some(Probe {
+                auth_type,
+                ..Probe::default()
+            }));
+        }
+        if validators.is_some() && client_response.status == StatusCode::NotModified {
+            return Ok(None);
+        }
+        // The remote content is not available (not found, server error...)
+        if !client_response.status.is_success() {
+            return Err(BackendError::Status(client_response.status.to_u16()));
+        }
+
+        let probe = get_probe(&client_response.url, &client_response.headers, &redirections);
+        // The connection of the HEAD response must be given back before sending another
+        // request, as the number of connections per host may be limited to a single one
+        drop(client_response);
+
+        let content_length = match probe.content_length {
+            Some(remote_content_length) => Some(remote_content_length),
+            None => {
+                warning!(
+                    "Cannot get the remote content length, using an \
+                                     HEADER request."
+                );
+                warning!(
+                    "Trying to send an HTTP request, to get the remote \
+                                     content length..."
+                );
+                // Force the server to send to us the remote content length
+                let custom_http_header = get_content_length_header(auth);
+                // Get a response from the server, using the custom HTTP request
+                let client_response =
+                    self.send(Method::Get, url, custom_http_header, &mut Vec::new())?;
+                client_response.headers.get_content_length()
+            }
+        };
+
+        Ok(Some(Probe {
+            content_length,
+            ..probe
+        }))
+    }
+}
+
+impl Backend for HttpBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            ranges: true,
+            multiplexing: false,
+        }
+    }
+
+    fn probe(&self, url: URL, auth: Option<&AuthorizationHeaderFactory>) -> BackendResult<Probe> {
+        Ok(self.probe_with_validators(url, auth, None)?.unwrap_or_default())
+    }
+
+    /// The request sent to the server is conditional (`If-None-Match` and `If-Modified-Since`),
+    /// and the server sends back `304 Not Modified` if the remote content has not been modified.
+    fn probe_if_modified(
+        &self,
+        url: URL,
+        auth: Option<&AuthorizationHeaderFactory>,
+        validators: &Validators,
+    ) -> BackendResult<Option<Probe>> {
+        self.probe_with_validators(url, auth, Some(validators))
+    }
+
+    fn open_range(&self, request: &RangeRequest) -> BackendResult<Box<dyn Read + Send>> {
+        let (http_header, has_if_range) = get_range_request_header(request);
+        let body = self.send(Method::Get, request.url, http_header, &mut Vec::new())?;
+        check_range_response(request, has_if_range, body.status, &body.headers)?;
+        Ok(Box::new(body))
+    }
+}
+
+#[cfg(test)]
+mod test_header {
+
+    use super::{check_content_range, get_conditional_header, get_if_range_header, get_last_modified,
+                get_range_header};
+    use backend::{BackendError, Validators};
+    use hyper::header::{ByteRangeSpec, ContentRange, ContentRangeSpec, EntityTag, Headers, HttpDate,
+                        IfModifiedSince, IfNoneMatch, IfRange, LastModified, Range};
+    use std::time::{Duration, UNIX_EPOCH};
+    use RangeBytes;
+
+    #[test]
+    fn good_range_should_return_a_good_header() {
+        let mut test_header = Headers::new();
+        test_header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(750, 997)]));
+        assert_eq!(test_header, get_range_header(&RangeBytes(750, 997)));
+    }
+
+    #[test]
+    fn last_modified_header_should_return_a_date() {
+        let mut test_header = Headers::new();
+        test_header.set(LastModified(
+            "Sun, 06 Nov 1994 08:49:37 GMT".parse::<HttpDate>().unwrap(),
+        ));
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::from_secs(784_111_777)),
+            get_last_modified(&test_header)
+        );
+        assert_eq!(None, get_last_modified(&Headers::new()));
+    }
+
+    #[test]
+    fn validators_should_return_a_conditional_header() {
+        let etag = EntityTag::strong(String::from("zou"));
+        let validators = Validators {
+            etag: Some(etag.clone()),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(784_111_777)),
+        };
+        let mut test_header = Headers::new();
+        test_header.set(IfNoneMatch::Items(vec![etag]));
+        test_header.set(IfModifiedSince(
+            "Sun, 06 Nov 1994 08:49:37 GMT".parse::<HttpDate>().unwrap(),
+        ));
+        assert_eq!(test_header, get_conditional_header(&validators));
+        assert_eq!(Headers::new(), get_conditional_header(&Validators::default()));
+    }
+
+    #[test]
+    fn if_range_header_should_use_a_strong_etag_or_the_date() {
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT".parse::<HttpDate>().unwrap();
+        let mut validators = Validators {
+            etag: Some(EntityTag::strong(String::from("zou"))),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(784_111_777)),
+        };
+        assert_eq!(
+            Some(IfRange::EntityTag(EntityTag::strong(String::from("zou")))),
+            get_if_range_header(&validators)
+        );
+        validators.etag = Some(EntityTag::weak(String::from("zou")));
+        // The parsed dates and the computed dates are compared as strings
+        assert_eq!(
+            Some(IfRange::Date(date).to_string()),
+            get_if_range_header(&validators).map(|if_range| if_range.to_string())
+        );
+        validators.last_modified = None;
+        assert_eq!(None, get_if_range_header(&validators));
+    }
+
+    #[test]
+    fn content_range_should_match_the_requested_range() {
+        let range = RangeBytes(750, 997);
+        let mut test_header = Headers::new();
+        test_header.set(ContentRange(ContentRangeSpec::Bytes {
+            range: Some((750, 997)),
+            instance_length: Some(1000),
+        }));
+        assert!(check_content_range(&range, &test_header).is_ok());
+
+        test_header.set(ContentRange(ContentRangeSpec::Bytes {
+            range: Some((750, 999)),
+            instance_length: Some(1000),
+        }));
+        match check_content_range(&range, &test_header) {
+            Err(BackendError::RangeMismatch { expected, found }) => {
+                assert_eq!(range, expected);
+                assert_eq!(Some(RangeBytes(750, 999)), found);
+            }
+            _ => panic!("the range mismatch should be detected"),
+        }
+        assert!(check_content_range(&range, &Headers::new()).is_err());
+    }
+}
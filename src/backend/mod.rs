@@ -0,0 +1,392 @@
+use authorization::{AuthorizationHeaderFactory, AuthorizationType};
+use client::Config;
+use hyper;
+use hyper::header::EntityTag;
+use metalink::ContentDigest;
+use protocol::get_scheme;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tls;
+use tls::TlsError;
+use write::OutputChunkWriter;
+use {Bytes, RangeBytes, URL};
+
+#[cfg(feature = "async-http")]
+pub mod async_http;
+pub mod file;
+pub mod http;
+
+#[cfg(feature = "async-http")]
+pub use self::async_http::AsyncHttpBackend;
+pub use self::file::FileBackend;
+pub use self::http::HttpBackend;
+
+/// Constant to represent the length of the buffer to download
+/// the remote content
+pub const DOWNLOAD_BUFFER_BYTES: usize = 1024 * 64;
+
+/// Describes what a backend is able to do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capabilities {
+    /// The backend is able to fetch a range of the remote content
+    pub ranges: bool,
+    /// The backend is able to send many requests through a single connection
+    pub multiplexing: bool,
+}
+
+/// Contains informations sent back by a backend, when probing a remote content
+#[derive(Debug, Default)]
+pub struct Probe {
+    /// The length of the remote content, if known
+    pub content_length: Option<Bytes>,
+    /// The authorization type required to access the remote content, if any
+    pub auth_type: Option<AuthorizationType>,
+    /// The entity tag of the remote content, if known
+    pub etag: Option<EntityTag>,
+    /// The last modification date of the remote content, if known
+    pub last_modified: Option<SystemTime>,
+    /// The URLs of the duplicates of the remote content (Metalink/HTTP), the preferred ones first
+    pub duplicates: Vec<String>,
+    /// The digests of the remote content advertised by the server (Metalink/HTTP)
+    pub digests: Vec<ContentDigest>,
+    /// The server multiplexes the requests as streams of a single connection (HTTP/2)
+    pub multiplexed: bool,
+}
+
+/// Returns the number of whole seconds since the UNIX epoch of the given date
+fn get_seconds(date: SystemTime) -> u64 {
+    date.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Contains the validators of a previous download of a remote content, to download it again
+/// only if it has been modified
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Validators {
+    /// The entity tag of the remote content, if known
+    pub etag: Option<EntityTag>,
+    /// The last modification date of the remote content, if known
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Returns the validators of a probed remote content
+    pub fn from_probe(probe: &Probe) -> Validators {
+        Validators {
+            etag: probe.etag.clone(),
+            last_modified: probe.last_modified,
+        }
+    }
+
+    /// Returns true if there is no validator
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Returns true if the remote content, with the current validators, has not been modified
+    /// since these validators.
+    /// The entity tags are compared first (weak comparison, as for `If-None-Match`), then the
+    /// last modification dates - to the second, as HTTP dates.
+    pub fn is_not_modified(&self, current: &Validators) -> bool {
+        if let (Some(etag), Some(current_etag)) = (self.etag.as_ref(), current.etag.as_ref()) {
+            return etag.weak_eq(current_etag);
+        }
+        match (self.last_modified, current.last_modified) {
+            (Some(last_modified), Some(current_last_modified)) => {
+                get_seconds(current_last_modified) <= get_seconds(last_modified)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if the current validators show that the remote content is not the one
+    /// described by these validators anymore.
+    /// If the validators cannot be compared, the remote content is assumed unchanged.
+    pub fn is_changed(&self, current: &Validators) -> bool {
+        self.is_same_content(current) == Some(false)
+    }
+
+    /// Returns true only if the current validators show that the remote content is still the
+    /// one described by these validators - the bytes downloaded from it can be kept.
+    pub fn is_unchanged(&self, current: &Validators) -> bool {
+        self.is_same_content(current) == Some(true)
+    }
+
+    /// Returns whether both validators describe the same content, or None if they cannot be
+    /// compared.
+    /// The bytes of the content are pinned, so the entity tags are compared using the strong
+    /// comparison (as for `If-Range`): a weak entity tag is not comparable, and the last
+    /// modification dates are compared instead - to the second, as HTTP dates.
+    fn is_same_content(&self, current: &Validators) -> Option<bool> {
+        if let (Some(etag), Some(current_etag)) = (self.etag.as_ref(), current.etag.as_ref()) {
+            if !etag.weak && !current_etag.weak {
+                return Some(etag.strong_eq(current_etag));
+            }
+        }
+        match (self.last_modified, current.last_modified) {
+            (Some(last_modified), Some(current_last_modified)) => {
+                Some(get_seconds(current_last_modified) == get_seconds(last_modified))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Contains everything a backend needs to fetch a range of a remote content
+pub struct RangeRequest<'a> {
+    pub url: URL<'a>,
+    pub range: RangeBytes,
+    pub auth: Option<&'a AuthorizationHeaderFactory>,
+    /// If true, the backend must send back only the requested range
+    pub partial: bool,
+    /// The validators of the remote content when the download started, if any: the backend
+    /// fails with `BackendError::ContentChanged` if the remote content has changed since then
+    pub validators: Option<&'a Validators>,
+}
+
+impl<'a> RangeRequest<'a> {
+    /// Check the current validators of the remote content against the validators of the
+    /// request, if any
+    pub fn check_validators(&self, current: &Validators) -> BackendResult<()> {
+        match self.validators {
+            Some(validators) if validators.is_changed(current) => Err(BackendError::ContentChanged),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Some enumeration to display accurate backend errors
+#[derive(Debug)]
+pub enum BackendError {
+    /// Error throwed by an HTTP request
+    Http(hyper::Error),
+    /// Error throwed when reading the content
+    Io(io::Error),
+    /// Error throwed when writing the content in the output file
+    Write(io::Error),
+    /// Error throwed when the remote server sends back an unexpected status
+    Status(u16),
+    /// Error throwed when the remote content has changed since the download started
+    ContentChanged,
+    /// Error throwed when the remote server sends back another range than the requested one
+    RangeMismatch {
+        expected: RangeBytes,
+        found: Option<RangeBytes>,
+    },
+    /// Error throwed when the content ends before the end of the requested range
+    Truncated { expected: Bytes, received: Bytes },
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BackendError::Http(ref err) => write!(f, "{}", err),
+            BackendError::Io(ref err) => write!(f, "{}", err),
+            BackendError::Write(ref err) => write!(f, "cannot write the content: {}", err),
+            BackendError::Status(ref status) => write!(f, "unexpected status {}", status),
+            BackendError::ContentChanged => {
+                write!(f, "the remote content has changed since the download started")
+            }
+            BackendError::RangeMismatch {
+                ref expected,
+                found: Some(ref found),
+            } => write!(
+                f,
+                "unexpected range {}-{} instead of {}-{}",
+                found.0,
+                found.1,
+                expected.0,
+                expected.1
+            ),
+            BackendError::RangeMismatch {
+                ref expected,
+                found: None,
+            } => write!(f, "missing range, instead of {}-{}", expected.0, expected.1),
+            BackendError::Truncated { expected, received } => write!(
+                f,
+                "the connection has been closed too early ({} bytes received instead of {})",
+                received,
+                expected
+            ),
+        }
+    }
+}
+
+impl BackendError {
+    /// Returns true if the server asks to slow down (429 Too Many Requests, 503 Service
+    /// Unavailable), or resets the connections
+    pub fn is_throttling(&self) -> bool {
+        let io_error = match *self {
+            BackendError::Status(status) => return status == 429 || status == 503,
+            BackendError::Http(hyper::Error::Io(ref err)) | BackendError::Io(ref err) => err,
+            BackendError::Http(_)
+            | BackendError::Write(_)
+            | BackendError::ContentChanged
+            | BackendError::RangeMismatch { .. }
+            | BackendError::Truncated { .. } => return false,
+        };
+        io_error.kind() == io::ErrorKind::ConnectionReset
+            || io_error.kind() == io::ErrorKind::ConnectionAborted
+    }
+}
+
+impl error::Error for BackendError {
+    fn description(&self) -> &str {
+        match *self {
+            BackendError::Http(_) => "HTTP error",
+            BackendError::Io(_) => "I/O error",
+            BackendError::Write(_) => "Write error",
+            BackendError::Status(_) => "Unexpected status",
+            BackendError::ContentChanged => "Content changed",
+            BackendError::RangeMismatch { .. } => "Range mismatch",
+            BackendError::Truncated { .. } => "Truncated content",
+        }
+    }
+}
+
+impl From<hyper::Error> for BackendError {
+    fn from(err: hyper::Error) -> BackendError {
+        BackendError::Http(err)
+    }
+}
+
+impl From<io::Error> for BackendError {
+    fn from(err: io::Error) -> BackendError {
+        BackendError::Io(err)
+    }
+}
+
+pub type BackendResult<T> = Result<T, BackendError>;
+
+/// Trait that represents a way to access remote contents (HTTP, local files, ...).
+/// Each backend is registered for one or many URL schemes in a `BackendRegistry`.
+pub trait Backend: Send + Sync {
+    /// Returns what the backend is able to do
+    fn capabilities(&self) -> Capabilities;
+
+    /// Given a specific URL, get informations about the remote content without downloading it
+    fn probe(&self, url: URL, auth: Option<&AuthorizationHeaderFactory>) -> BackendResult<Probe>;
+
+    /// Given a specific URL, get informations about the remote content only if it has been
+    /// modified since the given validators.
+    /// This function returns None if the remote content has not been modified.
+    fn probe_if_modified(
+        &self,
+        url: URL,
+        auth: Option<&AuthorizationHeaderFactory>,
+        validators: &Validators,
+    ) -> BackendResult<Option<Probe>> {
+        let probe = self.probe(url, auth)?;
+        if probe.auth_type.is_none() && validators.is_not_modified(&Validators::from_probe(&probe)) {
+            return Ok(None);
+        }
+        Ok(Some(probe))
+    }
+
+    /// Open a reader on the requested range of the remote content
+    fn open_range(&self, request: &RangeRequest) -> BackendResult<Box<dyn Read + Send>>;
+
+    /// Download the requested range of the remote content into the chunk writer.
+    /// `progress` is called with the number of bytes written so far.
+    /// The errors of the chunk writer are reported as `BackendError::Write`, and a content that
+    /// ends before the end of the range as `BackendError::Truncated`.
+    /// The bytes after the end of the range are never written: they belong to another chunk.
+    /// This function returns the number of bytes that have been downloaded, i.e. the length of
+    /// the range.
+    fn download_range(
+        &self,
+        request: &RangeRequest,
+        chunk_writer: &mut OutputChunkWriter,
+        progress: &mut dyn FnMut(Bytes),
+    ) -> BackendResult<Bytes> {
+        let chunk_length = request.range.length();
+        let mut body = self.open_range(request)?.take(chunk_length);
+        let mut bytes_buffer = [0; DOWNLOAD_BUFFER_BYTES];
+        let mut sum_bytes = 0;
+
+        loop {
+            let n = match body.read(&mut bytes_buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(BackendError::Io(err)),
+            };
+
+            chunk_writer
+                .write(sum_bytes, &bytes_buffer[0..n])
+                .map_err(BackendError::Write)?;
+            sum_bytes += n as u64;
+            progress(sum_bytes);
+        }
+        if sum_bytes < chunk_length {
+            return Err(BackendError::Truncated {
+                expected: chunk_length,
+                received: sum_bytes,
+            });
+        }
+        Ok(sum_bytes)
+    }
+}
+
+/// Structure that maps URL schemes to the backends to use
+pub struct BackendRegistry {
+    backends: HashMap<String, Arc<dyn Backend>>,
+}
+
+impl BackendRegistry {
+    /// Returns a registry without any backend
+    pub fn empty() -> BackendRegistry {
+        BackendRegistry { backends: HashMap::new() }
+    }
+
+    /// Returns a registry that contains the default backends (HTTP, HTTPS and local files),
+    /// using the default TLS settings
+    pub fn new() -> BackendRegistry {
+        BackendRegistry::with_http_config(Config::default()).expect("Cannot initialize the SSL client")
+    }
+
+    /// Returns a registry that contains the default backends, using a custom configuration
+    /// for the HTTP client.
+    /// A single HTTP backend handles both HTTP and HTTPS URLs, so a download can mix both
+    /// schemes (mirrors, redirections, HSTS upgrades...).
+    /// The registry keeps the HTTP client, and its connections, alive until it is dropped.
+    /// If zou has been built with the `async-http` feature, the HTTP backend is
+    /// `AsyncHttpBackend`.
+    /// If zou has been built without TLS, HTTPS URLs are not supported.
+    /// This function returns an error if the TLS settings cannot be applied.
+    pub fn with_http_config(config: Config) -> Result<BackendRegistry, TlsError> {
+        let mut registry = BackendRegistry::empty();
+        #[cfg(feature = "async-http")]
+        let http_backend = Arc::new(AsyncHttpBackend::with_config(config)?);
+        #[cfg(not(feature = "async-http"))]
+        let http_backend = Arc::new(HttpBackend::with_config(config)?);
+        if tls::is_supported() {
+            registry.register("https", http_backend.clone());
+        }
+        registry.register("http", http_backend);
+        registry.register("file", Arc::new(FileBackend));
+        Ok(registry)
+    }
+
+    /// Register a backend for the given scheme - a previous backend for this scheme is replaced
+    pub fn register(&mut self, scheme: &str, backend: Arc<dyn Backend>) {
+        self.backends.insert(scheme.to_lowercase(), backend);
+    }
+
+    /// Returns the backend to use for the given URL, if its scheme is supported
+    pub fn get(&self, url: URL) -> Option<Arc<dyn Backend>> {
+        get_scheme(url).and_then(|scheme| self.backends.get(&scheme).cloned())
+    }
+}
+
+impl Default for BackendRegistry {
+    fn default() -> BackendRegistry {
+        BackendRegistry::new()
+    }
+}
@@ -1,68 +1,306 @@
-use client::{Config, GetResponse};
-use hyper::Client;
-use hyper::header::{ByteRangeSpec, Headers, Range};
+use backend::{Backend, BackendError, BackendRegistry, RangeRequest, DOWNLOAD_BUFFER_BYTES};
+use filesize::StringFileSize;
+use mirror_scores::MirrorScores;
+use mirrors::get_mirror_url;
 use MirrorsList;
 use rayon::prelude::*;
-use std::path::Path;
+use std::cmp::{min, Ordering};
+use std::error;
+use std::fmt;
+use std::io::Read;
 use std::time::{Duration, Instant};
-use URL;
-
-/// Number of times to ping the remote server
-const PING_TIMES: usize = 5;
-/// Number of bytes to download from the remote server
-const LEN_BENCH_CHUNK: u64 = 64;
-
-/// Launch a benchmark on a single URL
-/// This benchmark tests the network for this URL, downloading five times a 64 bits packet
-/// The result is the mean of the five measures
-fn launch_bench<'a>(bench_client: &Client, url: URL<'a>) -> u32 {
-    let mut c_ping_time: [u32; PING_TIMES] = [0; PING_TIMES];
-    for index in 0..PING_TIMES {
-        let now = Instant::now();
-        let mut header = Headers::new();
-        header.set(Range::Bytes(
-            vec![ByteRangeSpec::FromTo(0, LEN_BENCH_CHUNK)],
-        ));
-        match bench_client.get_head_response_using_headers(url, header) {
-            Ok(_) => c_ping_time[index] = now.elapsed().subsec_nanos(),
-            Err(_) => break,
+use {Bytes, RangeBytes, URL};
+
+/// Default number of bytes to download from each mirror, to measure its throughput
+pub const DEFAULT_SAMPLE_BYTES: Bytes = 1024 * 1024;
+
+/// Some enumeration to display accurate benchmark errors
+#[derive(Debug)]
+pub enum BenchError {
+    /// Error throwed when no backend is registered for the URL scheme
+    UnsupportedScheme,
+    /// Error throwed by the backend
+    Backend(BackendError),
+    /// Error throwed when the mirror does not send back the remote content length
+    UnknownContentLength,
+    /// Error throwed when the content length of the mirror is not the expected one
+    ContentLengthMismatch { expected: Bytes, found: Bytes },
+    /// Error throwed when the mirror sends back an empty sample
+    EmptySample,
+}
+
+impl fmt::Display for BenchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BenchError::UnsupportedScheme => write!(f, "unknown protocol"),
+            BenchError::Backend(ref err) => write!(f, "{}", err),
+            BenchError::UnknownContentLength => write!(f, "unknown content length"),
+            BenchError::ContentLengthMismatch { expected, found } => write!(
+                f,
+                "content length mismatch ({} bytes instead of {})",
+                found,
+                expected
+            ),
+            BenchError::EmptySample => write!(f, "empty sample"),
+        }
+    }
+}
+
+impl error::Error for BenchError {
+    fn description(&self) -> &str {
+        match *self {
+            BenchError::UnsupportedScheme => "Protocol not supported",
+            BenchError::Backend(_) => "Backend error",
+            BenchError::UnknownContentLength => "Unknown content length",
+            BenchError::ContentLengthMismatch { .. } => "Content length mismatch",
+            BenchError::EmptySample => "Empty sample",
+        }
+    }
+}
+
+impl From<BackendError> for BenchError {
+    fn from(err: BackendError) -> BenchError {
+        BenchError::Backend(err)
+    }
+}
+
+/// Contains the measures of a mirror
+#[derive(Debug)]
+pub struct BenchMeasures {
+    /// Time to first byte of the sample, including the connection
+    pub ttfb: Duration,
+    /// Time to download the whole sample, including the connection
+    pub duration: Duration,
+    /// Number of bytes downloaded
+    pub bytes: Bytes,
+}
+
+impl BenchMeasures {
+    /// Returns the throughput of the mirror, in bytes per second
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.duration.as_secs() as f64 + f64::from(self.duration.subsec_nanos()) / 1e9;
+        if seconds == 0. {
+            return self.bytes as f64;
+        }
+        self.bytes as f64 / seconds
+    }
+}
+
+/// Result of the benchmark of a mirror
+#[derive(Debug)]
+pub struct BenchResult {
+    pub url: String,
+    pub measures: Result<BenchMeasures, BenchError>,
+}
+
+/// Returns the number of milliseconds of a duration
+fn as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos()) / 1_000_000
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.measures {
+            Ok(ref measures) => write!(
+                f,
+                "{:>12}/s {:>8} ms  {}",
+                StringFileSize::from(measures.throughput() as u64).to_string(),
+                as_millis(measures.ttfb),
+                self.url
+            ),
+            Err(ref err) => write!(f, "{:>14} {:>11}  {} ({})", "-", "-", self.url, err),
+        }
+    }
+}
+
+/// Launch a benchmark on a single URL.
+/// The remote content length is checked against `content_length` - if given -, and the first
+/// `sample_bytes` bytes of the remote content are downloaded to measure the throughput of the
+/// server.
+pub fn launch_bench(
+    backend: &dyn Backend,
+    url: URL,
+    content_length: Option<Bytes>,
+    sample_bytes: Bytes,
+) -> Result<BenchMeasures, BenchError> {
+    let remote_content_length = backend
+        .probe(url, None)?
+        .content_length
+        .ok_or(BenchError::UnknownContentLength)?;
+    if let Some(expected) = content_length {
+        if expected != remote_content_length {
+            return Err(BenchError::ContentLengthMismatch {
+                expected,
+                found: remote_content_length,
+            });
+        }
+    }
+    let sample_bytes = min(sample_bytes, remote_content_length);
+    if sample_bytes == 0 {
+        return Err(BenchError::EmptySample);
+    }
+
+    let request = RangeRequest {
+        url,
+        range: RangeBytes(0, sample_bytes - 1),
+        auth: None,
+        partial: false,
+        validators: None,
+    };
+    let start = Instant::now();
+    let mut body = backend.open_range(&request)?.take(sample_bytes);
+    let mut bytes_buffer = vec![0; DOWNLOAD_BUFFER_BYTES];
+    let mut ttfb = None;
+    let mut bytes = 0;
+    loop {
+        let n = body.read(&mut bytes_buffer).map_err(BackendError::Io)?;
+        if n == 0 {
+            break;
+        }
+        if ttfb.is_none() {
+            ttfb = Some(start.elapsed());
         }
+        bytes += n as Bytes;
     }
-    // Return 0 if an error occured - the mirror is automatically removed
-    if c_ping_time.iter().any(|&x| x == 0) {
-        return 0;
+    let duration = start.elapsed();
+    match ttfb {
+        Some(ttfb) => Ok(BenchMeasures {
+            ttfb,
+            duration,
+            bytes,
+        }),
+        None => Err(BenchError::EmptySample),
     }
-    // Return the mean value
-    let sum: u32 = c_ping_time.iter().sum();
-    sum / PING_TIMES as u32
+}
+
+/// Compare two benchmark results: the fastest mirror first, the failing mirrors last
+fn compare_results(a: &BenchResult, b: &BenchResult) -> Ordering {
+    match (&a.measures, &b.measures) {
+        (Ok(a), Ok(b)) => b.throughput()
+            .partial_cmp(&a.throughput())
+            .unwrap_or(Ordering::Equal),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => Ordering::Equal,
+    }
+}
+
+/// Benchmark each URL, and returns the results sorted from the fastest to the slowest mirror -
+/// the failing mirrors are at the end of the list
+pub fn bench_urls(
+    urls: Vec<String>,
+    content_length: Option<Bytes>,
+    sample_bytes: Bytes,
+    registry: &BackendRegistry,
+) -> Vec<BenchResult> {
+    let mut results: Vec<BenchResult> = urls.into_par_iter()
+        .map(|url| {
+            let measures = match registry.get(&url) {
+                Some(backend) => launch_bench(&*backend, &url, content_length, sample_bytes),
+                None => Err(BenchError::UnsupportedScheme),
+            };
+            BenchResult { url, measures }
+        })
+        .collect();
+    results.sort_by(compare_results);
+    results
+}
+
+/// Benchmark the URLs of the remote content on each mirror.
+/// This function returns the URLs sorted by throughput (the first URL is the fastest server) -
+/// the mirrors that fail, or that do not serve a content of `content_length` bytes, are removed
+pub fn bench_mirror_urls(
+    urls: Vec<String>,
+    content_length: Bytes,
+    registry: &BackendRegistry,
+) -> Vec<String> {
+    bench_urls(urls, Some(content_length), DEFAULT_SAMPLE_BYTES, registry)
+        .into_iter()
+        .filter_map(|result| match result.measures {
+            Ok(_) => Some(result.url),
+            Err(ref err) => {
+                warning!(&format!("Ignoring the mirror {}: {}", result.url, err));
+                None
+            }
+        })
+        .collect()
+}
+
+/// Rank the URLs of the remote content on each mirror, from the best to the worst one.
+/// The mirrors that have a recent score are ranked using it, without any benchmark - unless
+/// `rebench` is true. The other mirrors are benchmarked, and their scores are updated.
+/// The mirrors that fail the benchmark are removed.
+pub fn rank_mirror_urls(
+    urls: Vec<String>,
+    content_length: Bytes,
+    registry: &BackendRegistry,
+    scores: &MirrorScores,
+    rebench: bool,
+) -> Vec<String> {
+    let mut ranked_urls: Vec<(String, f64)> = Vec::new();
+    let mut urls_to_bench = Vec::new();
+    for url in urls {
+        match scores.get(&url) {
+            Some(score) if !rebench && score.throughput > 0. => ranked_urls.push((url, score.rank())),
+            _ => urls_to_bench.push(url),
+        }
+    }
+    for result in bench_urls(urls_to_bench, Some(content_length), DEFAULT_SAMPLE_BYTES, registry) {
+        match result.measures {
+            Ok(ref measures) => {
+                let rank = scores
+                    .record_bench(&result.url, measures.bytes, measures.duration, measures.ttfb)
+                    .map(|score| score.rank())
+                    .unwrap_or_else(|| measures.throughput());
+                ranked_urls.push((result.url.clone(), rank));
+            }
+            Err(ref err) => {
+                scores.record_failure(&result.url);
+                warning!(&format!("Ignoring the mirror {}: {}", result.url, err));
+            }
+        }
+    }
+    ranked_urls.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    ranked_urls.into_iter().map(|ranked_url| ranked_url.0).collect()
 }
 
 /// Test each URL to download the required file
-/// This function returns a list of URLs, which is sorted by median measures (the first URL is the fastest server)
+/// This function returns a list of URLs, which is sorted by throughput (the first URL is the
+/// fastest server) - the mirrors that fail, or that do not serve a content of `content_length`
+/// bytes, are removed
 pub fn bench_mirrors<'a>(
     mirrors: MirrorsList<'a>,
     filename: &str,
-    ssl_support: bool,
+    content_length: Bytes,
+    registry: &BackendRegistry,
 ) -> MirrorsList<'a> {
-    // Hyper client to make benchmarks
-    let current_config = Config { enable_ssl: ssl_support };
-    let mut bench_client = current_config.get_hyper_client();
-    bench_client.set_read_timeout(Some(Duration::from_secs(3)));
-    // Get mirrors list
-    // let mut b_mirrors: Vec<(&'a str, u32)> = Vec::with_capacity(PING_TIMES);
-    let mut b_mirrors: Vec<(&'a str, u32)> = mirrors
-        .par_iter()
-        // Launch bench tests
-        .map(|mirror| -> (&'a str, u32) {
-                 let mirror_file = Path::new(mirror).join(filename);
-                 match mirror_file.to_str() {
-                     Some(mirror_path) => (mirror, launch_bench(&bench_client, mirror_path)),
-                     None => (mirror, 0),
-                 }
-             })
-        // If the bench is equals to 0, an error occured
-        .filter(|x| x.1 != 0)
+    let mirror_urls: Vec<(&'a str, String)> = mirrors
+        .into_iter()
+        .filter_map(|mirror| get_mirror_url(mirror, filename).map(|url| (mirror, url)))
         .collect();
-    b_mirrors.sort_by_key(|k| k.1);
-    b_mirrors.iter().map(|x| x.0).collect()
+    bench_mirror_urls(
+        mirror_urls.iter().map(|x| x.1.clone()).collect(),
+        content_length,
+        registry,
+    ).iter()
+        .filter_map(|url| mirror_urls.iter().find(|x| x.1 == *url).map(|x| x.0))
+        .collect()
+}
+
+#[cfg(test)]
+mod test_bench {
+
+    use super::BenchMeasures;
+    use std::time::Duration;
+
+    #[test]
+    fn throughput_should_include_whole_seconds() {
+        let measures = BenchMeasures {
+            ttfb: Duration::from_millis(100),
+            duration: Duration::from_millis(2500),
+            bytes: 5000,
+        };
+        assert_eq!(2000., measures.throughput());
+    }
+
 }
@@ -0,0 +1,27 @@
+use std::env;
+use std::fs::create_dir_all;
+use std::io;
+use std::path::PathBuf;
+
+/// Name of the zou directory, in the cache directory of the user
+const CACHE_DIRECTORY_NAME: &str = "zou";
+
+/// Returns the cache directory of zou: `$XDG_CACHE_HOME/zou`, or `~/.cache/zou`.
+/// This function returns None if the home directory of the user is unknown.
+pub fn get_cache_dir() -> Option<PathBuf> {
+    let cache_home = match env::var_os("XDG_CACHE_HOME") {
+        Some(ref cache_home) if !cache_home.is_empty() => PathBuf::from(cache_home),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_home.join(CACHE_DIRECTORY_NAME))
+}
+
+/// Returns the path of a file stored in the cache directory of zou, creating the directory
+/// if it does not exist yet
+pub fn get_cache_file(name: &str) -> io::Result<PathBuf> {
+    let cache_dir = get_cache_dir().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "cannot find the cache directory")
+    })?;
+    create_dir_all(&cache_dir)?;
+    Ok(cache_dir.join(name))
+}
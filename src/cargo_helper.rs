@@ -1,11 +1,12 @@
-use authorization::{AuthorizationHeaderFactory, AuthorizationType, GetAuthorizationType};
+use authorization::{AuthorizationHeaderFactory, AuthorizationType};
+use backend::{Backend, BackendError, BackendRegistry, BackendResult, Probe, Validators};
+use hyper::header::EntityTag;
+use metalink::ContentDigest;
 use Bytes;
-use client::{Config, GetResponse};
-use contentlength::GetContentLength;
-use hyper::header::{ByteRangeSpec, Headers, Range};
 use std::error;
 use std::fmt;
 use std::result::Result;
+use std::time::SystemTime;
 use util::prompt_user;
 
 /// Contains informations about the remote server
@@ -14,6 +15,11 @@ pub struct RemoteServerInformations<'a> {
     pub accept_partialcontent: bool,
     pub auth_header: Option<AuthorizationHeaderFactory>,
     pub file: RemoteFileInformations,
+    /// The mirrors advertised by the remote server (Metalink/HTTP), the preferred ones first
+    pub mirrors: Vec<String>,
+    /// The chunks are downloaded as streams of a single connection (HTTP/2), instead of one
+    /// connection per thread
+    pub multiplexed: bool,
     pub url: &'a str,
 }
 
@@ -21,6 +27,23 @@ pub struct RemoteServerInformations<'a> {
 #[derive(Debug)]
 pub struct RemoteFileInformations {
     pub content_length: Bytes,
+    /// The entity tag of the remote content, if known
+    pub etag: Option<EntityTag>,
+    /// The last modification date of the remote content, if known
+    pub last_modified: Option<SystemTime>,
+    /// The digests advertised by the remote server (Metalink/HTTP), to check the download
+    pub digests: Vec<ContentDigest>,
+}
+
+impl RemoteFileInformations {
+    /// Returns the validators of the remote content, pinned when the download starts: the
+    /// chunks are downloaded only if the remote content has not changed since then
+    pub fn get_validators(&self) -> Validators {
+        Validators {
+            etag: self.etag.clone(),
+            last_modified: self.last_modified,
+        }
+    }
 }
 
 /// Some enumeration to display accurate errors
@@ -31,6 +54,10 @@ pub enum RemoteServerError {
     TooMuchAttempting(usize),
     /// Error throwed when an Authorization type can't be deal with Zou
     UnknownAuthorizationType(AuthorizationType),
+    /// Error throwed when no backend is registered for the URL scheme
+    UnsupportedScheme(String),
+    /// Error throwed by the backend, when probing the remote content
+    Backend(BackendError),
 }
 
 impl fmt::Display for RemoteServerError {
@@ -39,7 +66,9 @@ impl fmt::Display for RemoteServerError {
             RemoteServerError::TooMuchAttempting(ref attempts) => write!(f, "{} attempts failed", attempts),
             RemoteServerError::UnknownAuthorizationType(ref unknown_type) => write!(f, "{} is not supporting by Zou.\
                                                                                         You can create a new issue to report this problem \
-                                                                                        at https://github.com/k0pernicus/zou/issues/new", unknown_type) 
+                                                                                        at https://github.com/k0pernicus/zou/issues/new", unknown_type),
+            RemoteServerError::UnsupportedScheme(ref url) => write!(f, "the protocol of {} is not supported", url),
+            RemoteServerError::Backend(ref err) => write!(f, "{}", err),
         }
     }
 }
@@ -48,26 +77,66 @@ impl error::Error for RemoteServerError {
     fn description(&self) -> &str {
         match *self {
             RemoteServerError::TooMuchAttempting(_) => "Many attempts failed",
-            RemoteServerError::UnknownAuthorizationType(_) => "Authorization type not supported"
+            RemoteServerError::UnknownAuthorizationType(_) => "Authorization type not supported",
+            RemoteServerError::UnsupportedScheme(_) => "Protocol not supported",
+            RemoteServerError::Backend(_) => "Backend error",
         }
     }
 }
 
 type RemoteServerInformationsResult<'a> = Result<RemoteServerInformations<'a>, RemoteServerError>;
 
+/// Probe the remote content, conditionally if validators are given
+fn probe_remote_content(
+    backend: &dyn Backend,
+    url: &str,
+    auth: Option<&AuthorizationHeaderFactory>,
+    validators: Option<&Validators>,
+) -> BackendResult<Option<Probe>> {
+    match validators {
+        Some(validators) => backend.probe_if_modified(url, auth, validators),
+        None => backend.probe(url, auth).map(Some),
+    }
+}
+
 /// Get Rust structure that contains network benchmarks
 pub fn get_remote_server_informations<'a>(
     url: &'a str,
-    ssl_support: bool,
+    registry: &BackendRegistry,
 ) -> RemoteServerInformationsResult<'a> {
-    // Get the Hyper configuration
-    let current_config = Config { enable_ssl: ssl_support };
-    let hyper_client = current_config.get_hyper_client();
-    // Get the Hyper client
-    let client_response = hyper_client.get_head_response(url).unwrap();
-    let auth_type = client_response.headers.get_authorization_type();
+    let informations = get_remote_server_informations_with_validators(url, registry, None)?;
+    Ok(informations.expect("an unconditional probe always returns the remote informations"))
+}
+
+/// Get Rust structure that contains network benchmarks, only if the remote content has been
+/// modified since the given validators (timestamping).
+/// This function returns None if the remote content has not been modified.
+pub fn get_modified_remote_server_informations<'a>(
+    url: &'a str,
+    registry: &BackendRegistry,
+    validators: &Validators,
+) -> Result<Option<RemoteServerInformations<'a>>, RemoteServerError> {
+    get_remote_server_informations_with_validators(url, registry, Some(validators))
+}
+
+/// Get the informations about the remote content, conditionally if validators are given
+fn get_remote_server_informations_with_validators<'a>(
+    url: &'a str,
+    registry: &BackendRegistry,
+    validators: Option<&Validators>,
+) -> Result<Option<RemoteServerInformations<'a>>, RemoteServerError> {
+    // Get the backend to use for this URL
+    let backend = match registry.get(url) {
+        Some(backend) => backend,
+        None => return Err(RemoteServerError::UnsupportedScheme(String::from(url))),
+    };
+    let probe = match probe_remote_content(&*backend, url, None, validators)
+        .map_err(RemoteServerError::Backend)? {
+        Some(probe) => probe,
+        None => return Ok(None),
+    };
     // Perform Authorization task
-    let auth_header_factory = match auth_type {
+    let auth_header_factory = match probe.auth_type.clone() {
         Some(a_type) => {
             match a_type {
                 AuthorizationType::Basic => {
@@ -89,53 +158,38 @@ pub fn get_remote_server_informations<'a>(
         None => None,
     };
 
-    let client_response = match auth_header_factory.clone() {
-        Some(header_factory) => {
-            let mut headers = Headers::new();
-            headers.set(header_factory.build_header());
-            hyper_client
-                .get_head_response_using_headers(url, headers)
-                .unwrap()
+    let probe = match auth_header_factory {
+        Some(ref header_factory) => {
+            match probe_remote_content(&*backend, url, Some(header_factory), validators)
+                .map_err(RemoteServerError::Backend)? {
+                Some(probe) => probe,
+                None => return Ok(None),
+            }
         }
-        None => client_response,
+        None => probe,
     };
 
-    let remote_content_length = match client_response.headers.get_content_length() {
+    // If the remote content length is unknown again, stop the program
+    let remote_content_length = match probe.content_length {
         Some(remote_content_length) => remote_content_length,
-        None => {
-            warning!(
-                "Cannot get the remote content length, using an \
-                                 HEADER request."
-            );
-            warning!(
-                "Trying to send an HTTP request, to get the remote \
-                                 content length..."
-            );
-            // Force the server to send to us the remote content length
-            let mut custom_http_header = Headers::new();
-            // HTTP header to get all the remote content - if the response is OK, get the
-            // ContentLength information sent back from the server
-            custom_http_header.set(Range::Bytes(vec![ByteRangeSpec::AllFrom(0)]));
-            // Get a response from the server, using the custom HTTP request
-            let client_response = hyper_client
-                .get_http_response_using_headers(url, custom_http_header)
-                .unwrap();
-            // Try again to get the content length - if this one is unknown again, stop the program
-            match client_response.headers.get_content_length() {
-                Some(remote_content_length) => remote_content_length,
-                None => {
-                    return Err(RemoteServerError::TooMuchAttempting(2));
-                }
-            }
-        }
+        None => return Err(RemoteServerError::TooMuchAttempting(2)),
     };
 
-    Ok(RemoteServerInformations {
-        accept_partialcontent: true,
+    Ok(Some(RemoteServerInformations {
+        accept_partialcontent: backend.capabilities().ranges,
         auth_header: auth_header_factory,
         file: RemoteFileInformations {
             content_length: remote_content_length,
-        },    
-        url: url,
-    })
+            etag: probe.etag,
+            last_modified: probe.last_modified,
+            digests: probe.digests,
+        },
+        mirrors: probe
+            .duplicates
+            .into_iter()
+            .filter(|duplicate| duplicate != url)
+            .collect(),
+        multiplexed: backend.capabilities().multiplexing && probe.multiplexed,
+        url,
+    }))
 }
@@ -1,38 +1,103 @@
-use hyper::client::Client;
+use hyper::client::{pool, Client, Pool, RedirectPolicy};
 use hyper::client::response::Response;
 use hyper::error::Error;
-use hyper::header::Headers;
+use hyper::header::{Headers, Location};
 use hyper::method::Method;
+use hyper::status::StatusCode;
+use hyper::Url;
+#[cfg(any(feature = "tls-openssl", feature = "tls-rustls"))]
 use hyper::net::HttpsConnector;
-use hyper_openssl::OpensslClient;
+use connector::HostLimitedConnector;
+use hsts::HstsStore;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(any(feature = "tls-openssl", feature = "tls-rustls"))]
+use tls::get_tls_client;
+use tls::{TlsConfig, TlsError};
 
-/// Structure to store if SSL is required, and to
-/// implement a default HTTP/HTTPS client
+/// Maximum number of idle connections kept alive per host, when the number of connections
+/// per host is not limited
+const MAX_IDLE_CONNECTIONS_PER_HOST: usize = 32;
+
+/// Maximum number of redirections to follow, for a single request
+pub const MAX_REDIRECTIONS: usize = 10;
+
+/// Structure to store the settings of the default HTTP/HTTPS client.
+/// The client handles both schemes, for each request - HTTPS URLs are supported only if zou
+/// has been built with a TLS implementation (OpenSSL or rustls, selected using the cargo
+/// features).
+#[derive(Clone, Default)]
 pub struct Config {
-    pub enable_ssl: bool,
+    /// Maximum number of simultaneous connections per host - 0 means no limit
+    pub max_connections_per_host: usize,
+    /// TLS settings, used for HTTPS URLs
+    pub tls: TlsConfig,
+    /// Store of the known HSTS hosts, if HTTP URLs of these hosts must be upgraded to HTTPS
+    pub hsts: Option<Arc<HstsStore>>,
+    /// Maximum time to wait for a read - a stalled connection is dropped after it
+    pub read_timeout: Option<Duration>,
 }
 
 impl Config {
-    /// Get the HTTP/HTTPS Hyper client
-    pub fn get_hyper_client(&self) -> Client {
-        if !self.enable_ssl {
-            return Client::default();
+    /// Get the HTTP/HTTPS Hyper client.
+    /// The connections of the client are kept alive in a pool, in order to be reused by the
+    /// next requests sent to the same host.
+    /// The redirections are followed by `GetResponse`, in order to keep the headers of each
+    /// response of the chain.
+    /// This function returns an error if the TLS settings cannot be applied.
+    pub fn get_hyper_client(&self) -> Result<Client, TlsError> {
+        let pool_config = pool::Config {
+            max_idle: self.get_max_idle_connections(),
+        };
+        let mut client = Client::pooled(pool_config, self.max_connections_per_host, &self.tls)?;
+        client.set_read_timeout(self.read_timeout);
+        client.set_redirect_policy(RedirectPolicy::FollowNone);
+        Ok(client)
+    }
+
+    /// Returns the maximum number of idle connections kept alive per host
+    pub fn get_max_idle_connections(&self) -> usize {
+        match self.max_connections_per_host {
+            0 => MAX_IDLE_CONNECTIONS_PER_HOST,
+            max_connections => max_connections,
         }
-        Client::default_ssl()
     }
 }
 
-/// Trait to instantiate an Hyper client, with SSL support
-trait SSLSupport {
-    /// Function to return a Client, with SSL support
-    fn default_ssl() -> Client;
+/// Trait to instantiate an Hyper client, that keeps its connections alive
+trait PooledClient {
+    /// Function to return a Client that keeps its connections alive - the connector of the
+    /// client picks HTTP or HTTPS for each request, using the URL scheme
+    fn pooled(
+        pool_config: pool::Config,
+        max_connections_per_host: usize,
+        tls: &TlsConfig,
+    ) -> Result<Client, TlsError>;
 }
 
-impl SSLSupport for Client {
-    fn default_ssl() -> Client {
-        let ssl = OpensslClient::new().unwrap();
-        let connector = HttpsConnector::new(ssl);
-        Client::with_connector(connector)
+#[cfg(any(feature = "tls-openssl", feature = "tls-rustls"))]
+impl PooledClient for Client {
+    fn pooled(
+        pool_config: pool::Config,
+        max_connections_per_host: usize,
+        tls: &TlsConfig,
+    ) -> Result<Client, TlsError> {
+        let ssl = get_tls_client(tls)?;
+        let connector = Pool::with_connector(pool_config, HttpsConnector::new(ssl));
+        Ok(Client::with_connector(HostLimitedConnector::new(connector, max_connections_per_host)))
+    }
+}
+
+#[cfg(not(any(feature = "tls-openssl", feature = "tls-rustls")))]
+impl PooledClient for Client {
+    fn pooled(
+        pool_config: pool::Config,
+        max_connections_per_host: usize,
+        _: &TlsConfig,
+    ) -> Result<Client, TlsError> {
+        let connector = Pool::new(pool_config);
+        Ok(Client::with_connector(HostLimitedConnector::new(connector, max_connections_per_host)))
     }
 }
 
@@ -50,6 +115,14 @@ pub trait GetResponse {
         header: Headers,
     ) -> Result<Response, Error>;
 
+    /// Given a specific URL and an header, get the header without the content body, and the
+    /// URL and the header of each redirection that has been followed to get it
+    fn get_head_response_with_redirections(
+        &self,
+        url: &str,
+        header: Headers,
+    ) -> Result<(Response, Vec<(Url, Headers)>), Error>;
+
     /// Given a specific URL, get the response from the target server
     fn get_http_response(&self, url: &str) -> Result<Response, Error>;
 
@@ -61,9 +134,77 @@ pub trait GetResponse {
     ) -> Result<Response, Error>;
 }
 
+/// Returns the URL to request after a redirection, if the response to the given URL is a
+/// redirection
+pub fn get_redirection_url(
+    url: &Url,
+    status: StatusCode,
+    headers: &Headers,
+) -> Option<Result<String, Error>> {
+    match status {
+        StatusCode::MovedPermanently |
+        StatusCode::Found |
+        StatusCode::SeeOther |
+        StatusCode::TemporaryRedirect |
+        StatusCode::PermanentRedirect => {}
+        _ => return None,
+    }
+    let location = headers.get::<Location>()?;
+    Some(url.join(location).map(|url| url.into_string()).map_err(Error::Uri))
+}
+
+/// Returns the URL to request after a redirection, if the response is a redirection
+fn get_redirection(response: &Response) -> Option<Result<String, Error>> {
+    get_redirection_url(&response.url, response.status, &response.headers)
+}
+
+/// Returns the URL to request - if an HSTS store is given, HTTP URLs of known HSTS hosts are
+/// upgraded to HTTPS
+pub fn get_secure_url(url: &str, hsts: Option<&HstsStore>) -> String {
+    hsts.and_then(|hsts| hsts.upgrade(url))
+        .unwrap_or_else(|| String::from(url))
+}
+
+/// Send a request, following the redirections.
+/// The URL and the headers of each redirection response are pushed into `redirections` - the
+/// redirection responses are dropped as soon as possible, in order to release their connection.
+/// If an HSTS store is given, the policies sent back by the servers are recorded, and every
+/// URL requested (the given one, then each redirection) is upgraded to HTTPS if its host is a
+/// known HSTS host.
+pub fn send_following_redirections(
+    client: &Client,
+    mut method: Method,
+    url: &str,
+    mut header: Headers,
+    hsts: Option<&HstsStore>,
+    redirections: &mut Vec<(Url, Headers)>,
+) -> Result<Response, Error> {
+    let mut url = get_secure_url(url, hsts);
+    for _ in 0..MAX_REDIRECTIONS + 1 {
+        let response = client.request(method.clone(), url.as_str()).headers(header.clone()).send()?;
+        if let Some(hsts) = hsts {
+            hsts.record(&response.url, &response.headers);
+        }
+        let next_url = match get_redirection(&response) {
+            Some(next_url) => get_secure_url(&next_url?, hsts),
+            None => return Ok(response),
+        };
+        // Do not send the credentials to another host
+        if response.url.host_str() != Url::parse(&next_url).ok().as_ref().and_then(Url::host_str) {
+            header.remove_raw("Authorization");
+        }
+        if response.status == StatusCode::SeeOther && method != Method::Head {
+            method = Method::Get;
+        }
+        redirections.push((response.url.clone(), response.headers.clone()));
+        url = next_url;
+    }
+    Err(Error::Io(io::Error::other("too many redirections")))
+}
+
 impl GetResponse for Client {
     fn get_head_response(&self, url: &str) -> Result<Response, Error> {
-        self.request(Method::Head, url).send()
+        self.get_head_response_using_headers(url, Headers::new())
     }
 
     fn get_head_response_using_headers(
@@ -71,9 +212,24 @@ impl GetResponse for Client {
         url: &str,
         custom_header: Headers,
     ) -> Result<Response, Error> {
-        self.request(Method::Head, url)
-            .headers(custom_header)
-            .send()
+        send_following_redirections(self, Method::Head, url, custom_header, None, &mut Vec::new())
+    }
+
+    fn get_head_response_with_redirections(
+        &self,
+        url: &str,
+        custom_header: Headers,
+    ) -> Result<(Response, Vec<(Url, Headers)>), Error> {
+        let mut redirections = Vec::new();
+        let response = send_following_redirections(
+            self,
+            Method::Head,
+            url,
+            custom_header,
+            None,
+            &mut redirections,
+        )?;
+        Ok((response, redirections))
     }
 
     fn get_http_response(&self, url: &str) -> Result<Response, Error> {
@@ -85,6 +241,6 @@ impl GetResponse for Client {
         url: &str,
         custom_header: Headers,
     ) -> Result<Response, Error> {
-        self.request(Method::Get, url).headers(custom_header).send()
+        send_following_redirections(self, Method::Get, url, custom_header, None, &mut Vec::new())
     }
 }
@@ -0,0 +1,160 @@
+use hyper;
+use hyper::net::{NetworkConnector, NetworkStream};
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Returns the key of the counter of a host - the connections to the other ports of the same
+/// host are counted apart
+pub fn get_host_key(host: &str, port: u16) -> String {
+    format!("{}:{}", host, port)
+}
+
+/// Counts the active connections, per host and port
+#[derive(Default)]
+struct HostCounters {
+    active: Mutex<HashMap<String, usize>>,
+    released: Condvar,
+}
+
+impl HostCounters {
+    /// Wait until a connection to the host is available, and take it
+    fn acquire(&self, host_key: &str, limit: usize) {
+        let mut active = self.active.lock().unwrap();
+        while active.get(host_key).cloned().unwrap_or(0) >= limit {
+            active = self.released.wait(active).unwrap();
+        }
+        *active.entry(String::from(host_key)).or_insert(0) += 1;
+    }
+
+    /// Give back a connection to the host
+    fn release(&self, host_key: &str) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(count) = active.get_mut(host_key) {
+            *count -= 1;
+        }
+        self.released.notify_all();
+    }
+}
+
+/// Connector that limits the number of simultaneous connections per host (and port).
+/// When the limit is reached, a new connection waits for an active one to be given back.
+/// It wraps a `Pool` connector, so a given back connection is kept alive for the next requests.
+pub struct HostLimitedConnector<C> {
+    connector: C,
+    limit: usize,
+    counters: Arc<HostCounters>,
+}
+
+impl<C> HostLimitedConnector<C> {
+    /// Returns a connector that opens at most `limit` connections per host - 0 means no limit
+    pub fn new(connector: C, limit: usize) -> HostLimitedConnector<C> {
+        HostLimitedConnector {
+            connector,
+            limit,
+            counters: Arc::new(HostCounters::default()),
+        }
+    }
+}
+
+impl<C: NetworkConnector<Stream = S>, S: NetworkStream + Send> NetworkConnector
+    for HostLimitedConnector<C> {
+    type Stream = HostLimitedStream<S>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HostLimitedStream<S>> {
+        let host_key = get_host_key(host, port);
+        if self.limit == 0 {
+            return Ok(HostLimitedStream {
+                stream: Some(self.connector.connect(host, port, scheme)?),
+                host_key,
+                counters: None,
+            });
+        }
+        self.counters.acquire(&host_key, self.limit);
+        match self.connector.connect(host, port, scheme) {
+            Ok(stream) => Ok(HostLimitedStream {
+                stream: Some(stream),
+                host_key,
+                counters: Some(self.counters.clone()),
+            }),
+            Err(err) => {
+                self.counters.release(&host_key);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Stream that gives back its connection to the host when dropped
+pub struct HostLimitedStream<S> {
+    stream: Option<S>,
+    host_key: String,
+    counters: Option<Arc<HostCounters>>,
+}
+
+impl<S> HostLimitedStream<S> {
+    fn get_ref(&self) -> &S {
+        self.stream.as_ref().unwrap()
+    }
+
+    fn get_mut(&mut self) -> &mut S {
+        self.stream.as_mut().unwrap()
+    }
+}
+
+impl<S: Read> Read for HostLimitedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.get_mut().read(buf)
+    }
+}
+
+impl<S: Write> Write for HostLimitedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
+
+impl<S: NetworkStream + Send> NetworkStream for HostLimitedStream<S> {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.get_mut().peer_addr()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_write_timeout(dur)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.get_mut().close(how)
+    }
+
+    fn set_previous_response_expected_no_content(&mut self, expected: bool) {
+        self.get_mut()
+            .set_previous_response_expected_no_content(expected)
+    }
+
+    fn previous_response_expected_no_content(&self) -> bool {
+        self.get_ref().previous_response_expected_no_content()
+    }
+}
+
+impl<S> Drop for HostLimitedStream<S> {
+    fn drop(&mut self) {
+        // Give back the connection to the pool before waking up the waiting requests,
+        // so they can reuse it
+        self.stream.take();
+        if let Some(ref counters) = self.counters {
+            counters.release(&self.host_key);
+        }
+    }
+}
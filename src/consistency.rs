@@ -0,0 +1,324 @@
+//! Before mixing bytes from several sources, every mirror has to serve the same object as the
+//! original URL: same content length, same strong entity tag and no older last modification
+//! date when they are comparable - otherwise, same bytes in a few sampled ranges.
+
+use authorization::AuthorizationHeaderFactory;
+use backend::{Backend, BackendError, BackendRegistry, Probe, RangeRequest};
+use rayon::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::error;
+use std::fmt;
+use std::hash::Hasher;
+use std::io::Read;
+use {Bytes, RangeBytes, URL};
+
+/// Number of bytes of each sampled range
+pub const SAMPLE_RANGE_BYTES: Bytes = 4 * 1024;
+
+/// Some enumeration to display accurate consistency errors
+#[derive(Debug)]
+pub enum ConsistencyError {
+    /// Error throwed when no backend is registered for the URL scheme
+    UnsupportedScheme,
+    /// Error throwed by the backend
+    Backend(BackendError),
+    /// Error throwed when the mirror does not send back the remote content length
+    UnknownContentLength,
+    /// Error throwed when the content length of the mirror is not the expected one
+    ContentLengthMismatch { expected: Bytes, found: Bytes },
+    /// Error throwed when a sampled range is shorter than requested
+    IncompleteSample,
+    /// Error throwed when the mirror copy is older than the original one
+    Stale,
+    /// Error throwed when the strong entity tags or the sampled bytes differ
+    ContentMismatch,
+}
+
+impl fmt::Display for ConsistencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConsistencyError::UnsupportedScheme => write!(f, "unknown protocol"),
+            ConsistencyError::Backend(ref err) => write!(f, "{}", err),
+            ConsistencyError::UnknownContentLength => write!(f, "unknown content length"),
+            ConsistencyError::ContentLengthMismatch { expected, found } => write!(
+                f,
+                "content length mismatch ({} bytes instead of {})",
+                found,
+                expected
+            ),
+            ConsistencyError::IncompleteSample => write!(f, "incomplete sample"),
+            ConsistencyError::Stale => write!(f, "outdated copy of the remote content"),
+            ConsistencyError::ContentMismatch => write!(f, "the content differs from the original one"),
+        }
+    }
+}
+
+impl error::Error for ConsistencyError {
+    fn description(&self) -> &str {
+        match *self {
+            ConsistencyError::UnsupportedScheme => "Protocol not supported",
+            ConsistencyError::Backend(_) => "Backend error",
+            ConsistencyError::UnknownContentLength => "Unknown content length",
+            ConsistencyError::ContentLengthMismatch { .. } => "Content length mismatch",
+            ConsistencyError::IncompleteSample => "Incomplete sample",
+            ConsistencyError::Stale => "Outdated content",
+            ConsistencyError::ContentMismatch => "Content mismatch",
+        }
+    }
+}
+
+impl From<BackendError> for ConsistencyError {
+    fn from(err: BackendError) -> ConsistencyError {
+        ConsistencyError::Backend(err)
+    }
+}
+
+/// Identity of the remote content served by a source
+struct ContentIdentity {
+    probe: Probe,
+    sample_hash: u64,
+}
+
+/// Returns the ranges to sample: the beginning, the middle and the end of the content - or the
+/// whole content, if it is small enough
+fn get_sample_ranges(content_length: Bytes) -> Vec<RangeBytes> {
+    if content_length == 0 {
+        return Vec::new();
+    }
+    if content_length <= 3 * SAMPLE_RANGE_BYTES {
+        return vec![RangeBytes(0, content_length - 1)];
+    }
+    let middle = content_length / 2 - SAMPLE_RANGE_BYTES / 2;
+    let end = content_length - SAMPLE_RANGE_BYTES;
+    [0, middle, end]
+        .iter()
+        .map(|&begin| RangeBytes(begin, begin + SAMPLE_RANGE_BYTES - 1))
+        .collect()
+}
+
+/// Hash the sampled ranges of the remote content
+fn hash_sample(
+    backend: &dyn Backend,
+    url: URL,
+    auth: Option<&AuthorizationHeaderFactory>,
+    content_length: Bytes,
+) -> Result<u64, ConsistencyError> {
+    let mut hasher = DefaultHasher::new();
+    for range in get_sample_ranges(content_length) {
+        let request = RangeRequest {
+            url,
+            range: range.clone(),
+            auth,
+            partial: true,
+            validators: None,
+        };
+        let mut sample = Vec::with_capacity(range.length() as usize);
+        backend
+            .open_range(&request)?
+            .take(range.length())
+            .read_to_end(&mut sample)
+            .map_err(BackendError::Io)?;
+        if sample.len() as Bytes != range.length() {
+            return Err(ConsistencyError::IncompleteSample);
+        }
+        hasher.write(&sample);
+    }
+    Ok(hasher.finish())
+}
+
+/// Returns the identity of the remote content served at the given URL
+fn get_identity(
+    backend: &dyn Backend,
+    url: URL,
+    auth: Option<&AuthorizationHeaderFactory>,
+    content_length: Bytes,
+) -> Result<ContentIdentity, ConsistencyError> {
+    let probe = backend.probe(url, auth)?;
+    match probe.content_length {
+        Some(found) if found == content_length => {}
+        Some(found) => {
+            return Err(ConsistencyError::ContentLengthMismatch {
+                expected: content_length,
+                found,
+            })
+        }
+        None => return Err(ConsistencyError::UnknownContentLength),
+    }
+    let sample_hash = hash_sample(backend, url, auth, content_length)?;
+    Ok(ContentIdentity { probe, sample_hash })
+}
+
+/// Check that a mirror serves the same content as the original source.
+/// The validators decide when they are comparable: the same strong entity tag identifies the
+/// same content, while another strong entity tag or an older last modification date identify
+/// another content. The sampled bytes are compared only if the validators cannot decide.
+fn check_identity(
+    original: &ContentIdentity,
+    mirror: &ContentIdentity,
+) -> Result<(), ConsistencyError> {
+    let is_older = match (original.probe.last_modified, mirror.probe.last_modified) {
+        (Some(original_date), Some(mirror_date)) => mirror_date < original_date,
+        _ => false,
+    };
+    // Weak entity tags are not comparable: they do not identify the bytes of the content
+    if let (Some(original_etag), Some(mirror_etag)) =
+        (original.probe.etag.as_ref(), mirror.probe.etag.as_ref())
+    {
+        if !original_etag.weak && !mirror_etag.weak {
+            if original_etag.strong_eq(mirror_etag) {
+                return Ok(());
+            }
+            return Err(if is_older {
+                ConsistencyError::Stale
+            } else {
+                ConsistencyError::ContentMismatch
+            });
+        }
+    }
+    if is_older {
+        return Err(ConsistencyError::Stale);
+    }
+    if original.sample_hash != mirror.sample_hash {
+        return Err(ConsistencyError::ContentMismatch);
+    }
+    Ok(())
+}
+
+/// Check that every mirror serves the same content as the original URL, and returns the
+/// consistent mirrors - in the same order.
+/// The other mirrors are excluded, with a warning.
+/// If the original content cannot be sampled, every mirror is excluded.
+pub fn check_mirrors(
+    url: URL,
+    auth: Option<&AuthorizationHeaderFactory>,
+    content_length: Bytes,
+    mirror_urls: Vec<String>,
+    registry: &BackendRegistry,
+) -> Vec<String> {
+    if mirror_urls.is_empty() {
+        return mirror_urls;
+    }
+    let original = match registry.get(url) {
+        Some(backend) => get_identity(&*backend, url, auth, content_length),
+        None => Err(ConsistencyError::UnsupportedScheme),
+    };
+    let original = match original {
+        Ok(original) => original,
+        Err(err) => {
+            warning!(&format!(
+                "Ignoring the mirrors: cannot check the original content ({})",
+                err
+            ));
+            return Vec::new();
+        }
+    };
+
+    mirror_urls
+        .into_par_iter()
+        .filter(|mirror_url| {
+            let result = match registry.get(mirror_url) {
+                // Do not send the credentials of the original URL to the mirrors
+                Some(backend) => get_identity(&*backend, mirror_url, None, content_length)
+                    .and_then(|mirror| check_identity(&original, &mirror)),
+                None => Err(ConsistencyError::UnsupportedScheme),
+            };
+            match result {
+                Ok(()) => true,
+                Err(err) => {
+                    warning!(&format!(
+                        "Ignoring the mirror {}: it does not serve the same content ({})",
+                        mirror_url,
+                        err
+                    ));
+                    false
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test_consistency {
+
+    use super::{check_identity, get_sample_ranges, ConsistencyError, ContentIdentity,
+                SAMPLE_RANGE_BYTES};
+    use backend::Probe;
+    use hyper::header::EntityTag;
+    use std::time::{Duration, UNIX_EPOCH};
+    use RangeBytes;
+
+    fn identity(etag: Option<EntityTag>, last_modified: u64, sample_hash: u64) -> ContentIdentity {
+        ContentIdentity {
+            probe: Probe {
+                content_length: Some(1000),
+                etag,
+                last_modified: Some(UNIX_EPOCH + Duration::from_secs(last_modified)),
+                ..Probe::default()
+            },
+            sample_hash,
+        }
+    }
+
+    #[test]
+    fn small_content_should_be_sampled_entirely() {
+        assert_eq!(vec![RangeBytes(0, 999)], get_sample_ranges(1000));
+        assert!(get_sample_ranges(0).is_empty());
+    }
+
+    #[test]
+    fn large_content_should_be_sampled_at_three_places() {
+        let length = 10 * SAMPLE_RANGE_BYTES;
+        let ranges = get_sample_ranges(length);
+        assert_eq!(3, ranges.len());
+        assert_eq!(0, ranges[0].0);
+        assert_eq!(length - 1, ranges[2].1);
+        assert!(ranges.iter().all(|range| range.length() == SAMPLE_RANGE_BYTES));
+    }
+
+    #[test]
+    fn same_strong_etag_should_be_consistent() {
+        let etag = Some(EntityTag::strong(String::from("zou")));
+        assert!(check_identity(&identity(etag.clone(), 10, 1), &identity(etag, 10, 2)).is_ok());
+    }
+
+    #[test]
+    fn same_weak_etag_should_not_be_enough() {
+        let etag = Some(EntityTag::weak(String::from("zou")));
+        assert!(check_identity(&identity(etag.clone(), 10, 1), &identity(etag, 10, 2)).is_err());
+    }
+
+    #[test]
+    fn different_etags_with_same_sample_should_not_be_consistent() {
+        let original = identity(Some(EntityTag::strong(String::from("a"))), 10, 1);
+        let mirror = identity(Some(EntityTag::strong(String::from("b"))), 5, 1);
+        match check_identity(&original, &mirror) {
+            Err(ConsistencyError::Stale) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        let mirror = identity(Some(EntityTag::strong(String::from("b"))), 20, 1);
+        match check_identity(&original, &mirror) {
+            Err(ConsistencyError::ContentMismatch) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn older_mirror_should_be_stale() {
+        // The sampled bytes may be the same, and the content may differ elsewhere
+        match check_identity(&identity(None, 10, 1), &identity(None, 5, 1)) {
+            Err(ConsistencyError::Stale) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        match check_identity(&identity(None, 10, 1), &identity(None, 20, 2)) {
+            Err(ConsistencyError::ContentMismatch) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn newer_mirror_with_same_sample_should_be_consistent() {
+        let etag = Some(EntityTag::weak(String::from("zou")));
+        assert!(check_identity(&identity(etag, 10, 1), &identity(None, 20, 1)).is_ok());
+    }
+
+}
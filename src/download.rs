@@ -1,44 +1,26 @@
+use adaptive::ConnectionController;
+use backend::{Backend, BackendError, BackendRegistry, BackendResult, RangeRequest};
 use cargo_helper::RemoteServerInformations;
-use Bytes;
-use client::{Config, GetResponse};
-use hyper::client::Client;
-use hyper::error::Error;
-use hyper::header::{ByteRangeSpec, Headers, Range};
-use pbr::{MultiBar, Pipe, ProgressBar, Units};
-use response::CheckResponseStatus;
-use std::cmp::min;
-use std::io::Read;
-use std::thread;
+use consistency::check_mirrors;
+use mirror_scores::MirrorScores;
+use mirrors::Mirrors;
+use progress::{ChunkBar, ProgressBars};
+#[allow(deprecated)]
+use rayon::{Configuration, ThreadPool};
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Instant, Duration};
 use write::{OutputFileWriter, OutputChunkWriter};
-
-/// Constant to represent the length of the buffer to download
-/// the remote content
-const DOWNLOAD_BUFFER_BYTES: usize = 1024 * 64;
+use {Bytes, RangeBytes};
 
 /// Constant to represent the refresh interval (in milliseconds)
 /// for the CLI
 const PROGRESS_UPDATE_INTERVAL_MILLIS: u64 = 500;
 
-/// Represents a range between two Bytes types
-#[derive(Debug, PartialEq)]
-struct RangeBytes(Bytes, Bytes);
-
-macro_rules! initbar {
-    ($mp:ident,$mpb:ident,$length:expr,$index:expr,$server:expr) => {
-        let mut $mp = $mpb.create_bar($length);
-        $mp.tick_format("▏▎▍▌▋▊▉██▉▊▋▌▍▎▏");
-        $mp.format("|#--|");
-        $mp.show_tick = true;
-        $mp.show_speed = true;
-        $mp.show_percent = true;
-        $mp.show_counter = false;
-        $mp.show_time_left = true;
-        $mp.set_units(Units::Bytes);
-        $mp.message(&format!("Chunk {} (from {}) ", $index, $server));
-    }
-}
-
 /// Function to get the current chunk length, based on the chunk index.
 fn get_chunk_length(
     chunk_index: u64,
@@ -52,7 +34,7 @@ fn get_chunk_length(
 
     let b_range: Bytes = chunk_index * global_chunk_length;
 
-    if b_range >= (content_length - 1) {
+    if b_range >= content_length {
         return None;
     }
 
@@ -66,53 +48,28 @@ fn get_chunk_length(
 }
 
 
-/// Function to get the HTTP header to send to the file server, for a chunk (specified by its index)
-fn get_header_from_index(
-    chunk_index: u64,
-    content_length: Bytes,
-    global_chunk_length: Bytes,
-) -> Option<(Headers, RangeBytes)> {
-
-    get_chunk_length(chunk_index, content_length, global_chunk_length).map(|range| {
-        let mut header = Headers::new();
-        header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(range.0, range.1)]));
-        (header, RangeBytes(range.0, range.1 - range.0))
-    })
-}
-
-
-/// Function to get from the server the content of a chunk.
-/// This function returns a Result type - Bytes if the content of the header is accessible, an Error type otherwise.
+/// Function to get from the backend the content of a chunk.
+/// The downloaded bytes are reported to the connection controller, if any.
+/// This function returns the number of bytes written in the chunk (even if an error occured),
+/// and the result of the backend - the chunk writer is flushed before returning.
 fn download_a_chunk(
-    http_client: &Client,
-    http_header: Headers,
+    backend: &dyn Backend,
+    request: &RangeRequest,
     mut chunk_writer: OutputChunkWriter,
-    url: &str,
-    mpb: &mut ProgressBar<Pipe>,
-    monothreading: bool,
-) -> Result<Bytes, Error> {
-
-    let mut body = http_client
-        .get_http_response_using_headers(url, http_header)
-        .unwrap();
-    if monothreading && !body.check_partialcontent_status() {
-        return Err(Error::Status);
-    }
-    let mut bytes_buffer = [0; DOWNLOAD_BUFFER_BYTES];
-    let mut sum_bytes = 0;
+    mpb: &mut ChunkBar,
+    controller: Option<&ConnectionController>,
+) -> (Bytes, BackendResult<Bytes>) {
 
     let progress_update_interval = Duration::from_millis(PROGRESS_UPDATE_INTERVAL_MILLIS);
     let mut last_progress_bytes = 0;
     let mut last_progress_time = Instant::now() - progress_update_interval;
+    let mut sum_bytes = 0;
 
-
-    while let Ok(n) = body.read(&mut bytes_buffer) {
-        if n == 0 {
-            return Ok(sum_bytes);
+    let result = backend.download_range(request, &mut chunk_writer, &mut |written_bytes| {
+        if let Some(controller) = controller {
+            controller.add_bytes(written_bytes - sum_bytes);
         }
-
-        chunk_writer.write(sum_bytes, &bytes_buffer[0..n]);
-        sum_bytes += n as u64;
+        sum_bytes = written_bytes;
 
         // Update the CLI
         if Instant::now().duration_since(last_progress_time) > progress_update_interval {
@@ -121,100 +78,439 @@ fn download_a_chunk(
             last_progress_bytes = sum_bytes;
             mpb.add(progress_bytes_delta);
         }
-    }
+    });
     mpb.add(sum_bytes - last_progress_bytes);
-    return Ok(0u64);
+    // The bytes kept in memory by the chunk writer are part of the downloaded bytes
+    match chunk_writer.flush() {
+        Ok(()) => (sum_bytes, result),
+        Err(err) => (sum_bytes, Err(BackendError::Write(err))),
+    }
+}
+
+/// Describes a chunk to download
+struct ChunkTask {
+    index: u64,
+    range: RangeBytes,
+    /// The mirror to download the chunk from, at first
+    first_mirror: usize,
+}
+
+/// Function to download a whole chunk, starting from its first mirror.
+/// If the mirror fails, the remaining part of the chunk is downloaded from the next best
+/// mirror - this function returns false if every mirror has been blacklisted, if the chunk
+/// cannot be written in the output file, or if the remote content has changed since the
+/// download started.
+/// The number of bytes written at the beginning of the chunk is stored in `downloaded`, after
+/// each attempt.
+/// If a connection controller is given, each attempt waits for a connection to be allowed, and
+/// the controller backs off when a server asks to slow down.
+fn download_a_chunk_from_mirrors(
+    task: &ChunkTask,
+    mirrors: &Mirrors,
+    cargo_info: &RemoteServerInformations,
+    out_file: &OutputFileWriter,
+    mpb: &mut ChunkBar,
+    controller: Option<&ConnectionController>,
+    downloaded: &AtomicU64,
+) -> bool {
+    let (chunk_index, range) = (task.index, &task.range);
+    let accept_partialcontent = cargo_info.accept_partialcontent;
+    let validators = cargo_info.file.get_validators();
+    let mut mirror = task.first_mirror;
+    let mut done: Bytes = 0;
+
+    while done < range.length() {
+        let request = RangeRequest {
+            url: mirrors.url(mirror),
+            range: RangeBytes(range.0 + done, range.1),
+            // Do not send the credentials of the original URL to the mirrors
+            auth: if mirror == 0 { cargo_info.auth_header.as_ref() } else { None },
+            partial: accept_partialcontent,
+            // The mirrors have their own validators: they are checked before the download
+            // (see `consistency::check_mirrors`)
+            validators: if mirror == 0 { Some(&validators) } else { None },
+        };
+        let chunk_writer = out_file.get_chunk_writer(request.range.0, request.range.length());
+        let backend = mirrors.backend(mirror);
+        let permit = controller.map(|controller| controller.acquire());
+        let start = Instant::now();
+        let (bytes_written, result) =
+            download_a_chunk(&*backend, &request, chunk_writer, mpb, controller);
+        drop(permit);
+        done += bytes_written;
+
+        let error = match result {
+            Ok(_) if done >= range.length() => {
+                mirrors.report_success(mirror, bytes_written, start.elapsed());
+                break;
+            }
+            Ok(_) => String::from("the connection has been closed too early"),
+            // The output file cannot be written: the other mirrors would not help
+            Err(BackendError::Write(error)) => {
+                error!(&format!("Cannot write the chunk {}: {}", chunk_index, error));
+                return false;
+            }
+            // The downloaded bytes would mix two versions of the remote content
+            Err(BackendError::ContentChanged) => {
+                error!(&format!(
+                    "Cannot download the chunk {}: {}",
+                    chunk_index,
+                    BackendError::ContentChanged
+                ));
+                return false;
+            }
+            Err(error) => {
+                if error.is_throttling() {
+                    if let Some(controller) = controller {
+                        controller.back_off();
+                    }
+                }
+                error.to_string()
+            }
+        };
+        warning!(&format!(
+            "Cannot download the chunk {} from {}, due to error {}",
+            chunk_index,
+            mirrors.url(mirror),
+            error
+        ));
+        if mirrors.report_failure(mirror) {
+            warning!(&format!("The mirror {} is blacklisted", mirrors.url(mirror)));
+        }
+        // Without ranges support, the chunk has to be downloaded again from the beginning
+        if !accept_partialcontent {
+            mpb.set(0);
+            done = 0;
+        }
+        downloaded.store(done, Ordering::SeqCst);
+        mirror = match mirrors.next(mirror) {
+            Some(next_mirror) => next_mirror,
+            None => {
+                error!(&format!(
+                    "Cannot download the chunk {}: every mirror failed",
+                    chunk_index
+                ));
+                return false;
+            }
+        };
+        mpb.message(&format!("Chunk {} (from {}) ", chunk_index, mirrors.url(mirror)));
+    }
+    downloaded.store(done, Ordering::SeqCst);
+    true
+}
+
+/// Default maximum length of a chunk, when the chunk size is not given (64 MiB)
+pub const DEFAULT_MAX_CHUNK_SIZE: Bytes = 64 * 1_048_576;
+
+/// Describes how a remote content is split into chunks, and how many workers download them.
+/// The chunks are queued, and each worker downloads the next chunk of the queue until the
+/// queue is empty - there can be many more chunks than workers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SplitConfig {
+    /// Number of workers, i.e. chunks downloaded at the same time
+    pub workers: usize,
+    /// Length of each chunk - if None, the content is split between the workers
+    pub chunk_size: Option<Bytes>,
+    /// Minimum length of a chunk: the content is not split into smaller chunks
+    pub min_split_size: Bytes,
+    /// Number of bytes at the beginning of the content that are already in the output file:
+    /// only the remaining bytes are split and downloaded
+    pub resume_from: Bytes,
+    /// Show the progress bars of the workers on the standard output (false by default)
+    pub show_progress: bool,
+}
+
+impl SplitConfig {
+    /// Returns a configuration that splits the content between the given number of workers
+    pub fn new(workers: usize) -> SplitConfig {
+        SplitConfig {
+            workers,
+            chunk_size: None,
+            min_split_size: 0,
+            resume_from: 0,
+            show_progress: false,
+        }
+    }
+
+    /// Returns the length of each chunk of a content (the last chunk can be shorter).
+    /// Without a chunk size, the content is split between the workers, in chunks of at most
+    /// `DEFAULT_MAX_CHUNK_SIZE` bytes.
+    /// In any case, the chunks are not shorter than the minimum split size.
+    pub fn get_chunk_length(&self, content_length: Bytes) -> Bytes {
+        let chunk_length = match self.chunk_size {
+            Some(chunk_size) => chunk_size,
+            None => {
+                let workers = if self.workers == 0 { 1 } else { self.workers as Bytes };
+                min(content_length / workers + 1, DEFAULT_MAX_CHUNK_SIZE)
+            }
+        };
+        max(max(chunk_length, self.min_split_size), 1)
+    }
+}
+
+/// Function to get the number of chunks of a content, given the length of each chunk
+fn get_chunks_count(content_length: Bytes, global_chunk_length: Bytes) -> u64 {
+    if global_chunk_length == 0 {
+        return 0;
+    }
+    content_length.div_ceil(global_chunk_length)
+}
+
+/// Function to get the number of bytes downloaded from the beginning of a content, given the
+/// number of bytes downloaded at the beginning of each chunk: the chunks are counted until the
+/// first incomplete one.
+fn get_downloaded_prefix(
+    downloaded: &[Bytes],
+    content_length: Bytes,
+    global_chunk_length: Bytes,
+) -> Bytes {
+    let mut prefix = 0;
+    for (chunk_index, &chunk_downloaded) in downloaded.iter().enumerate() {
+        let chunk_length =
+            match get_chunk_length(chunk_index as u64, content_length, global_chunk_length) {
+                Some(range) => range.length(),
+                None => break,
+            };
+        prefix += min(chunk_downloaded, chunk_length);
+        if chunk_downloaded < chunk_length {
+            break;
+        }
+    }
+    prefix
+}
+
+/// Outcome of a download
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DownloadOutcome {
+    /// True if every chunk has been downloaded
+    pub is_downloaded: bool,
+    /// Number of bytes at the beginning of the content that are in the output file, including
+    /// the resumed ones: a failed download can be resumed after them
+    pub downloaded_prefix: Bytes,
+}
+
+/// Function to start a pool of `workers` threads.
+/// `Configuration` is the only way to configure a pool with the version of rayon we use.
+#[allow(deprecated)]
+fn new_thread_pool(workers: usize) -> Result<ThreadPool, Box<dyn Error>> {
+    ThreadPool::new(Configuration::new().num_threads(workers))
 }
 
 /// Function to download each chunk of a remote content (given by its URL).
 /// This function takes as parameters:
-/// * the remote content length,
-/// * a mutable reference to share between threads, which contains each chunk,
+/// * the remote server informations (content length, URL and authorization),
+/// * the shared output file,
 /// * the number of chunks that contains the remote content,
-/// * the URL of the remote content server,
-/// * a custom authorization to access and download the remote content.
+/// * the registry of backends, to access the remote content.
 pub fn download_chunks<'a>(
     cargo_info: RemoteServerInformations<'a>,
-    mut out_file: OutputFileWriter,
+    out_file: OutputFileWriter,
     nb_chunks: u64,
-    ssl_support: bool,
+    registry: &BackendRegistry,
 ) -> bool {
-    let (content_length, auth_header_factory) =
-        (cargo_info.file.content_length, cargo_info.auth_header);
-    let global_chunk_length: u64 = (content_length / nb_chunks) + 1;
+    download_chunks_from_mirrors(
+        cargo_info,
+        Vec::new(),
+        out_file,
+        SplitConfig::new(nb_chunks as usize),
+        registry,
+        None,
+        None,
+    )
+}
 
-    let mut jobs = vec![];
+/// Function to download each chunk of a remote content, from its URL and a list of mirrors.
+/// The chunks are spread over the original URL and the mirrors, which have to be sorted from
+/// the best to the worst one (see `bench::bench_mirrors`).
+/// The mirrors that do not serve the same content as the original URL are ignored (see
+/// `consistency::check_mirrors`).
+/// If a source fails, the remaining part of its chunk is downloaded from the next best source.
+/// This function takes as parameters:
+/// * the remote server informations (content length, URL and authorization),
+/// * the URLs of the remote content on the mirrors,
+/// * the shared output file,
+/// * the split of the remote content into chunks, and the number of workers,
+/// * the registry of backends, to access the remote content,
+/// * the scores of the mirrors, updated using the throughput of each chunk - if any,
+/// * the controller of the number of simultaneous connections, for an adaptive download - if
+///   any (otherwise, each worker uses its own connection).
+pub fn download_chunks_from_mirrors<'a>(
+    cargo_info: RemoteServerInformations<'a>,
+    mirror_urls: Vec<String>,
+    out_file: OutputFileWriter,
+    split: SplitConfig,
+    registry: &BackendRegistry,
+    scores: Option<Arc<MirrorScores>>,
+    controller: Option<Arc<ConnectionController>>,
+) -> bool {
+    download_resumable_chunks_from_mirrors(
+        cargo_info,
+        mirror_urls,
+        out_file,
+        split,
+        registry,
+        scores,
+        controller,
+    ).is_downloaded
+}
 
-    let mut mpb = MultiBar::new();
-    mpb.println(&format!("Downloading {} chunk(s): ", nb_chunks));
+/// Function to download each chunk of a remote content, like `download_chunks_from_mirrors`.
+/// If the download fails, the outcome tells how many bytes at the beginning of the content
+/// have been written in the output file, in order to resume the download after them.
+pub fn download_resumable_chunks_from_mirrors<'a>(
+    cargo_info: RemoteServerInformations<'a>,
+    mirror_urls: Vec<String>,
+    out_file: OutputFileWriter,
+    split: SplitConfig,
+    registry: &BackendRegistry,
+    scores: Option<Arc<MirrorScores>>,
+    controller: Option<Arc<ConnectionController>>,
+) -> DownloadOutcome {
+    let content_length = cargo_info.file.content_length;
+    let accept_partialcontent = cargo_info.accept_partialcontent;
+    // Without ranges support, the whole content is downloaded as a single chunk
+    let resume_from = if accept_partialcontent {
+        min(split.resume_from, content_length)
+    } else {
+        0
+    };
+    let remaining_length = content_length - resume_from;
+    let global_chunk_length = if accept_partialcontent {
+        split.get_chunk_length(remaining_length)
+    } else {
+        max(content_length, 1)
+    };
+    let nb_chunks = get_chunks_count(remaining_length, global_chunk_length);
+
+    if registry.get(cargo_info.url).is_none() {
+        error!(&format!("No backend is available for {}", cargo_info.url));
+        return DownloadOutcome {
+            is_downloaded: false,
+            downloaded_prefix: resume_from,
+        };
+    }
+    let mut urls = vec![String::from(cargo_info.url)];
+    urls.extend(check_mirrors(
+        cargo_info.url,
+        cargo_info.auth_header.as_ref(),
+        content_length,
+        mirror_urls,
+        registry,
+    ));
+    let mirrors = Mirrors::with_scores(urls, registry, scores);
+
+    // The queue of chunks, pulled in order by the workers
+    let queue: Mutex<VecDeque<ChunkTask>> = Mutex::new(
+        (0..nb_chunks)
+            .map(|chunk_index| ChunkTask {
+                index: chunk_index,
+                range: get_chunk_length(chunk_index, remaining_length, global_chunk_length)
+                    .map(|RangeBytes(begin, end)| RangeBytes(begin + resume_from, end + resume_from))
+                    .unwrap(),
+                first_mirror: (chunk_index % mirrors.len() as u64) as usize,
+            })
+            .collect(),
+    );
+    let workers = max(min(split.workers as u64, nb_chunks) as usize, 1);
+    let pool = match new_thread_pool(workers) {
+        Ok(pool) => pool,
+        Err(err) => {
+            error!(&format!("Cannot start the workers: {}", err));
+            return DownloadOutcome {
+                is_downloaded: false,
+                downloaded_prefix: resume_from,
+            };
+        }
+    };
 
-    for chunk_index in 0..nb_chunks {
+    if let Some(ref controller) = controller {
+        ConnectionController::monitor(controller.clone());
+    }
 
-        let server_url = cargo_info.url.clone();
-        let url_clone = String::from(server_url);
-        let (mut http_header, RangeBytes(chunk_offset, chunk_length)) =
-            get_header_from_index(chunk_index, content_length, global_chunk_length).unwrap();
-        let current_config = Config { enable_ssl: ssl_support };
-        let hyper_client = current_config.get_hyper_client();
-        if let Some(auth_header_factory) = auth_header_factory.clone() {
-            http_header.set(auth_header_factory.build_header());
-        }
-        let monothreading = cargo_info.accept_partialcontent;
-
-        // Initialize the progress bar for that chunk
-        initbar!(mp, mpb, chunk_length, chunk_index, server_url);
-
-        let chunk_writer = out_file.get_chunk_writer(chunk_offset);
-
-        // In this work, we push a boolean value to know if the chunk is OK
-        jobs.push(thread::spawn(move || match download_a_chunk(
-            &hyper_client,
-            http_header,
-            chunk_writer,
-            &url_clone,
-            &mut mp,
-            monothreading,
-        ) {
-            Ok(bytes_written) => {
-                mp.finish();
-                if bytes_written == 0 {
-                    error!(&format!("The downloaded chunk {} is empty", chunk_index));
+    // One progress bar per worker, reset for each chunk - the bars are drawn by another thread,
+    // which returns once every worker has dropped its bar
+    let (bars, progress): (Vec<ChunkBar>, _) = if split.show_progress {
+        let mut progress = ProgressBars::new(&format!(
+            "Downloading {} chunk(s), using {} worker(s): ",
+            nb_chunks, workers
+        ));
+        let bars = (0..workers).map(|_| progress.create_bar()).collect();
+        (bars, Some(progress.render(io::stdout())))
+    } else {
+        ((0..workers).map(|_| ChunkBar::hidden()).collect(), None)
+    };
+
+    let is_downloaded = AtomicBool::new(true);
+    // The number of bytes written at the beginning of each chunk
+    let downloaded: Vec<AtomicU64> = (0..nb_chunks).map(|_| AtomicU64::new(0)).collect();
+    {
+        let (queue, mirrors, out_file) = (&queue, &mirrors, &out_file);
+        let is_downloaded = &is_downloaded;
+        let downloaded = &downloaded;
+        let cargo_info = &cargo_info;
+        let controller = controller.as_deref();
+        pool.scope(|scope| for mut mp in bars {
+            scope.spawn(move |_| {
+                // Stop pulling chunks as soon as a chunk failed: the download failed anyway
+                while is_downloaded.load(Ordering::SeqCst) {
+                    let task = match queue.lock().unwrap().pop_front() {
+                        Some(task) => task,
+                        None => break,
+                    };
+                    mp.start(
+                        task.range.length(),
+                        &format!("Chunk {} (from {}) ", task.index, mirrors.url(task.first_mirror)),
+                    );
+                    if !download_a_chunk_from_mirrors(
+                        &task,
+                        mirrors,
+                        cargo_info,
+                        out_file,
+                        &mut mp,
+                        controller,
+                        &downloaded[task.index as usize],
+                    ) {
+                        is_downloaded.store(false, Ordering::SeqCst);
+                    }
                 }
-                return true;
-            }
-            Err(error) => {
                 mp.finish();
-                error!(&format!(
-                    "Cannot download the chunk {}, due to error {}",
-                    chunk_index,
-                    error
-                ));
-                return false;
-            }
-        }));
+            });
+        });
+    }
+    if let Some(progress) = progress {
+        let _ = progress.join();
+    }
+    if let Some(ref controller) = controller {
+        controller.finish();
     }
 
-    mpb.listen();
-
-    // Contain the result state for chunks
-    let mut child_results: Vec<bool> = Vec::with_capacity(nb_chunks as usize);
-
-    for child in jobs {
-        match child.join() {
-            Ok(b) => child_results.push(b),
-            Err(_) => child_results.push(false),
-        }
+    // Wait for the writes still in progress
+    if let Err(err) = out_file.flush() {
+        error!(&format!("Cannot write the downloaded content: {}", err));
+        return DownloadOutcome {
+            is_downloaded: false,
+            downloaded_prefix: resume_from,
+        };
     }
 
     // Check if all chunks are OK
-    return child_results.iter().all(|x| *x);
+    let downloaded: Vec<Bytes> = downloaded
+        .iter()
+        .map(|chunk_downloaded| chunk_downloaded.load(Ordering::SeqCst))
+        .collect();
+    DownloadOutcome {
+        is_downloaded: is_downloaded.load(Ordering::SeqCst),
+        downloaded_prefix: resume_from
+            + get_downloaded_prefix(&downloaded, remaining_length, global_chunk_length),
+    }
 }
 
 #[cfg(test)]
 mod test_chunk_length {
 
-    use super::get_chunk_length;
-    use super::RangeBytes;
+    use super::{get_chunk_length, get_chunks_count, get_downloaded_prefix, SplitConfig,
+                DEFAULT_MAX_CHUNK_SIZE};
+    use RangeBytes;
 
     #[test]
     fn wrong_content_length_parameter_should_return_none() {
@@ -251,27 +547,43 @@ mod test_chunk_length {
         assert_eq!(None, get_chunk_length(4, 1000, 250));
     }
 
-}
-
-#[cfg(test)]
-mod test_header {
+    #[test]
+    fn get_the_last_range_of_one_byte() {
+        assert_eq!(Some(RangeBytes(1000, 1000)), get_chunk_length(4, 1001, 250));
+        assert_eq!(5, get_chunks_count(1001, 250));
+    }
 
-    use super::{get_header_from_index, RangeBytes};
-    use hyper::header::{ByteRangeSpec, Headers, Range};
+    #[test]
+    fn content_should_be_split_between_the_workers() {
+        let split = SplitConfig::new(4);
+        assert!(!split.show_progress);
+        assert_eq!(251, split.get_chunk_length(1000));
+        assert_eq!(4, get_chunks_count(1000, 251));
+        assert_eq!(DEFAULT_MAX_CHUNK_SIZE, split.get_chunk_length(1 << 40));
+    }
 
     #[test]
-    fn wrong_chunk_length_should_return_none() {
-        assert_eq!(None, get_header_from_index(0, 0, 0));
+    fn chunk_size_should_respect_the_minimum_split_size() {
+        let split = SplitConfig {
+            chunk_size: Some(100),
+            ..SplitConfig::new(4)
+        };
+        assert_eq!(100, split.get_chunk_length(1000));
+        assert_eq!(10, get_chunks_count(1000, 100));
+        let split = SplitConfig {
+            min_split_size: 400,
+            ..SplitConfig::new(4)
+        };
+        assert_eq!(400, split.get_chunk_length(1000));
+        assert_eq!(3, get_chunks_count(1000, 400));
     }
 
     #[test]
-    fn good_chunk_length_should_return_a_good_header() {
-        let mut test_header = Headers::new();
-        test_header.set(Range::Bytes(vec![ByteRangeSpec::FromTo(750, 997)]));
-        assert_eq!(
-            Some((test_header, RangeBytes(750, 247))),
-            get_header_from_index(3, 998, 250)
-        );
+    fn downloaded_prefix_should_stop_at_the_first_incomplete_chunk() {
+        assert_eq!(1000, get_downloaded_prefix(&[250, 250, 250, 250], 1000, 250));
+        assert_eq!(600, get_downloaded_prefix(&[250, 250, 100, 250], 1000, 250));
+        assert_eq!(0, get_downloaded_prefix(&[0, 250, 250, 250], 1000, 250));
+        assert_eq!(998, get_downloaded_prefix(&[250, 250, 250, 248], 998, 250));
     }
 
 }
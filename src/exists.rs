@@ -0,0 +1,167 @@
+//! Policies to apply when the local path of a download already exists.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+use Bytes;
+
+/// What to do when the local path already exists
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExistsPolicy {
+    /// Replace the existing file
+    Overwrite,
+    /// Keep the existing file, and do not download anything
+    Skip,
+    /// Download to another path: `<name>.1`, `<name>.2`...
+    Rename,
+    /// Download only the end of the remote content, after the bytes kept in the temporary file
+    /// of a failed download
+    Resume,
+    /// Replace the existing file only if the remote content is newer, or has another length
+    Newer,
+}
+
+impl FromStr for ExistsPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ExistsPolicy, ()> {
+        match s.trim().to_lowercase().as_str() {
+            "overwrite" => Ok(ExistsPolicy::Overwrite),
+            "skip" => Ok(ExistsPolicy::Skip),
+            "rename" => Ok(ExistsPolicy::Rename),
+            "resume" => Ok(ExistsPolicy::Resume),
+            "newer" => Ok(ExistsPolicy::Newer),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ExistsPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExistsPolicy::Overwrite => write!(f, "overwrite"),
+            ExistsPolicy::Skip => write!(f, "skip"),
+            ExistsPolicy::Rename => write!(f, "rename"),
+            ExistsPolicy::Resume => write!(f, "resume"),
+            ExistsPolicy::Newer => write!(f, "newer"),
+        }
+    }
+}
+
+/// Returns the first path `<path>.1`, `<path>.2`... that does not exist yet
+pub fn get_available_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default();
+    let mut index: u64 = 1;
+    loop {
+        let mut available_name = OsString::from(file_name);
+        available_name.push(format!(".{}", index));
+        let available_path = path.with_file_name(available_name);
+        if !available_path.exists() {
+            return available_path;
+        }
+        index += 1;
+    }
+}
+
+/// Returns true if the local file is up to date: it has the length of the remote content, and
+/// it has been modified after the remote content.
+/// If the last modification date of the remote content is unknown, the local file is never up
+/// to date.
+pub fn is_up_to_date(
+    path: &Path,
+    content_length: Bytes,
+    last_modified: Option<SystemTime>,
+) -> io::Result<bool> {
+    let metadata = metadata(path)?;
+    let remote_last_modified = match last_modified {
+        Some(remote_last_modified) => remote_last_modified,
+        None => return Ok(false),
+    };
+    Ok(metadata.len() == content_length && metadata.modified()? >= remote_last_modified)
+}
+
+/// Returns the number of bytes of the remote content already in the local file, i.e. the
+/// offset to resume the download from.
+/// This function returns None if the local file is longer than the remote content: the local
+/// file is not a part of the remote content.
+pub fn get_resume_offset(path: &Path, content_length: Bytes) -> io::Result<Option<Bytes>> {
+    let length = metadata(path)?.len();
+    if length > content_length {
+        return Ok(None);
+    }
+    Ok(Some(length))
+}
+
+#[cfg(test)]
+mod test_exists {
+
+    use super::{get_available_path, get_resume_offset, is_up_to_date, ExistsPolicy};
+    use std::env;
+    use std::fs::{create_dir_all, remove_dir_all, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::{Duration, SystemTime};
+
+    fn create_test_dir(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("zou-{}-{}", name, process::id()));
+        create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn policies_should_be_parsed() {
+        assert_eq!(Ok(ExistsPolicy::Overwrite), "overwrite".parse());
+        assert_eq!(Ok(ExistsPolicy::Skip), "skip".parse());
+        assert_eq!(Ok(ExistsPolicy::Rename), "rename".parse());
+        assert_eq!(Ok(ExistsPolicy::Resume), "Resume".parse());
+        assert_eq!(Ok(ExistsPolicy::Newer), "newer".parse());
+        assert!("prompt".parse::<ExistsPolicy>().is_err());
+    }
+
+    #[test]
+    fn renamed_path_should_not_exist() {
+        let directory = create_test_dir("rename");
+        let path = directory.join("zou.bin");
+        File::create(&path).unwrap();
+        File::create(directory.join("zou.bin.1")).unwrap();
+        let available_path = get_available_path(&path);
+        remove_dir_all(&directory).unwrap();
+        assert_eq!(directory.join("zou.bin.2"), available_path);
+    }
+
+    #[test]
+    fn local_file_should_be_up_to_date() {
+        let directory = create_test_dir("newer");
+        let path = directory.join("zou.bin");
+        File::create(&path).unwrap().write_all(b"zou").unwrap();
+        let local_date = path.metadata().unwrap().modified().unwrap();
+        let older_date = local_date - Duration::from_secs(3600);
+        let newer_date = local_date + Duration::from_secs(3600);
+
+        assert!(is_up_to_date(&path, 3, Some(older_date)).unwrap());
+        assert!(!is_up_to_date(&path, 3, Some(newer_date)).unwrap());
+        assert!(!is_up_to_date(&path, 4, Some(older_date)).unwrap());
+        assert!(!is_up_to_date(&path, 3, None).unwrap());
+        remove_dir_all(&directory).unwrap();
+        assert!(is_up_to_date(&path, 3, Some(SystemTime::now())).is_err());
+    }
+
+    #[test]
+    fn download_should_resume_after_the_local_bytes() {
+        let directory = create_test_dir("resume");
+        let path = directory.join("zou.bin");
+        File::create(&path).unwrap().write_all(b"zou").unwrap();
+        let offsets = (
+            get_resume_offset(&path, 10).unwrap(),
+            get_resume_offset(&path, 3).unwrap(),
+            get_resume_offset(&path, 2).unwrap(),
+        );
+        remove_dir_all(&directory).unwrap();
+        assert_eq!((Some(3), Some(3), None), offsets);
+    }
+}
@@ -56,3 +56,50 @@ impl From<u64> for StringFileSize {
     }
 }
 
+
+/// Function to parse a size given by the user, as a number of bytes, optionally followed by a
+/// binary unit (K, M, G or T - e.g. "512K", "4M", "1GiB").
+/// This function returns None if the size is not valid.
+pub fn parse_file_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let number_length = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(number_length);
+    let number: u64 = number.parse().ok()?;
+    let unit = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => FileSize::B,
+        "K" | "KB" | "KIB" => FileSize::KB,
+        "M" | "MB" | "MIB" => FileSize::MB,
+        "G" | "GB" | "GIB" => FileSize::GB,
+        "T" | "TB" | "TIB" => FileSize::TB,
+        _ => return None,
+    };
+    number.checked_mul(unit.value())
+}
+
+#[cfg(test)]
+mod test_filesize {
+
+    use super::parse_file_size;
+
+    #[test]
+    fn sizes_without_unit_should_be_in_bytes() {
+        assert_eq!(Some(1000), parse_file_size("1000"));
+    }
+
+    #[test]
+    fn sizes_should_use_binary_units() {
+        assert_eq!(Some(512 * 1024), parse_file_size("512K"));
+        assert_eq!(Some(4 * 1_048_576), parse_file_size("4M"));
+        assert_eq!(Some(1_073_741_824), parse_file_size("1GiB"));
+    }
+
+    #[test]
+    fn wrong_sizes_should_return_none() {
+        assert_eq!(None, parse_file_size(""));
+        assert_eq!(None, parse_file_size("M"));
+        assert_eq!(None, parse_file_size("4X"));
+        assert_eq!(None, parse_file_size("99999999999T"));
+    }
+}
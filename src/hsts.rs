@@ -0,0 +1,268 @@
+//! HTTP Strict Transport Security (RFC 6797): hosts that asked to be accessed only through
+//! HTTPS are stored, and their HTTP URLs are upgraded to HTTPS before sending any request.
+
+use cache::get_cache_file;
+use hyper::header::{Headers, StrictTransportSecurity};
+use hyper::Url;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the file that stores the known HSTS hosts, in the cache directory
+const HSTS_CACHE_FILE: &str = "hsts";
+
+/// Policy of a known HSTS host
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct HstsPolicy {
+    /// Expiry date of the policy, in seconds since the UNIX epoch
+    expires: u64,
+    /// The policy applies to the subdomains of the host too
+    include_subdomains: bool,
+}
+
+/// Returns the current date, in seconds since the UNIX epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Store of the known HSTS hosts.
+/// If the store is backed by a file, every new policy is saved immediately, in order to be
+/// used by the next runs.
+pub struct HstsStore {
+    policies: Mutex<HashMap<String, HstsPolicy>>,
+    path: Option<PathBuf>,
+}
+
+impl HstsStore {
+    /// Returns an empty store, that lives only in memory
+    pub fn new() -> HstsStore {
+        HstsStore {
+            policies: Mutex::new(HashMap::new()),
+            path: None,
+        }
+    }
+
+    /// Returns the store saved in the given file - if the file does not exist yet, the store
+    /// is empty.
+    /// Each line of the file is `<host> <expiry date> <include subdomains (0 or 1)>`.
+    pub fn load(path: PathBuf) -> io::Result<HstsStore> {
+        let mut policies = HashMap::new();
+        match File::open(&path) {
+            Ok(file) => {
+                let current_date = now();
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    if fields.len() != 3 {
+                        continue;
+                    }
+                    let expires = match fields[1].parse() {
+                        Ok(expires) if expires > current_date => expires,
+                        _ => continue,
+                    };
+                    policies.insert(
+                        fields[0].to_lowercase(),
+                        HstsPolicy {
+                            expires,
+                            include_subdomains: fields[2] == "1",
+                        },
+                    );
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(HstsStore {
+            policies: Mutex::new(policies),
+            path: Some(path),
+        })
+    }
+
+    /// Returns the store saved in the cache directory of zou
+    pub fn load_from_cache() -> io::Result<HstsStore> {
+        HstsStore::load(get_cache_file(HSTS_CACHE_FILE)?)
+    }
+
+    /// Returns true if the given host (a domain name) must be accessed only through HTTPS
+    pub fn is_known_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        let policies = self.policies.lock().unwrap();
+        let current_date = now();
+        let is_valid = |policy: &HstsPolicy| policy.expires > current_date;
+
+        if let Some(policy) = policies.get(&host) {
+            if is_valid(policy) {
+                return true;
+            }
+        }
+        // Check the superdomains that include their subdomains
+        let mut domain = host.as_str();
+        while let Some(index) = domain.find('.') {
+            domain = &domain[index + 1..];
+            if let Some(policy) = policies.get(domain) {
+                if policy.include_subdomains && is_valid(policy) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Returns the HTTPS URL to use instead of the given HTTP URL, if its host is a known
+    /// HSTS host
+    pub fn upgrade(&self, url: &str) -> Option<String> {
+        let mut parsed_url = Url::parse(url).ok()?;
+        if parsed_url.scheme() != "http" || !self.is_known_host(parsed_url.domain()?) {
+            return None;
+        }
+        let port = parsed_url.port();
+        parsed_url.set_scheme("https").ok()?;
+        // The default HTTP port is replaced by the default HTTPS port
+        if port == Some(80) {
+            parsed_url.set_port(None).ok()?;
+        }
+        Some(parsed_url.into_string())
+    }
+
+    /// Store the policy sent back by a server, in the headers of a response to the given URL.
+    /// The policy is ignored if it has not been received through HTTPS, or if the host is
+    /// an IP address.
+    pub fn record(&self, url: &Url, headers: &Headers) {
+        let sts = match headers.get::<StrictTransportSecurity>() {
+            Some(sts) => sts,
+            None => return,
+        };
+        let host = match url.domain() {
+            Some(host) if url.scheme() == "https" => host.trim_end_matches('.').to_lowercase(),
+            _ => return,
+        };
+
+        let changed = {
+            let mut policies = self.policies.lock().unwrap();
+            if sts.max_age == 0 {
+                policies.remove(&host).is_some()
+            } else {
+                let policy = HstsPolicy {
+                    expires: now() + sts.max_age,
+                    include_subdomains: sts.include_subdomains,
+                };
+                match policies.insert(host, policy) {
+                    Some(previous) => previous.include_subdomains != policy.include_subdomains,
+                    None => true,
+                }
+            }
+        };
+        // Refreshing the expiry date only is not worth a write
+        if changed {
+            if let Err(err) = self.save() {
+                warning!(&format!("Cannot save the HSTS hosts: {}", err));
+            }
+        }
+    }
+
+    /// Save the store in its file, if any
+    pub fn save(&self) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut file = File::create(path)?;
+        let policies = self.policies.lock().unwrap();
+        for (host, policy) in policies.iter() {
+            writeln!(
+                file,
+                "{} {} {}",
+                host,
+                policy.expires,
+                if policy.include_subdomains { 1 } else { 0 }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for HstsStore {
+    fn default() -> HstsStore {
+        HstsStore::new()
+    }
+}
+
+#[cfg(test)]
+mod test_hsts {
+
+    use super::HstsStore;
+    use hyper::header::{Headers, StrictTransportSecurity};
+    use hyper::Url;
+    use std::env;
+    use std::fs::remove_file;
+    use std::process;
+
+    fn record(store: &HstsStore, url: &str, sts: StrictTransportSecurity) {
+        let mut headers = Headers::new();
+        headers.set(sts);
+        store.record(&Url::parse(url).unwrap(), &headers);
+    }
+
+    #[test]
+    fn unknown_host_should_not_be_upgraded() {
+        let store = HstsStore::new();
+        assert_eq!(None, store.upgrade("http://example.com/zou.bin"));
+    }
+
+    #[test]
+    fn known_host_should_be_upgraded() {
+        let store = HstsStore::new();
+        record(&store, "https://example.com/", StrictTransportSecurity::excluding_subdomains(3600));
+        assert_eq!(
+            Some(String::from("https://example.com/zou.bin")),
+            store.upgrade("http://example.com:80/zou.bin")
+        );
+        assert_eq!(
+            Some(String::from("https://example.com:8080/zou.bin")),
+            store.upgrade("http://example.com:8080/zou.bin")
+        );
+        assert_eq!(None, store.upgrade("http://www.example.com/zou.bin"));
+    }
+
+    #[test]
+    fn subdomains_should_be_upgraded_if_included() {
+        let store = HstsStore::new();
+        record(&store, "https://example.com/", StrictTransportSecurity::including_subdomains(3600));
+        assert!(store.upgrade("http://dl.www.example.com/zou.bin").is_some());
+    }
+
+    #[test]
+    fn policy_received_through_http_should_be_ignored() {
+        let store = HstsStore::new();
+        record(&store, "http://example.com/", StrictTransportSecurity::excluding_subdomains(3600));
+        assert!(!store.is_known_host("example.com"));
+    }
+
+    #[test]
+    fn null_max_age_should_remove_the_host() {
+        let store = HstsStore::new();
+        record(&store, "https://example.com/", StrictTransportSecurity::excluding_subdomains(3600));
+        record(&store, "https://example.com/", StrictTransportSecurity::excluding_subdomains(0));
+        assert!(!store.is_known_host("example.com"));
+    }
+
+    #[test]
+    fn known_hosts_should_be_persisted() {
+        let path = env::temp_dir().join(format!("zou-hsts-{}", process::id()));
+        {
+            let store = HstsStore::load(path.clone()).unwrap();
+            record(&store, "https://example.com/", StrictTransportSecurity::including_subdomains(3600));
+        }
+        let store = HstsStore::load(path.clone()).unwrap();
+        remove_file(&path).unwrap();
+        assert!(store.is_known_host("www.example.com"));
+    }
+
+}
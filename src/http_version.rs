@@ -6,6 +6,9 @@ use hyper::version::HttpVersion;
 pub trait ValidateHttpVersion {
     /// Validate that the current HttpVersion is at least 1.1 to be able to download chunks.
     fn greater_than_http_11(&self) -> bool;
+
+    /// Validate that the requests can be multiplexed on a single connection (HTTP/2).
+    fn is_multiplexed(&self) -> bool;
 }
 
 impl ValidateHttpVersion for HttpVersion {
@@ -15,4 +18,11 @@ impl ValidateHttpVersion for HttpVersion {
     fn greater_than_http_11(&self) -> bool {
         self >= &HttpVersion::Http11
     }
+
+    /// Check the given HttpVersion.
+    ///
+    /// This version should be at least 2.0 to send the requests as streams of a single connection.
+    fn is_multiplexed(&self) -> bool {
+        self >= &HttpVersion::Http20
+    }
 }
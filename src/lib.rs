@@ -1,29 +1,97 @@
 extern crate ansi_term;
+extern crate base64;
+#[cfg(feature = "async-http")]
+extern crate http;
+#[cfg(feature = "async-http")]
+extern crate http_body_util;
 extern crate hyper;
+#[cfg(feature = "async-http")]
+extern crate hyper1;
+#[cfg(feature = "tls-openssl")]
 extern crate hyper_openssl;
+#[cfg(feature = "async-http")]
+extern crate hyper_rustls;
+#[cfg(feature = "async-http")]
+extern crate hyper_util;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+extern crate io_uring;
+extern crate libc;
+extern crate md5;
+#[cfg(feature = "tls-openssl")]
+extern crate openssl;
 extern crate pbr;
 extern crate rayon;
+#[cfg(feature = "tls-rustls")]
+extern crate rustls;
+#[cfg(feature = "async-http")]
+extern crate rustls_async;
+#[cfg(feature = "async-http")]
+extern crate rustls_pki_types;
+extern crate sha1;
+extern crate sha2;
+extern crate time;
+#[cfg(feature = "async-http")]
+extern crate tokio;
+#[cfg(feature = "async-http")]
+extern crate tower_service;
+#[cfg(feature = "tls-rustls")]
+extern crate webpki;
+#[cfg(feature = "tls-rustls")]
+extern crate webpki_roots;
+#[cfg(feature = "async-http")]
+extern crate webpki_roots_async;
 
 use std::sync::{Arc, Mutex};
 
 #[macro_use]
 pub mod logs;
 
+pub mod adaptive;
+pub mod allocation;
+#[cfg(feature = "async-http")]
+pub mod async_pool;
 pub mod authorization;
+pub mod backend;
 pub mod bench;
+pub mod cache;
 pub mod cargo_helper;
 pub mod client;
+pub mod consistency;
+pub mod connector;
 pub mod contentlength;
 pub mod download;
+pub mod exists;
 pub mod filesize;
+pub mod hsts;
 pub mod http_version;
+pub mod memory;
+pub mod metalink;
+pub mod mirror_scores;
+pub mod mirrors;
+pub mod part;
+pub mod progress;
 pub mod protocol;
 pub mod response;
+pub mod stream;
+pub mod timestamping;
+pub mod tls;
 pub mod util;
 pub mod write;
 
 /// Represents a number of bytes, as `u64`.
 pub type Bytes = u64;
+
+/// Represents an inclusive range between two Bytes types
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeBytes(pub Bytes, pub Bytes);
+
+impl RangeBytes {
+    /// Returns the number of bytes contained in the range
+    pub fn length(&self) -> Bytes {
+        self.1 - self.0 + 1
+    }
+}
+
 /// Represents a 'chunk', which is just a piece of bytes.
 type Chunk = Vec<u8>;
 /// Represents a list of chunks
@@ -1,7 +1,7 @@
 macro_rules! warning {
     ($message:expr) => {{
         use ansi_term::Colour::Yellow;
-        println!("{}",
+        eprintln!("{}",
             Yellow
                 .bold()
                 .paint("[WARNING] ".to_owned() + $message)
@@ -13,7 +13,7 @@ macro_rules! warning {
 macro_rules! error {
     ($message:expr) => {{
         use ansi_term::Colour::Red;
-        println!("{}",
+        eprintln!("{}",
             Red
                 .bold()
                 .paint("[ERROR] ".to_owned() + $message)
@@ -31,7 +31,7 @@ macro_rules! epanic {
 macro_rules! info {
     ($message:expr) => {{
         use ansi_term::Colour::White;
-        println!("{}",
+        eprintln!("{}",
             White
                 .bold()
                 .paint("[DEBUG] ".to_owned() + $message)
@@ -43,7 +43,7 @@ macro_rules! info {
 macro_rules! ok {
     ($message:expr) => {{
         use ansi_term::Colour::Green;
-        println!("{}",
+        eprintln!("{}",
             Green
                 .bold()
                 .paint("OK! ".to_owned() + $message)
@@ -2,22 +2,240 @@ extern crate ansi_term;
 #[macro_use]
 extern crate clap;
 extern crate hyper;
+extern crate libc;
 extern crate libzou;
 extern crate num_cpus;
 
-use clap::{App, Arg};
-use libzou::cargo_helper::get_remote_server_informations;
-use libzou::download::download_chunks;
-use libzou::filesize::StringFileSize;
-use libzou::protocol::{get_protocol, Protocol};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use libzou::adaptive::{ConnectionController, DEFAULT_MAX_CONNECTIONS};
+use libzou::allocation::{allocate, check_free_space, FileAllocation};
+use libzou::backend::BackendRegistry;
+use libzou::cargo_helper::{get_modified_remote_server_informations, get_remote_server_informations};
+use libzou::client::Config;
+use libzou::Bytes;
+use libzou::bench::{bench_urls, rank_mirror_urls, DEFAULT_SAMPLE_BYTES};
+use libzou::download::{download_resumable_chunks_from_mirrors, SplitConfig};
+use libzou::exists::{get_available_path, get_resume_offset, is_up_to_date, ExistsPolicy};
+use libzou::filesize::{parse_file_size, StringFileSize};
+use libzou::hsts::HstsStore;
+use libzou::metalink::verify_file;
+use libzou::mirror_scores::{MirrorScores, DEFAULT_SCORES_TTL_SECS};
+use libzou::mirrors::get_mirror_url;
+use libzou::part::{clear_part_validators, commit_part_file, get_part_path, get_part_resume_offset,
+                   keep_part_file};
+use libzou::protocol::get_scheme;
+use libzou::stream::{open_stream, StreamConfig};
+use libzou::timestamping::{get_local_validators, load_validators, save_validators,
+                           set_last_modified};
+use libzou::tls;
+use libzou::tls::{ClientCertificate, TlsConfig, TlsVersion};
 use libzou::util::prompt_user;
 use libzou::write::OutputFileWriter;
 #[macro_use]
 mod logs;
-use std::error::Error;
-use std::fs::{File, remove_file};
-use std::path::Path;
+use std::fs::{OpenOptions, remove_file};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default number of seconds after which a stalled connection is dropped
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Default minimum length of a chunk (1 MiB)
+const DEFAULT_MIN_SPLIT_SIZE: Bytes = 1_048_576;
+
+/// Returns the size given to the argument `name`, if any - exits if the size is not valid
+fn get_size_argument(argparse: &ArgMatches, name: &str) -> Option<Bytes> {
+    argparse.value_of(name).map(|value| match parse_file_size(value) {
+        Some(size) => size,
+        None => {
+            error!(&format!("Invalid size for --{}: {}", name.replace('_', "-"), value));
+            exit(1);
+        }
+    })
+}
+
+/// Returns the registry of backends, configured using the arguments (TLS, HSTS, connections...)
+fn get_registry(argparse: &ArgMatches) -> BackendRegistry {
+    // Get the TLS settings
+    let client_cert = match argparse.value_of("cert") {
+        Some(cert) => {
+            match ClientCertificate::new(
+                Path::new(cert),
+                argparse.value_of("key").map(Path::new),
+                argparse.value_of("cert_password"),
+            ) {
+                Ok(client_cert) => Some(client_cert),
+                Err(err) => {
+                    error!(&format!("Cannot read the client certificate {}: {}", cert, err));
+                    exit(1);
+                }
+            }
+        }
+        None => None,
+    };
+    let insecure = argparse.is_present("insecure");
+    if insecure {
+        warning!(
+            "--insecure: the certificates of the remote servers are NOT verified! \
+             Anyone on the network can intercept or modify your download."
+        );
+    }
+    let tls = TlsConfig {
+        ca_file: argparse.value_of("cacert").map(PathBuf::from),
+        ca_path: argparse.value_of("capath").map(PathBuf::from),
+        client_cert,
+        insecure,
+        min_version: value_t!(argparse, "tls_min_version", TlsVersion).ok(),
+    };
+
+    // Get the HSTS hosts stored by the previous runs
+    let hsts = if argparse.is_present("hsts") {
+        match HstsStore::load_from_cache() {
+            Ok(hsts) => Some(Arc::new(hsts)),
+            Err(err) => {
+                warning!(&format!("Cannot load the HSTS hosts: {}", err));
+                Some(Arc::new(HstsStore::new()))
+            }
+        }
+    } else {
+        None
+    };
+
+    match BackendRegistry::with_http_config(Config {
+        max_connections_per_host: value_t!(argparse, "max_connections_per_server", usize)
+            .unwrap_or(0),
+        tls,
+        hsts,
+        read_timeout: Some(Duration::from_secs(
+            value_t!(argparse, "timeout", u64).unwrap_or(DEFAULT_TIMEOUT_SECS),
+        )),
+    }) {
+        Ok(registry) => registry,
+        Err(err) => {
+            error!(&format!("Cannot initialize the TLS client: {}", err));
+            exit(1);
+        }
+    }
+}
+
+/// Returns the scores of the mirrors stored by the previous runs
+fn get_mirror_scores() -> MirrorScores {
+    match MirrorScores::load_from_cache(DEFAULT_SCORES_TTL_SECS) {
+        Ok(scores) => scores,
+        Err(err) => {
+            warning!(&format!("Cannot load the scores of the mirrors: {}", err));
+            MirrorScores::default()
+        }
+    }
+}
+
+/// Save the scores of the mirrors, for the next runs
+fn save_mirror_scores(scores: &MirrorScores) {
+    if let Err(err) = scores.save() {
+        warning!(&format!("Cannot save the scores of the mirrors: {}", err));
+    }
+}
+
+/// Download the remote content to the standard output, in order - the chunks fetched ahead are
+/// kept in memory, up to a bounded number of chunks
+fn stream_to_stdout(argparse: &ArgMatches, url: &str, threads: usize) {
+    let registry = get_registry(argparse);
+    let informations = match get_remote_server_informations(url, &registry) {
+        Ok(informations) => informations,
+        Err(err) => {
+            error!(&format!("Getting remote server informations: {}", err));
+            exit(1);
+        }
+    };
+    if !informations.file.digests.is_empty() {
+        warning!("The digest of the download cannot be checked on the standard output.");
+    }
+
+    let mut config = StreamConfig::new(threads);
+    if let Some(chunk_size) = get_size_argument(argparse, "chunk_size") {
+        config.chunk_size = chunk_size;
+    }
+    if argparse.is_present("debug") {
+        info!(&format!(
+            "streaming {}, keeping at most {} in memory",
+            StringFileSize::from(informations.file.content_length),
+            StringFileSize::from(config.get_max_buffered_bytes())
+        ));
+    }
+    let mut stream = match open_stream(&informations, &registry, config) {
+        Ok(stream) => stream,
+        Err(err) => {
+            error!(&format!("Cannot download the remote content: {}", err));
+            exit(1);
+        }
+    };
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if let Err(err) = io::copy(&mut stream, &mut stdout).and_then(|_| stdout.flush()) {
+        error!(&format!("Cannot stream the download: {}", err));
+        exit(1);
+    }
+}
+
+/// Benchmark the mirrors of a remote content, and print the results from the fastest to the
+/// slowest one
+fn bench(argparse: &ArgMatches, bench_args: &ArgMatches) {
+    let url = bench_args.value_of("url").unwrap();
+    let filename = match Path::new(url).file_name().and_then(|filename| filename.to_str()) {
+        Some(filename) => filename,
+        None => epanic!("Cannot get the name of the remote file!"),
+    };
+    let sample_bytes = value_t!(bench_args, "sample_size", Bytes).unwrap_or(DEFAULT_SAMPLE_BYTES);
+    let registry = get_registry(argparse);
+
+    // The content length of the original URL is the reference
+    let probe = match registry.get(url).map(|backend| backend.probe(url, None)) {
+        Some(Ok(probe)) => probe,
+        Some(Err(err)) => {
+            error!(&format!("Getting remote server informations: {}", err));
+            exit(1);
+        }
+        None => epanic!("Unknown protocol!"),
+    };
+    let mut urls = vec![String::from(url)];
+    if let Some(mirrors) = bench_args.values_of("mirrors") {
+        urls.extend(mirrors.filter_map(|mirror| get_mirror_url(mirror, filename)));
+    }
+    // The mirrors advertised by the remote server (Metalink/HTTP) are benchmarked too
+    for duplicate in probe.duplicates {
+        if !urls.contains(&duplicate) {
+            urls.push(duplicate);
+        }
+    }
+    let content_length = probe.content_length;
+
+    info!(&format!(
+        "Downloading {} from {} source(s)...",
+        StringFileSize::from(sample_bytes),
+        urls.len()
+    ));
+    println!("   #     Throughput        TTFB  URL");
+    let scores = get_mirror_scores();
+    for (index, result) in bench_urls(urls, content_length, sample_bytes, &registry)
+        .iter()
+        .enumerate()
+    {
+        println!("{:>4} {}", index + 1, result);
+        match result.measures {
+            Ok(ref measures) => {
+                scores.record_bench(&result.url, measures.bytes, measures.duration, measures.ttfb);
+            }
+            Err(_) => {
+                scores.record_failure(&result.url);
+            }
+        }
+    }
+    save_mirror_scores(&scores);
+}
 
 fn main() {
 
@@ -30,35 +248,122 @@ fn main() {
                  .long("threads")
                  .short("t")
                  .takes_value(true)
-                 .help("Threads which can use to download"))
+                 .help("Threads which can use to download - 'auto' starts with a few connections, and opens more while the throughput keeps rising. If the server negotiates HTTP/2, the threads are streams of a single connection"))
+        .arg(Arg::with_name("chunk_size")
+                 .long("chunk-size")
+                 .takes_value(true)
+                 .help("Length of the chunks downloaded by the threads, e.g. 4M (by default, the file is split between the threads)"))
+        .arg(Arg::with_name("min_split_size")
+                 .long("min-split-size")
+                 .takes_value(true)
+                 .help("Do not split the file into chunks shorter than this size (1M by default)"))
+        .arg(Arg::with_name("cacert")
+                 .long("cacert")
+                 .takes_value(true)
+                 .help("PEM file that contains the certificate authorities to trust"))
+        .arg(Arg::with_name("capath")
+                 .long("capath")
+                 .takes_value(true)
+                 .help("Directory of PEM files that contain the certificate authorities to trust"))
+        .arg(Arg::with_name("cert")
+                 .long("cert")
+                 .takes_value(true)
+                 .help("Client certificate, as a PEM file or a PKCS#12 archive"))
+        .arg(Arg::with_name("cert_password")
+                 .long("cert-password")
+                 .takes_value(true)
+                 .requires("cert")
+                 .help("Password of the PKCS#12 archive given to --cert"))
+        .arg(Arg::with_name("key")
+                 .long("key")
+                 .takes_value(true)
+                 .requires("cert")
+                 .help("PEM private key of the client certificate"))
+        .arg(Arg::with_name("hsts")
+                 .long("hsts")
+                 .help("Upgrade the HTTP URLs of the hosts that asked for HTTPS only (HSTS) - the hosts are stored in the cache directory"))
+        .arg(Arg::with_name("insecure")
+                 .long("insecure")
+                 .short("k")
+                 .help("Do not verify the certificates of the remote servers (DANGEROUS)"))
+        .arg(Arg::with_name("tls_min_version")
+                 .long("tls-min-version")
+                 .takes_value(true)
+                 .possible_values(&["1.0", "1.1", "1.2", "1.3"])
+                 .help("Minimum TLS version to accept"))
         .arg(Arg::with_name("debug")
                  .long("debug")
                  .short("d")
                  .help("Active the debug mode"))
+        .arg(Arg::with_name("file_allocation")
+                 .long("file-allocation")
+                 .takes_value(true)
+                 .possible_values(&["none", "trunc", "falloc"])
+                 .help("How to allocate the local file: 'none' lets the file grow, 'trunc' creates a sparse file (default), 'falloc' allocates the whole file before the download"))
         .arg(Arg::with_name("force")
                  .long("force")
                  .help("Assume Yes to all queries and do not prompt"))
+        .arg(Arg::with_name("on_exists")
+                 .long("on-exists")
+                 .takes_value(true)
+                 .possible_values(&["overwrite", "skip", "rename", "resume", "newer"])
+                 .help("What to do if the local file already exists: replace it, keep it, save the download as <file>.1, <file>.2..., resume the failed download kept in <file>.part, or replace it only if the remote file is newer (asks by default)"))
+        .arg(Arg::with_name("max_connections_per_server")
+                 .long("max-connections-per-server")
+                 .takes_value(true)
+                 .help("Maximum number of simultaneous connections to a server (no limit by default)"))
         .arg(Arg::with_name("mirrors")
                  .long("mirrors")
                  .short("m")
                  .multiple(true)
                  .takes_value(true)
                  .help("Download using a list of mirrors - the list of mirrors is used WITH the original URL"))
+        .arg(Arg::with_name("mmap")
+                 .long("mmap")
+                 .help("Write the chunks in a memory mapping of the local file, instead of using positional writes"))
+        .arg(Arg::with_name("rebench")
+                 .long("rebench")
+                 .help("Benchmark the mirrors again, instead of ranking them using the scores of the previous runs"))
+        .arg(Arg::with_name("timestamping")
+                 .long("timestamping")
+                 .short("N")
+                 .help("Download the remote file only if it has been modified since the previous download - the validators of the remote file are stored in <file>.validators"))
         .arg(Arg::with_name("output")
                 .long("output")
                 .short("o")
                 .takes_value(true)
-                .help("Specify the local output"))
-        .arg(Arg::with_name("ssl_support")
-                .long("ssl_support")
-                .short("s")
-                .help("Switch to an SSL client"))
+                .help("Specify the local output ('-' writes the file to the standard output, in order)"))
+        .arg(Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .help("Number of seconds after which a stalled connection is dropped (30 by default)"))
         .arg(Arg::with_name("url")
             .index(1)
             //.multiple(true)
             .required(true))
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("bench")
+            .about("Benchmark the mirrors of a remote content, using their throughput")
+            .arg(Arg::with_name("mirrors")
+                     .long("mirrors")
+                     .short("m")
+                     .multiple(true)
+                     .takes_value(true)
+                     .help("List of mirrors to benchmark - the original URL is benchmarked too"))
+            .arg(Arg::with_name("sample_size")
+                     .long("sample-size")
+                     .takes_value(true)
+                     .help("Number of bytes to download from each mirror (1 MiB by default)"))
+            .arg(Arg::with_name("url")
+                     .index(1)
+                     .required(true)))
         .get_matches();
 
+    if let Some(bench_args) = argparse.subcommand_matches("bench") {
+        bench(&argparse, bench_args);
+        return;
+    }
+
     // Get informations from arguments
 
     // Get the URL as a Path structure
@@ -68,78 +373,135 @@ fn main() {
     // Get the path filename
     let filename = url.file_name().unwrap().to_str().unwrap();
 
+    // In adaptive mode, the number of threads is the maximum number of connections
+    let adaptive = argparse.value_of("threads") == Some("auto");
+    let max_connections_per_server =
+        value_t!(argparse, "max_connections_per_server", usize).unwrap_or(0);
+
     // Check if multi-threaded download is possible
-    let mut threads: usize = value_t!(argparse, "threads", usize)
-        .and_then(|v| if v != 0 {
-            Ok(v)
-        } else {
-            Err(clap::Error::with_description(
-                "Cannot download a file using 0 thread",
-                clap::ErrorKind::InvalidValue,
-            ))
-        })
-        .unwrap_or(num_cpus::get_physical());
+    let mut threads: usize = if adaptive {
+        match max_connections_per_server {
+            0 => DEFAULT_MAX_CONNECTIONS,
+            max_connections => max_connections,
+        }
+    } else {
+        value_t!(argparse, "threads", usize)
+            .and_then(|v| if v != 0 {
+                Ok(v)
+            } else {
+                Err(clap::Error::with_description(
+                    "Cannot download a file using 0 thread",
+                    clap::ErrorKind::InvalidValue,
+                ))
+            })
+            .unwrap_or(num_cpus::get_physical())
+    };
 
     if argparse.is_present("debug") {
         info!(&format!("zou V{}", crate_version!()));
         info!(&format!(
-            "downloading {}, using {} threads",
+            "downloading {}, using {}{} threads",
             filename,
+            if adaptive { "at most " } else { "" },
             threads
         ));
     }
 
-    let local_path = Path::new(argparse.value_of("output").unwrap_or(&filename));
+    // With `-o -`, the remote content is written to the standard output, without any local file
+    if argparse.value_of("output") == Some("-") {
+        stream_to_stdout(&argparse, url_str, threads);
+        return;
+    }
 
-    if local_path.exists() {
+    let mut local_path = PathBuf::from(argparse.value_of("output").unwrap_or(filename));
+    let timestamping = argparse.is_present("timestamping");
+
+    // Without any policy, ask the user what to do if the local path already exists
+    let on_exists = if local_path.exists() {
         if local_path.is_dir() {
             epanic!(
                 "The local path to store the remote content is already exists, \
                         and is a directory!"
             );
         }
-        if !argparse.is_present("force") {
-            let user_input = prompt_user(
-                "The path to store the file already exists! \
+        match value_t!(argparse, "on_exists", ExistsPolicy) {
+            Ok(on_exists) => on_exists,
+            // The remote server tells if the local file has to be replaced
+            Err(_) if timestamping => ExistsPolicy::Overwrite,
+            Err(_) if argparse.is_present("force") => {
+                warning!(
+                    "The path to store the file already exists! \
+                                 It is going to be overriden once the download is complete."
+                );
+                ExistsPolicy::Overwrite
+            }
+            Err(_) => {
+                let user_input = prompt_user(
+                    "The path to store the file already exists! \
                                           Do you want to override it? [y/N]",
-            );
-            if !(user_input == "y" || user_input == "Y") {
-                exit(0);
+                );
+                if !(user_input == "y" || user_input == "Y") {
+                    exit(0);
+                }
+                ExistsPolicy::Overwrite
             }
-        } else {
-            warning!(
-                "The path to store the file already exists! \
-                                 It is going to be overriden."
-            );
         }
+    } else {
+        // The temporary file of a failed download can be resumed, without any local file
+        value_t!(argparse, "on_exists", ExistsPolicy).unwrap_or(ExistsPolicy::Overwrite)
+    };
+
+    // Get automaticaly the backend to use from the given URL
+    // The registry keeps the connections alive, to reuse them for each request
+    let registry = get_registry(&argparse);
+    if registry.get(url_str).is_none() {
+        if !tls::is_supported() && get_scheme(url_str) == Some(String::from("https")) {
+            epanic!("zou has been built without TLS support: HTTPS URLs are not supported!");
+        }
+        epanic!("Unknown protocol!");
     }
 
-    // Get automaticaly the protocol from the given URL
-    let ssl_support = match get_protocol(url.to_str().unwrap()) {
-        Some(protocol) => {
-            match protocol {
-                // If the protocol is HTTP, return the user decision for the HTTPS client
-                Protocol::HTTP => argparse.is_present("ssl_support"),
-                // Force to use HTTPS client
-                Protocol::HTTPS => true,
+    // With timestamping, the remote content is downloaded only if it has been modified since the
+    // local file
+    let local_validators = if timestamping && local_path.exists()
+        && (on_exists == ExistsPolicy::Overwrite || on_exists == ExistsPolicy::Newer)
+    {
+        match get_local_validators(&local_path) {
+            Ok(validators) => Some(validators),
+            Err(err) => {
+                warning!(&format!("Cannot get the validators of the local file: {}", err));
+                None
             }
         }
-        None => epanic!("Unknown protocol!"),
+    } else {
+        None
+    };
+    let remote_server_informations = match local_validators {
+        Some(ref validators) => {
+            match get_modified_remote_server_informations(url_str, &registry, validators) {
+                Ok(Some(informations)) => Ok(informations),
+                Ok(None) => {
+                    ok!(&format!("{} has not been modified: skipped", local_path.display()));
+                    exit(0);
+                }
+                Err(err) => Err(err),
+            }
+        }
+        None => get_remote_server_informations(url_str, &registry),
     };
 
     // Get remote server informations in order to perform the best download strategy as possible
-    let remote_server_informations = match get_remote_server_informations(url_str, ssl_support) {
+    let remote_server_informations = match remote_server_informations {
         Ok(mut informations) => {
-            // Check if the user asked for monothreading download
-            informations.accept_partialcontent = !(threads == 1);
+            // Check if the user asked for monothreading download - resuming a download needs
+            // ranges anyway
+            informations.accept_partialcontent = informations.accept_partialcontent
+                && (threads != 1 || on_exists == ExistsPolicy::Resume);
             // Return the data structure
             informations
         }
         Err(err) => {
-            error!(&format!(
-                "Getting remote server informations: {}",
-                err.description()
-            ));
+            error!(&format!("Getting remote server informations: {}", err));
             exit(1);
         }
     };
@@ -151,12 +513,156 @@ fn main() {
         )
     ));
 
-    let local_file = File::create(local_path).expect("[ERROR] Cannot create a file !");
+    // When the server multiplexes the requests (HTTP/2), the threads download their chunks
+    // through streams of a single connection: the connections per server do not bound them
+    if remote_server_informations.multiplexed {
+        if adaptive {
+            threads = DEFAULT_MAX_CONNECTIONS;
+        }
+        if argparse.is_present("debug") {
+            info!(&format!(
+                "the server multiplexes the requests (HTTP/2), using {}{} streams of a single connection",
+                if adaptive { "at most " } else { "" },
+                threads
+            ));
+        }
+    }
 
-    local_file
-        .set_len(remote_server_informations.file.content_length)
-        .expect("Cannot extend local file !");
-    let out_file = OutputFileWriter::new(local_file);
+    // Apply the policy if the local path already exists
+    let content_length = remote_server_informations.file.content_length;
+    let validators = remote_server_informations.file.get_validators();
+    let mut resume_from: Bytes = 0;
+    if local_path.exists() {
+        match on_exists {
+            ExistsPolicy::Overwrite => {}
+            ExistsPolicy::Skip => {
+                ok!(&format!("{} already exists: skipped", local_path.display()));
+                exit(0);
+            }
+            ExistsPolicy::Rename => {
+                local_path = get_available_path(&local_path);
+                info!(&format!("The download is saved in {}", local_path.display()));
+            }
+            ExistsPolicy::Newer => {
+                match is_up_to_date(&local_path, content_length, remote_server_informations.file.last_modified) {
+                    Ok(true) => {
+                        ok!(&format!("{} is up to date: skipped", local_path.display()));
+                        exit(0);
+                    }
+                    Ok(false) => {}
+                    Err(err) => warning!(&format!("Cannot compare the local file: {}", err)),
+                }
+            }
+            ExistsPolicy::Resume => {
+                // The local file is complete only if its stored validators show that it is
+                // still the remote content - otherwise, it is replaced once the download is
+                // complete
+                let is_unchanged = match load_validators(&local_path) {
+                    Ok(Some(local_validators)) => local_validators.is_unchanged(&validators),
+                    _ => false,
+                };
+                match get_resume_offset(&local_path, content_length) {
+                    Ok(Some(offset)) if is_unchanged && offset == content_length => {
+                        ok!(&format!("{} is already complete: skipped", local_path.display()));
+                        exit(0);
+                    }
+                    Ok(_) => {}
+                    Err(err) => warning!(&format!("Cannot compare the local file: {}", err)),
+                }
+            }
+        }
+    }
+    // The remote content is downloaded in a temporary file, that replaces the local path once
+    // complete and valid
+    let local_path = local_path.as_path();
+    let part_path = get_part_path(local_path);
+    // When resuming, the download starts after the bytes kept in the temporary file by a failed
+    // download - if the remote content has not changed since then
+    if on_exists == ExistsPolicy::Resume && part_path.exists() {
+        match get_part_resume_offset(&part_path, content_length, &validators) {
+            Ok(0) => warning!(
+                "The temporary file cannot be resumed! Downloading the whole remote file again."
+            ),
+            Ok(offset) if remote_server_informations.accept_partialcontent => {
+                info!(&format!(
+                    "Resuming the download after {}",
+                    StringFileSize::from(offset)
+                ));
+                resume_from = offset;
+            }
+            Ok(_) => warning!(
+                "The remote server does not accept PartialContent status! \
+                 Downloading the whole remote file again."
+            ),
+            Err(err) => warning!(&format!("Cannot resume the download: {}", err)),
+        }
+    }
+    // The content of the temporary file is unknown until the download stops gracefully
+    if let Err(err) = clear_part_validators(&part_path) {
+        error!(&format!("Cannot write the local file: {}", err));
+        exit(1);
+    }
+
+    // Check the free space before fetching any byte of the remote content - the bytes already
+    // in the temporary file are counted as free
+    if let Err(err) = check_free_space(&part_path, content_length) {
+        error!(&format!("Cannot store the remote content: {}", err));
+        exit(1);
+    }
+
+    // The file is opened for reading too, in order to map it in memory
+    let local_file = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(resume_from == 0)
+        .open(&part_path)
+    {
+        Ok(local_file) => local_file,
+        Err(err) => {
+            error!(&format!("Cannot create the local file: {}", err));
+            exit(1);
+        }
+    };
+
+    let file_allocation = value_t!(argparse, "file_allocation", FileAllocation)
+        .unwrap_or(FileAllocation::Trunc);
+    if let Err(err) = allocate(&local_file, content_length, file_allocation) {
+        error!(&format!("Cannot allocate the local file: {}", err));
+        if remove_file(&part_path).is_err() {
+            error!("Cannot delete the local file!");
+        }
+        exit(1);
+    }
+    // The memory mapping needs the whole file: it is not mapped if the file has to grow while
+    // the chunks are written
+    let out_file = if argparse.is_present("mmap") && file_allocation == FileAllocation::None {
+        warning!("The local file is not allocated (--file-allocation none): using positional writes");
+        OutputFileWriter::new(local_file)
+    } else if argparse.is_present("mmap") {
+        match local_file
+            .try_clone()
+            .and_then(|mapped_file| OutputFileWriter::with_mmap(mapped_file, content_length))
+        {
+            Ok(out_file) => out_file,
+            Err(ref err) if err.raw_os_error() == Some(libc::ENOSPC) => {
+                error!(&format!("Cannot allocate the local file: {}", err));
+                if remove_file(&part_path).is_err() {
+                    error!("Cannot delete the local file!");
+                }
+                exit(1);
+            }
+            Err(err) => {
+                warning!(&format!(
+                    "Cannot map the local file in memory ({}): using positional writes",
+                    err
+                ));
+                OutputFileWriter::new(local_file)
+            }
+        }
+    } else {
+        OutputFileWriter::new(local_file)
+    };
 
     // If the server does not accept PartialContent status, download the remote file
     // using only one thread
@@ -167,23 +673,158 @@ fn main() {
         );
         threads = 1;
     }
+    // The adaptive mode opens new connections while the throughput keeps rising, and backs off
+    // when the server asks to slow down
+    let controller = if adaptive && threads > 1 {
+        Some(Arc::new(ConnectionController::new(threads)))
+    } else {
+        None
+    };
 
-    if download_chunks(
+    // The mirrors given by the user, and the mirrors advertised by the remote server
+    // (Metalink/HTTP)
+    let mut candidate_urls: Vec<String> = match argparse.values_of("mirrors") {
+        Some(mirrors) => mirrors
+            .filter_map(|mirror| get_mirror_url(mirror, filename))
+            .collect(),
+        None => Vec::new(),
+    };
+    for duplicate in &remote_server_informations.mirrors {
+        if !candidate_urls.contains(duplicate) {
+            if argparse.is_present("debug") {
+                info!(&format!("the remote server advertises the mirror {}", duplicate));
+            }
+            candidate_urls.push(duplicate.clone());
+        }
+    }
+    // Sort the mirrors, from the fastest to the slowest one - the failing mirrors are removed.
+    // The mirrors are benchmarked only if their scores are unknown or too old.
+    let scores = Arc::new(get_mirror_scores());
+    let mirror_urls: Vec<String> = if candidate_urls.is_empty() {
+        Vec::new()
+    } else {
+        rank_mirror_urls(
+            candidate_urls,
+            remote_server_informations.file.content_length,
+            &registry,
+            &scores,
+            argparse.is_present("rebench"),
+        )
+    };
+    if argparse.is_present("debug") {
+        for mirror_url in &mirror_urls {
+            info!(&format!("using the mirror {}", mirror_url));
+        }
+    }
+
+    // The threads download the chunks one after the other, until every chunk is downloaded
+    let split = SplitConfig {
+        workers: threads,
+        chunk_size: get_size_argument(&argparse, "chunk_size"),
+        min_split_size: get_size_argument(&argparse, "min_split_size")
+            .unwrap_or(DEFAULT_MIN_SPLIT_SIZE),
+        resume_from,
+        show_progress: true,
+    };
+
+    let digests = remote_server_informations.file.digests.clone();
+    let output = out_file.clone();
+    let outcome = download_resumable_chunks_from_mirrors(
         remote_server_informations,
+        mirror_urls,
         out_file,
-        threads as u64,
-        ssl_support,
-    )
-    {
+        split,
+        &registry,
+        Some(scores.clone()),
+        controller,
+    );
+    save_mirror_scores(&scores);
+
+    // Write the whole content on the disk, before replacing the local path
+    let is_synced = outcome.is_downloaded && match output.sync() {
+        Ok(()) => true,
+        Err(err) => {
+            error!(&format!("Cannot write the download on the disk: {}", err));
+            false
+        }
+    };
+    drop(output);
+
+    // Check the download against the digest advertised by the remote server, if any
+    let is_verified = is_synced && match verify_file(&part_path, &digests) {
+        Ok(Some(algorithm)) => {
+            info!(&format!("The {} digest of the download is correct", algorithm));
+            true
+        }
+        Ok(None) => true,
+        Err(err) => {
+            error!(&format!("Cannot verify the download: {}", err));
+            false
+        }
+    };
+
+    // The local file keeps the last modification date of the remote content
+    if is_verified {
+        if let Some(last_modified) = validators.last_modified {
+            if let Err(err) = set_last_modified(&part_path, last_modified) {
+                warning!(&format!("Cannot set the modification date of the download: {}", err));
+            }
+        }
+    }
+
+    // Replace the previous version of the file, if any, by the complete download
+    let is_committed = is_verified && match commit_part_file(&part_path, local_path) {
+        Ok(()) => true,
+        Err(err) => {
+            error!(&format!(
+                "Cannot move the download to {}: {}",
+                local_path.to_str().unwrap(),
+                err
+            ));
+            false
+        }
+    };
+
+    // The validators are stored to tell if the file is complete, when resuming it
+    if is_committed && (timestamping || on_exists == ExistsPolicy::Resume) {
+        if let Err(err) = save_validators(local_path, &validators) {
+            warning!(&format!("Cannot store the validators of the download: {}", err));
+        }
+    }
+
+    if is_committed {
         ok!(&format!(
             "Your download is available in {}",
             local_path.to_str().unwrap()
         ));
     } else {
-        // If the file is not ok, delete it from the file system
-        error!("Download failed! An error occured - erasing file... ");
-        if remove_file(local_path).is_err() {
-            error!("Cannot delete downloaded file!");
+        // The downloaded bytes are kept in the temporary file, to resume the download - unless
+        // the download is complete but wrong. The previous version of the file, if any, is
+        // untouched.
+        let downloaded_prefix = if is_synced && !is_verified {
+            0
+        } else {
+            outcome.downloaded_prefix
+        };
+        let is_kept = downloaded_prefix > 0 && !validators.is_empty()
+            && match keep_part_file(&part_path, downloaded_prefix, &validators) {
+                Ok(()) => true,
+                Err(err) => {
+                    error!(&format!("Cannot keep the downloaded file: {}", err));
+                    false
+                }
+            };
+        if is_kept {
+            error!(&format!(
+                "Download failed! An error occured - use `--on-exists resume` to resume the \
+                 download after {}",
+                StringFileSize::from(downloaded_prefix)
+            ));
+        } else {
+            error!("Download failed! An error occured - erasing file... ");
+            if remove_file(&part_path).is_err() {
+                error!("Cannot delete downloaded file!");
+            }
         }
     }
 
@@ -0,0 +1,112 @@
+//! Downloads without any local file: in memory, or in any `Write + Seek` writer given by the
+//! caller.
+
+use backend::BackendRegistry;
+use cargo_helper::RemoteServerInformations;
+use download::{download_chunks_from_mirrors, SplitConfig};
+use filesize::StringFileSize;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::{Cursor, Seek, Write};
+use std::sync::Arc;
+use write::{OutputFileWriter, SeekSink};
+use Bytes;
+
+/// Some enumeration to display accurate errors, when downloading without any local file
+#[derive(Debug)]
+pub enum MemoryError {
+    /// Error throwed when the remote content is bigger than the maximum size
+    TooLarge { content_length: Bytes, max_size: Bytes },
+    /// Error throwed when a chunk cannot be downloaded
+    DownloadFailed,
+    /// Error throwed when the writer cannot be written, or is still used after the download
+    Io(io::Error),
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryError::TooLarge {
+                content_length,
+                max_size,
+            } => write!(
+                f,
+                "the remote content is too large ({}, at most {} allowed)",
+                StringFileSize::from(content_length),
+                StringFileSize::from(max_size)
+            ),
+            MemoryError::DownloadFailed => write!(f, "the download failed"),
+            MemoryError::Io(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for MemoryError {
+    fn description(&self) -> &str {
+        match *self {
+            MemoryError::TooLarge { .. } => "Remote content too large",
+            MemoryError::DownloadFailed => "Download failed",
+            MemoryError::Io(_) => "I/O error",
+        }
+    }
+}
+
+impl From<io::Error> for MemoryError {
+    fn from(err: io::Error) -> MemoryError {
+        MemoryError::Io(err)
+    }
+}
+
+/// Function to download a remote content into the given writer, and to give it back.
+/// The chunks are written at their offset in the writer, in any order.
+/// The progress bars are written on the standard output only if `split.show_progress` is set.
+pub fn download_to_writer<W: Write + Seek + Send + 'static>(
+    cargo_info: RemoteServerInformations,
+    writer: W,
+    split: SplitConfig,
+    registry: &BackendRegistry,
+) -> Result<W, MemoryError> {
+    let sink = Arc::new(SeekSink::new(writer));
+    let output = OutputFileWriter::with_sink(sink.clone());
+    if !download_chunks_from_mirrors(
+        cargo_info,
+        Vec::new(),
+        output.clone(),
+        split,
+        registry,
+        None,
+        None,
+    ) {
+        return Err(MemoryError::DownloadFailed);
+    }
+    output.sync()?;
+    drop(output);
+
+    match Arc::try_unwrap(sink) {
+        Ok(sink) => Ok(sink.into_inner()),
+        Err(_) => Err(MemoryError::Io(io::Error::other(
+            "the writer is still used after the download",
+        ))),
+    }
+}
+
+/// Function to download a remote content in memory.
+/// The remote content is not downloaded at all if it is bigger than `max_size` bytes.
+/// The progress bars are written on the standard output only if `split.show_progress` is set.
+pub fn download_to_vec(
+    cargo_info: RemoteServerInformations,
+    split: SplitConfig,
+    registry: &BackendRegistry,
+    max_size: Bytes,
+) -> Result<Vec<u8>, MemoryError> {
+    let content_length = cargo_info.file.content_length;
+    if content_length > max_size || content_length > usize::MAX as Bytes {
+        return Err(MemoryError::TooLarge {
+            content_length,
+            max_size,
+        });
+    }
+    let buffer = Cursor::new(Vec::with_capacity(content_length as usize));
+    Ok(download_to_writer(cargo_info, buffer, split, registry)?.into_inner())
+}
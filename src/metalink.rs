@@ -0,0 +1,311 @@
+//! Metalink/HTTP (RFC 6249): download redirectors advertise the mirrors of a content using
+//! `Link: <url>; rel=duplicate` headers, and the digest of the content using `Digest` headers
+//! (RFC 3230).
+
+use base64;
+use hyper::header::Headers;
+use hyper::Url;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Constant to represent the length of the buffer used to compute a digest
+const DIGEST_BUFFER_BYTES: usize = 1024 * 64;
+
+/// Priority of a duplicate without any `pri` parameter - the lowest one, as defined by RFC 6249
+const DEFAULT_DUPLICATE_PRIORITY: u32 = 999_999;
+
+/// Digest algorithms supported by zou, from the weakest to the strongest one
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl FromStr for DigestAlgorithm {
+    type Err = ();
+
+    /// Parse the name of the algorithm, as registered by RFC 3230 and RFC 5843
+    fn from_str(s: &str) -> Result<DigestAlgorithm, ()> {
+        match s.trim().to_uppercase().as_str() {
+            "MD5" => Ok(DigestAlgorithm::Md5),
+            "SHA" => Ok(DigestAlgorithm::Sha1),
+            "SHA-256" => Ok(DigestAlgorithm::Sha256),
+            "SHA-512" => Ok(DigestAlgorithm::Sha512),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DigestAlgorithm::Md5 => write!(f, "MD5"),
+            DigestAlgorithm::Sha1 => write!(f, "SHA"),
+            DigestAlgorithm::Sha256 => write!(f, "SHA-256"),
+            DigestAlgorithm::Sha512 => write!(f, "SHA-512"),
+        }
+    }
+}
+
+/// Digest of a remote content, advertised by the server
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentDigest {
+    pub algorithm: DigestAlgorithm,
+    pub value: Vec<u8>,
+}
+
+/// Some enumeration to display accurate digest errors
+#[derive(Debug)]
+pub enum DigestError {
+    /// Error throwed when reading the downloaded content
+    Io(io::Error),
+    /// Error throwed when the digest of the downloaded content is not the advertised one
+    Mismatch(DigestAlgorithm),
+}
+
+impl fmt::Display for DigestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DigestError::Io(ref err) => write!(f, "{}", err),
+            DigestError::Mismatch(ref algorithm) => {
+                write!(f, "the {} digest is not the advertised one", algorithm)
+            }
+        }
+    }
+}
+
+impl error::Error for DigestError {
+    fn description(&self) -> &str {
+        match *self {
+            DigestError::Io(_) => "I/O error",
+            DigestError::Mismatch(_) => "Digest mismatch",
+        }
+    }
+}
+
+impl From<io::Error> for DigestError {
+    fn from(err: io::Error) -> DigestError {
+        DigestError::Io(err)
+    }
+}
+
+/// Split a header value on the given delimiter, ignoring the delimiters between quotes or
+/// angle brackets
+fn split_unquoted(value: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut in_quotes, mut in_brackets, mut begin) = (false, false, 0);
+    for (index, c) in value.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '<' if !in_quotes => in_brackets = true,
+            '>' if !in_quotes => in_brackets = false,
+            c if c == delimiter && !in_quotes && !in_brackets => {
+                parts.push(&value[begin..index]);
+                begin = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[begin..]);
+    parts
+}
+
+/// Returns the values of a raw header, as strings
+fn get_raw_values(headers: &Headers, name: &str) -> Vec<String> {
+    match headers.get_raw(name) {
+        Some(lines) => lines
+            .iter()
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Returns the duplicates of the content advertised by the `Link` headers, with their
+/// priority.
+/// Relative links are resolved using the URL of the response.
+fn get_prioritized_duplicates(headers: &Headers, base: &Url) -> Vec<(u32, String)> {
+    let mut duplicates = Vec::new();
+    for line in get_raw_values(headers, "Link") {
+        for link_value in split_unquoted(&line, ',') {
+            let mut params = split_unquoted(link_value, ';').into_iter();
+            let target = match params.next().map(str::trim) {
+                Some(target) if target.starts_with('<') && target.ends_with('>') => {
+                    &target[1..target.len() - 1]
+                }
+                _ => continue,
+            };
+            let (mut is_duplicate, mut priority) = (false, DEFAULT_DUPLICATE_PRIORITY);
+            for param in params {
+                let mut param = param.splitn(2, '=');
+                let name = param.next().unwrap_or("").trim().to_lowercase();
+                let value = param.next().unwrap_or("").trim().trim_matches('"');
+                match name.as_str() {
+                    "rel" => {
+                        is_duplicate = value
+                            .split_whitespace()
+                            .any(|rel| rel.eq_ignore_ascii_case("duplicate"))
+                    }
+                    "pri" => priority = value.parse().unwrap_or(DEFAULT_DUPLICATE_PRIORITY),
+                    _ => {}
+                }
+            }
+            if is_duplicate {
+                if let Ok(url) = base.join(target) {
+                    duplicates.push((priority, url.into_string()));
+                }
+            }
+        }
+    }
+    duplicates
+}
+
+/// Returns the URLs of the duplicates of the content, advertised by the `Link` headers of the
+/// given responses (URL and headers of each response) - the preferred duplicates first
+pub fn get_duplicates(responses: &[(&Url, &Headers)]) -> Vec<String> {
+    let mut duplicates: Vec<(u32, String)> = Vec::new();
+    for &(url, headers) in responses {
+        for duplicate in get_prioritized_duplicates(headers, url) {
+            if !duplicates.iter().any(|known| known.1 == duplicate.1) {
+                duplicates.push(duplicate);
+            }
+        }
+    }
+    duplicates.sort_by_key(|duplicate| duplicate.0);
+    duplicates.into_iter().map(|duplicate| duplicate.1).collect()
+}
+
+/// Returns the digests of the content advertised by the `Digest` headers - the unknown
+/// algorithms are ignored
+pub fn get_digests(headers: &Headers) -> Vec<ContentDigest> {
+    let mut digests = Vec::new();
+    for line in get_raw_values(headers, "Digest") {
+        for instance in split_unquoted(&line, ',') {
+            let mut instance = instance.splitn(2, '=');
+            let algorithm = match instance.next().unwrap_or("").parse() {
+                Ok(algorithm) => algorithm,
+                Err(_) => continue,
+            };
+            // MD5 and SHA digests are base64 encoded (RFC 3230)
+            if let Ok(value) = base64::decode(instance.next().unwrap_or("").trim()) {
+                let digest = ContentDigest { algorithm, value };
+                if !digests.contains(&digest) {
+                    digests.push(digest);
+                }
+            }
+        }
+    }
+    digests
+}
+
+/// Function to compute a digest of the whole content of a reader
+fn compute<D: Digest, R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
+    let mut hasher = D::new();
+    let mut bytes_buffer = vec![0; DIGEST_BUFFER_BYTES];
+    loop {
+        let n = reader.read(&mut bytes_buffer)?;
+        if n == 0 {
+            return Ok(hasher.result().to_vec());
+        }
+        hasher.input(&bytes_buffer[0..n]);
+    }
+}
+
+/// Compute the digest of the whole content of a reader, using the given algorithm
+pub fn compute_digest<R: Read>(algorithm: DigestAlgorithm, reader: R) -> io::Result<Vec<u8>> {
+    match algorithm {
+        DigestAlgorithm::Md5 => compute::<Md5, R>(reader),
+        DigestAlgorithm::Sha1 => compute::<Sha1, R>(reader),
+        DigestAlgorithm::Sha256 => compute::<Sha256, R>(reader),
+        DigestAlgorithm::Sha512 => compute::<Sha512, R>(reader),
+    }
+}
+
+/// Check the downloaded file against the strongest advertised digest.
+/// This function returns the algorithm that has been used, or None if no digest has been
+/// advertised.
+pub fn verify_file(path: &Path, digests: &[ContentDigest]) -> Result<Option<DigestAlgorithm>, DigestError> {
+    let digest = match digests.iter().max_by_key(|digest| digest.algorithm) {
+        Some(digest) => digest,
+        None => return Ok(None),
+    };
+    if compute_digest(digest.algorithm, File::open(path)?)? != digest.value {
+        return Err(DigestError::Mismatch(digest.algorithm));
+    }
+    Ok(Some(digest.algorithm))
+}
+
+#[cfg(test)]
+mod test_metalink {
+
+    use super::{compute_digest, get_digests, get_duplicates, ContentDigest, DigestAlgorithm};
+    use hyper::header::Headers;
+    use hyper::Url;
+
+    fn headers(name: &str, values: &[&str]) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw(
+            name.to_owned(),
+            values.iter().map(|value| value.as_bytes().to_vec()).collect(),
+        );
+        headers
+    }
+
+    #[test]
+    fn duplicates_should_be_sorted_by_priority() {
+        let url = Url::parse("http://download.example.org/pub/zou.bin").unwrap();
+        let headers = headers(
+            "Link",
+            &[
+                "<http://ftp.example.com/zou.bin>; rel=duplicate; pri=2; geo=de",
+                "<http://mirror.example.net/zou.bin>; rel=\"duplicate\"; pri=1, \
+                 <http://example.org/zou.bin.meta4>; rel=describedby; type=\"application/metalink4+xml\"",
+                "</other/zou.bin>; rel=duplicate",
+            ],
+        );
+        assert_eq!(
+            vec![
+                String::from("http://mirror.example.net/zou.bin"),
+                String::from("http://ftp.example.com/zou.bin"),
+                String::from("http://download.example.org/other/zou.bin"),
+            ],
+            get_duplicates(&[(&url, &headers)])
+        );
+    }
+
+    #[test]
+    fn unknown_digests_should_be_ignored() {
+        let headers = headers(
+            "Digest",
+            &["SHA-256=ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=, UNIXsum=30637"],
+        );
+        assert_eq!(
+            vec![ContentDigest {
+                algorithm: DigestAlgorithm::Sha256,
+                value: compute_digest(DigestAlgorithm::Sha256, &b"abc"[..]).unwrap(),
+            }],
+            get_digests(&headers)
+        );
+    }
+
+    #[test]
+    fn known_digests_should_be_computed() {
+        let sha256 = compute_digest(DigestAlgorithm::Sha256, &b"abc"[..]).unwrap();
+        assert_eq!(0xba, sha256[0]);
+        assert_eq!(0xad, sha256[31]);
+        let md5 = compute_digest(DigestAlgorithm::Md5, &b"abc"[..]).unwrap();
+        assert_eq!(0x90, md5[0]);
+        assert_eq!(0x72, md5[15]);
+    }
+
+}
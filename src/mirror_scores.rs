@@ -0,0 +1,306 @@
+//! Scores of the mirrors (throughput, latency and failures), kept between the runs in order to
+//! rank the mirrors without benchmarking them again each time.
+
+use cache::get_cache_file;
+use hyper::Url;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use Bytes;
+
+/// Name of the file that stores the scores of the mirrors, in the cache directory
+const MIRROR_SCORES_CACHE_FILE: &str = "mirrors";
+
+/// Default number of seconds after which a score is too old to rank a mirror (one week)
+pub const DEFAULT_SCORES_TTL_SECS: u64 = 7 * 24 * 3600;
+
+/// Weight of a new throughput measure, compared to the previous throughput of a mirror
+const THROUGHPUT_SMOOTHING: f64 = 0.5;
+
+/// Returns the current date, in seconds since the UNIX epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Returns the number of seconds of a duration
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+/// Returns the host (and port) of the given URL, used to identify a mirror
+fn get_host(url: &str) -> Option<String> {
+    let parsed_url = Url::parse(url).ok()?;
+    let host = parsed_url.host_str()?.to_lowercase();
+    match parsed_url.port_or_known_default() {
+        Some(port) => Some(format!("{}:{}", host, port)),
+        None => Some(host),
+    }
+}
+
+/// Score of a mirror
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MirrorScore {
+    /// Throughput of the mirror, in bytes per second
+    pub throughput: f64,
+    /// Time to first byte of the last benchmark, in milliseconds
+    pub ttfb_millis: u64,
+    /// Number of successful downloads (benchmarks and chunks)
+    pub successes: u64,
+    /// Number of failed downloads (benchmarks and chunks)
+    pub failures: u64,
+    /// Date of the last update, in seconds since the UNIX epoch
+    pub updated: u64,
+}
+
+impl MirrorScore {
+    /// Returns the rank of the mirror - the throughput, lowered by the failure rate of the
+    /// mirror
+    pub fn rank(&self) -> f64 {
+        self.throughput * (self.successes + 1) as f64 / (self.successes + self.failures + 1) as f64
+    }
+}
+
+/// Store of the scores of the mirrors, per host.
+/// The scores are updated by the benchmarks and by the downloads - if the store is backed by a
+/// file, the scores have to be saved explicitly, at the end of the run.
+pub struct MirrorScores {
+    scores: Mutex<HashMap<String, MirrorScore>>,
+    path: Option<PathBuf>,
+    ttl: u64,
+}
+
+impl MirrorScores {
+    /// Returns an empty store, that lives only in memory.
+    /// The scores older than `ttl` seconds are not used to rank the mirrors.
+    pub fn new(ttl: u64) -> MirrorScores {
+        MirrorScores {
+            scores: Mutex::new(HashMap::new()),
+            path: None,
+            ttl,
+        }
+    }
+
+    /// Returns the store saved in the given file - if the file does not exist yet, the store
+    /// is empty.
+    /// Each line of the file is
+    /// `<host> <update date> <throughput> <TTFB (ms)> <successes> <failures>`.
+    pub fn load(path: PathBuf, ttl: u64) -> io::Result<MirrorScores> {
+        let mut scores = HashMap::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    if fields.len() != 6 {
+                        continue;
+                    }
+                    let score = match (
+                        fields[1].parse(),
+                        fields[2].parse(),
+                        fields[3].parse(),
+                        fields[4].parse(),
+                        fields[5].parse(),
+                    ) {
+                        (Ok(updated), Ok(throughput), Ok(ttfb_millis), Ok(successes), Ok(failures)) => {
+                            MirrorScore {
+                                throughput,
+                                ttfb_millis,
+                                successes,
+                                failures,
+                                updated,
+                            }
+                        }
+                        _ => continue,
+                    };
+                    scores.insert(fields[0].to_owned(), score);
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(MirrorScores {
+            scores: Mutex::new(scores),
+            path: Some(path),
+            ttl,
+        })
+    }
+
+    /// Returns the store saved in the cache directory of zou
+    pub fn load_from_cache(ttl: u64) -> io::Result<MirrorScores> {
+        MirrorScores::load(get_cache_file(MIRROR_SCORES_CACHE_FILE)?, ttl)
+    }
+
+    /// Returns the score of the mirror that serves the given URL, if the score is recent
+    /// enough to rank the mirror
+    pub fn get(&self, url: &str) -> Option<MirrorScore> {
+        let host = get_host(url)?;
+        let scores = self.scores.lock().unwrap();
+        let score = scores.get(&host)?;
+        if now().saturating_sub(score.updated) > self.ttl {
+            return None;
+        }
+        Some(*score)
+    }
+
+    /// Update the score of the mirror that serves the given URL, and returns the new score.
+    /// This function returns None if the URL does not target any host (local files...).
+    fn update<F>(&self, url: &str, update: F) -> Option<MirrorScore>
+    where
+        F: FnOnce(&mut MirrorScore, bool),
+    {
+        let host = get_host(url)?;
+        let mut scores = self.scores.lock().unwrap();
+        let current_date = now();
+        let ttl = self.ttl;
+        let score = scores.entry(host).or_default();
+        let is_recent = score.updated != 0 && current_date.saturating_sub(score.updated) <= ttl;
+        update(score, is_recent);
+        score.updated = current_date;
+        Some(*score)
+    }
+
+    /// Record that `bytes` bytes have been downloaded from the given URL in `duration`.
+    /// The throughput of the mirror is smoothed using its previous throughput, if recent.
+    pub fn record_throughput(&self, url: &str, bytes: Bytes, duration: Duration) -> Option<MirrorScore> {
+        let seconds = as_secs_f64(duration);
+        let throughput = if seconds == 0. { bytes as f64 } else { bytes as f64 / seconds };
+        self.update(url, |score, is_recent| {
+            score.throughput = if is_recent && score.throughput > 0. {
+                THROUGHPUT_SMOOTHING * throughput + (1. - THROUGHPUT_SMOOTHING) * score.throughput
+            } else {
+                throughput
+            };
+            score.successes += 1;
+        })
+    }
+
+    /// Record the result of a benchmark of the given URL
+    pub fn record_bench(
+        &self,
+        url: &str,
+        bytes: Bytes,
+        duration: Duration,
+        ttfb: Duration,
+    ) -> Option<MirrorScore> {
+        self.record_throughput(url, bytes, duration)?;
+        let ttfb_millis = ttfb.as_secs() * 1000 + u64::from(ttfb.subsec_nanos()) / 1_000_000;
+        self.update(url, |score, _| score.ttfb_millis = ttfb_millis)
+    }
+
+    /// Record a failure of the mirror that serves the given URL
+    pub fn record_failure(&self, url: &str) -> Option<MirrorScore> {
+        self.update(url, |score, _| score.failures += 1)
+    }
+
+    /// Save the store in its file, if any
+    pub fn save(&self) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut file = File::create(path)?;
+        let scores = self.scores.lock().unwrap();
+        for (host, score) in scores.iter() {
+            writeln!(
+                file,
+                "{} {} {} {} {} {}",
+                host,
+                score.updated,
+                score.throughput as u64,
+                score.ttfb_millis,
+                score.successes,
+                score.failures
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for MirrorScores {
+    fn default() -> MirrorScores {
+        MirrorScores::new(DEFAULT_SCORES_TTL_SECS)
+    }
+}
+
+#[cfg(test)]
+mod test_mirror_scores {
+
+    use super::{get_host, MirrorScores};
+    use std::env;
+    use std::fs::{remove_file, File};
+    use std::io::Write;
+    use std::process;
+    use std::time::Duration;
+
+    #[test]
+    fn mirrors_should_be_identified_by_host_and_port() {
+        assert_eq!(
+            Some(String::from("mirror.org:80")),
+            get_host("http://Mirror.org/pub/zou.bin")
+        );
+        assert_eq!(
+            Some(String::from("mirror.org:8443")),
+            get_host("https://mirror.org:8443/zou.bin")
+        );
+        assert_eq!(None, get_host("file:///tmp/zou.bin"));
+    }
+
+    #[test]
+    fn throughput_should_be_smoothed() {
+        let scores = MirrorScores::default();
+        scores.record_throughput("http://mirror.org/a.bin", 1000, Duration::from_secs(1));
+        let score = scores
+            .record_throughput("http://mirror.org/b.bin", 3000, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(2000., score.throughput);
+        assert_eq!(2, score.successes);
+    }
+
+    #[test]
+    fn failures_should_lower_the_rank() {
+        let scores = MirrorScores::default();
+        scores.record_throughput("http://mirror.org/zou.bin", 1000, Duration::from_secs(1));
+        let score = scores.record_failure("http://mirror.org/zou.bin").unwrap();
+        assert!(score.rank() < score.throughput);
+    }
+
+    #[test]
+    fn old_scores_should_be_ignored() {
+        let path = env::temp_dir().join(format!("zou-old-mirrors-{}", process::id()));
+        File::create(&path)
+            .unwrap()
+            .write_all(b"mirror.org:80 1 1000 10 1 0\n")
+            .unwrap();
+        let scores = MirrorScores::load(path.clone(), 3600).unwrap();
+        remove_file(&path).unwrap();
+        assert!(scores.get("http://mirror.org/zou.bin").is_none());
+    }
+
+    #[test]
+    fn scores_should_be_persisted() {
+        let path = env::temp_dir().join(format!("zou-mirrors-{}", process::id()));
+        {
+            let scores = MirrorScores::load(path.clone(), 3600).unwrap();
+            scores.record_bench(
+                "http://mirror.org/zou.bin",
+                4000,
+                Duration::from_secs(2),
+                Duration::from_millis(120),
+            );
+            scores.save().unwrap();
+        }
+        let scores = MirrorScores::load(path.clone(), 3600).unwrap();
+        remove_file(&path).unwrap();
+        let score = scores.get("http://mirror.org/other.bin").unwrap();
+        assert_eq!(2000., score.throughput);
+        assert_eq!(120, score.ttfb_millis);
+    }
+
+}
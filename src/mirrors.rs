@@ -0,0 +1,181 @@
+use backend::{Backend, BackendRegistry};
+use mirror_scores::MirrorScores;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use Bytes;
+
+/// Number of failures after which a mirror is blacklisted, for the rest of the session
+pub const MAX_MIRROR_FAILURES: usize = 3;
+
+/// Returns the URL of the remote file, on the given mirror
+pub fn get_mirror_url(mirror: &str, filename: &str) -> Option<String> {
+    Path::new(mirror)
+        .join(filename)
+        .to_str()
+        .map(String::from)
+}
+
+/// A source of the remote content
+struct Mirror {
+    url: String,
+    backend: Arc<dyn Backend>,
+}
+
+/// Structure that contains the sources of the remote content, sorted from the best to the
+/// worst one, and that keeps track of their failures.
+/// A mirror that fails `MAX_MIRROR_FAILURES` times is blacklisted.
+/// The successes and the failures of the sources are recorded in the scores of the mirrors,
+/// if any.
+pub struct Mirrors {
+    mirrors: Vec<Mirror>,
+    failures: Mutex<Vec<usize>>,
+    scores: Option<Arc<MirrorScores>>,
+}
+
+impl Mirrors {
+    /// Returns the sources to use, given their URLs (the best one first).
+    /// The URLs that are not supported by the registry are ignored.
+    pub fn new(urls: Vec<String>, registry: &BackendRegistry) -> Mirrors {
+        Mirrors::with_scores(urls, registry, None)
+    }
+
+    /// Returns the sources to use, like `new`, recording their successes and failures in the
+    /// given scores
+    pub fn with_scores(
+        urls: Vec<String>,
+        registry: &BackendRegistry,
+        scores: Option<Arc<MirrorScores>>,
+    ) -> Mirrors {
+        let mut mirrors = Vec::with_capacity(urls.len());
+        for url in urls {
+            match registry.get(&url) {
+                Some(backend) => mirrors.push(Mirror { url, backend }),
+                None => warning!(&format!("Ignoring the mirror {}: unknown protocol", url)),
+            }
+        }
+        let failures = Mutex::new(vec![0; mirrors.len()]);
+        Mirrors {
+            mirrors,
+            failures,
+            scores,
+        }
+    }
+
+    /// Returns the number of sources, including the blacklisted ones
+    pub fn len(&self) -> usize {
+        self.mirrors.len()
+    }
+
+    /// Returns true if there is no source at all
+    pub fn is_empty(&self) -> bool {
+        self.mirrors.is_empty()
+    }
+
+    /// Returns the URL of the given source
+    pub fn url(&self, index: usize) -> &str {
+        &self.mirrors[index].url
+    }
+
+    /// Returns the backend to use to access the given source
+    pub fn backend(&self, index: usize) -> Arc<dyn Backend> {
+        self.mirrors[index].backend.clone()
+    }
+
+    /// Returns true if the given source failed too many times
+    pub fn is_blacklisted(&self, index: usize) -> bool {
+        self.failures.lock().unwrap()[index] >= MAX_MIRROR_FAILURES
+    }
+
+    /// Report a failure of the given source.
+    /// This function returns true if the source has just been blacklisted.
+    pub fn report_failure(&self, index: usize) -> bool {
+        if let Some(ref scores) = self.scores {
+            scores.record_failure(self.url(index));
+        }
+        let mut failures = self.failures.lock().unwrap();
+        failures[index] += 1;
+        failures[index] == MAX_MIRROR_FAILURES
+    }
+
+    /// Report that `bytes` bytes have been downloaded from the given source in `duration`
+    pub fn report_success(&self, index: usize, bytes: Bytes, duration: Duration) {
+        if let Some(ref scores) = self.scores {
+            scores.record_throughput(self.url(index), bytes, duration);
+        }
+    }
+
+    /// Returns the best source to use after a failure of the `current` one: the best source
+    /// that is not blacklisted, other than `current` - or `current` itself, if this is the
+    /// last one.
+    /// This function returns None if every source is blacklisted.
+    pub fn next(&self, current: usize) -> Option<usize> {
+        let failures = self.failures.lock().unwrap();
+        let is_available = |index: &usize| failures[*index] < MAX_MIRROR_FAILURES;
+        (0..self.mirrors.len())
+            .filter(|index| *index != current)
+            .find(&is_available)
+            .or_else(|| Some(current).filter(&is_available))
+    }
+}
+
+#[cfg(test)]
+mod test_mirrors {
+
+    use super::{get_mirror_url, Mirrors, MAX_MIRROR_FAILURES};
+    use backend::BackendRegistry;
+
+    fn get_mirrors() -> Mirrors {
+        Mirrors::new(
+            vec![
+                String::from("file:///first/zou.bin"),
+                String::from("file:///second/zou.bin"),
+                String::from("unknown://third/zou.bin"),
+            ],
+            &BackendRegistry::default(),
+        )
+    }
+
+    #[test]
+    fn unsupported_mirrors_should_be_ignored() {
+        assert_eq!(2, get_mirrors().len());
+    }
+
+    #[test]
+    fn next_mirror_should_be_the_best_other_one() {
+        let mirrors = get_mirrors();
+        assert_eq!(Some(1), mirrors.next(0));
+        assert_eq!(Some(0), mirrors.next(1));
+    }
+
+    #[test]
+    fn failing_mirror_should_be_blacklisted() {
+        let mirrors = get_mirrors();
+        for _ in 1..MAX_MIRROR_FAILURES {
+            assert!(!mirrors.report_failure(0));
+        }
+        assert!(mirrors.report_failure(0));
+        assert!(mirrors.is_blacklisted(0));
+        assert_eq!(Some(1), mirrors.next(0));
+        assert_eq!(Some(1), mirrors.next(1));
+    }
+
+    #[test]
+    fn no_mirror_should_be_available_if_all_are_blacklisted() {
+        let mirrors = get_mirrors();
+        for _ in 0..MAX_MIRROR_FAILURES {
+            mirrors.report_failure(0);
+            mirrors.report_failure(1);
+        }
+        assert_eq!(None, mirrors.next(0));
+    }
+
+    #[test]
+    fn mirror_url_should_target_the_file() {
+        assert_eq!(
+            Some(String::from("http://mirror.org/pub/zou.bin")),
+            get_mirror_url("http://mirror.org/pub", "zou.bin")
+        );
+    }
+
+}
@@ -0,0 +1,150 @@
+//! Temporary `.part` file: the remote content is downloaded next to the local path, and the
+//! complete file replaces the local path atomically - a previous version of the file stays
+//! untouched until the new one is complete.
+//! When a download fails, the temporary file is kept with the validators of the remote content
+//! (`<name>.part.validators`), so the next run can resume it.
+
+use backend::Validators;
+use std::ffi::OsString;
+use std::fs::{metadata, rename, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use timestamping::{load_validators, save_validators};
+use Bytes;
+
+/// Suffix of the temporary file
+pub const PART_FILE_SUFFIX: &str = ".part";
+
+/// Returns the path of the temporary file of the given local path (`<name>.part`)
+pub fn get_part_path(path: &Path) -> PathBuf {
+    let mut part_name = OsString::from(path.file_name().unwrap_or_default());
+    part_name.push(PART_FILE_SUFFIX);
+    path.with_file_name(part_name)
+}
+
+/// Make sure that the entries of the directory (i.e. a rename) are written on the disk
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(directory)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Replace the local path by the temporary file, atomically.
+/// The content of the temporary file has to be written on the disk before (see
+/// `OutputFileWriter::sync`).
+pub fn commit_part_file(part_path: &Path, path: &Path) -> io::Result<()> {
+    rename(part_path, path)?;
+    sync_directory(path)
+}
+
+/// Keep the temporary file of a failed download, in order to resume it: the temporary file is
+/// truncated to the bytes downloaded at its beginning, written on the disk, and the validators
+/// of the remote content are stored next to it.
+/// Without any validator, the temporary file cannot be resumed, as the remote content could
+/// have changed.
+pub fn keep_part_file(part_path: &Path, downloaded: Bytes, validators: &Validators) -> io::Result<()> {
+    let part_file = OpenOptions::new().write(true).open(part_path)?;
+    part_file.set_len(downloaded)?;
+    part_file.sync_all()?;
+    save_validators(part_path, validators)
+}
+
+/// Forget the validators of the temporary file, before writing in it: if the download is
+/// interrupted, the content of the temporary file is unknown.
+pub fn clear_part_validators(part_path: &Path) -> io::Result<()> {
+    save_validators(part_path, &Validators::default())
+}
+
+/// Returns the number of bytes of the temporary file that can be kept, to resume the download
+/// of the remote content described by the given validators.
+/// This function returns 0 if the temporary file has not been kept by a failed download, if the
+/// remote content has changed since then, or if the temporary file is longer than the remote
+/// content.
+pub fn get_part_resume_offset(
+    part_path: &Path,
+    content_length: Bytes,
+    validators: &Validators,
+) -> io::Result<Bytes> {
+    match load_validators(part_path)? {
+        Some(ref part_validators) if part_validators.is_unchanged(validators) => {}
+        _ => return Ok(0),
+    }
+    let part_length = match metadata(part_path) {
+        Ok(part_metadata) => part_metadata.len(),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    Ok(if part_length <= content_length { part_length } else { 0 })
+}
+
+#[cfg(test)]
+mod test_part {
+
+    use super::{clear_part_validators, commit_part_file, get_part_path, get_part_resume_offset,
+                keep_part_file};
+    use backend::Validators;
+    use hyper::header::EntityTag;
+    use std::env;
+    use std::fs::{remove_file, File};
+    use std::io::{Read, Write};
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    #[test]
+    fn part_file_should_be_next_to_the_local_path() {
+        assert_eq!(
+            PathBuf::from("/tmp/zou.tar.gz.part"),
+            get_part_path(Path::new("/tmp/zou.tar.gz"))
+        );
+        assert_eq!(PathBuf::from("zou.bin.part"), get_part_path(Path::new("zou.bin")));
+    }
+
+    #[test]
+    fn part_file_should_replace_the_previous_version() {
+        let path = env::temp_dir().join(format!("zou-commit-{}", process::id()));
+        let part_path = get_part_path(&path);
+        File::create(&path).unwrap().write_all(b"old").unwrap();
+        File::create(&part_path).unwrap().write_all(b"new").unwrap();
+
+        commit_part_file(&part_path, &path).unwrap();
+        let mut content = String::new();
+        File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+        remove_file(&path).unwrap();
+        assert_eq!("new", content);
+        assert!(!part_path.exists());
+    }
+
+    #[test]
+    fn kept_part_file_should_be_resumed_only_if_unchanged() {
+        let path = env::temp_dir().join(format!("zou-keep-{}", process::id()));
+        let part_path = get_part_path(&path);
+        let validators = Validators {
+            etag: Some(EntityTag::strong(String::from("zou"))),
+            last_modified: None,
+        };
+        File::create(&part_path).unwrap().write_all(b"downloaded").unwrap();
+        // Not kept by a failed download
+        assert_eq!(0, get_part_resume_offset(&part_path, 100, &validators).unwrap());
+
+        keep_part_file(&part_path, 4, &validators).unwrap();
+        assert_eq!(4, get_part_resume_offset(&part_path, 100, &validators).unwrap());
+        assert_eq!(0, get_part_resume_offset(&part_path, 3, &validators).unwrap());
+        let changed = Validators {
+            etag: Some(EntityTag::strong(String::from("uoz"))),
+            last_modified: None,
+        };
+        assert_eq!(0, get_part_resume_offset(&part_path, 100, &changed).unwrap());
+
+        clear_part_validators(&part_path).unwrap();
+        assert_eq!(0, get_part_resume_offset(&part_path, 100, &validators).unwrap());
+        remove_file(&part_path).unwrap();
+    }
+}
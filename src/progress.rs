@@ -0,0 +1,202 @@
+//! Progress bars of the workers: one line per worker, below a header, rendered on the standard
+//! output by a dedicated thread.
+//! The rendering thread returns once every bar has been dropped, so the download can wait for
+//! it before returning.
+
+use pbr::{ProgressBar, Units};
+use std::io;
+use std::io::Write;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use Bytes;
+
+/// New content of a line of the progress bars
+struct LineUpdate {
+    line: usize,
+    content: String,
+}
+
+/// Output of a progress bar: each write replaces the line of the bar
+pub struct BarOutput {
+    line: usize,
+    sender: Sender<LineUpdate>,
+}
+
+impl Write for BarOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // `ProgressBar` writes each drawing at once, and nothing when it finishes
+        if !buf.is_empty() {
+            let content = String::from_utf8_lossy(buf);
+            // The rendering thread is gone only if the output cannot be written anymore
+            let _ = self.sender.send(LineUpdate {
+                line: self.line,
+                content: content.trim_start_matches('\r').to_owned(),
+            });
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Progress bar of the chunks downloaded by a worker - a hidden bar does not draw anything
+pub struct ChunkBar {
+    bar: Option<ProgressBar<BarOutput>>,
+}
+
+impl ChunkBar {
+    /// Returns a bar that does not draw anything
+    pub fn hidden() -> ChunkBar {
+        ChunkBar { bar: None }
+    }
+
+    /// Start drawing the download of a new chunk, of `total` bytes
+    pub fn start(&mut self, total: Bytes, message: &str) {
+        if let Some(ref mut bar) = self.bar {
+            bar.total = total;
+            bar.message(message);
+            bar.reset_start_time();
+            bar.set(0);
+        }
+    }
+
+    /// Add downloaded bytes to the current chunk
+    pub fn add(&mut self, bytes: Bytes) {
+        if let Some(ref mut bar) = self.bar {
+            bar.add(bytes);
+        }
+    }
+
+    /// Set the number of downloaded bytes of the current chunk
+    pub fn set(&mut self, bytes: Bytes) {
+        if let Some(ref mut bar) = self.bar {
+            bar.set(bytes);
+        }
+    }
+
+    /// Change the message drawn before the bar
+    pub fn message(&mut self, message: &str) {
+        if let Some(ref mut bar) = self.bar {
+            bar.message(message);
+        }
+    }
+
+    /// Draw the bar a last time - the bar stops drawing once dropped
+    pub fn finish(mut self) {
+        if let Some(ref mut bar) = self.bar {
+            bar.finish();
+        }
+    }
+}
+
+/// Lines of progress bars, below a header
+pub struct ProgressBars {
+    lines: Vec<String>,
+    sender: Sender<LineUpdate>,
+    receiver: Receiver<LineUpdate>,
+}
+
+impl ProgressBars {
+    /// Returns progress bars drawn below the given header
+    pub fn new(header: &str) -> ProgressBars {
+        let (sender, receiver) = channel();
+        ProgressBars {
+            lines: vec![String::from(header)],
+            sender,
+            receiver,
+        }
+    }
+
+    /// Returns a new progress bar of bytes, drawn in the next line
+    pub fn create_bar(&mut self) -> ChunkBar {
+        self.lines.push(String::new());
+        let output = BarOutput {
+            line: self.lines.len() - 1,
+            sender: self.sender.clone(),
+        };
+        let mut bar = ProgressBar::on(output, 0);
+        bar.tick_format("\\|/-");
+        bar.format("|#--|");
+        bar.show_tick = true;
+        bar.show_speed = true;
+        bar.show_percent = true;
+        bar.show_counter = false;
+        bar.show_time_left = true;
+        bar.set_units(Units::Bytes);
+        ChunkBar { bar: Some(bar) }
+    }
+
+    /// Draw the lines on the given output from a new thread, each time a bar changes.
+    /// The thread returns once every bar created before has been dropped.
+    pub fn render<W: Write + Send + 'static>(self, mut output: W) -> JoinHandle<()> {
+        let ProgressBars {
+            mut lines,
+            sender,
+            receiver,
+        } = self;
+        drop(sender);
+        thread::spawn(move || {
+            let mut is_drawn = false;
+            for update in receiver {
+                lines[update.line] = update.content;
+                let mut out = String::new();
+                // Draw over the previous lines
+                if is_drawn {
+                    out.push_str(&format!("\x1B[{}A", lines.len()));
+                }
+                for line in &lines {
+                    out.push_str(&format!("\r{}\n", line));
+                }
+                // The progress is not worth failing the download
+                let _ = output.write_all(out.as_bytes()).and_then(|_| output.flush());
+                is_drawn = true;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_progress {
+
+    use super::ProgressBars;
+    use std::io;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Output shared with the test, once the rendering thread returned
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rendering_should_return_once_every_bar_is_dropped() {
+        let mut progress = ProgressBars::new("Downloading 2 chunk(s)");
+        let bars = vec![progress.create_bar(), progress.create_bar()];
+        let output = SharedOutput::default();
+        let handle = progress.render(output.clone());
+        for (index, mut bar) in bars.into_iter().enumerate() {
+            bar.start(100, &format!("Chunk {} ", index));
+            bar.add(100);
+            bar.finish();
+        }
+        handle.join().unwrap();
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("\rDownloading 2 chunk(s)\n"));
+        assert!(output.contains("Chunk 0 "));
+        assert!(output.contains("Chunk 1 "));
+    }
+}
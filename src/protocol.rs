@@ -1,23 +1,52 @@
 use hyper::Url;
+use std::path::PathBuf;
 
-/// Supported protocols
-#[derive(Debug)]
-pub enum Protocol {
-    HTTP,
-    HTTPS,
-}
-
-/// Returns an Option type, that contains a Protocol enum
-pub fn get_protocol(url: &str) -> Option<Protocol> {
+/// Returns an Option type, that contains the scheme of the URL (in lowercase)
+pub fn get_scheme(url: &str) -> Option<String> {
     match Url::parse(url) {
-        Ok(url) => match url.scheme() {
-            "http" => Some(Protocol::HTTP),
-            "https" => Some(Protocol::HTTPS),
-            _ => None,
-        },
+        Ok(url) => Some(url.scheme().to_lowercase()),
         Err(error) => {
             warning!(&format!("Error extracting the protocol: {}", error));
             None
         }
     }
-}
\ No newline at end of file
+}
+
+/// Returns the local path targeted by a `file://` URL, if any
+pub fn get_local_path(url: &str) -> Option<PathBuf> {
+    match Url::parse(url) {
+        Ok(ref url) if url.scheme() == "file" => url.to_file_path().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_protocol {
+
+    use super::{get_local_path, get_scheme};
+    use std::path::PathBuf;
+
+    #[test]
+    fn file_url_should_return_the_file_scheme() {
+        assert_eq!(Some(String::from("file")), get_scheme("file:///tmp/zou.bin"));
+    }
+
+    #[test]
+    fn scheme_should_be_in_lowercase() {
+        assert_eq!(Some(String::from("https")), get_scheme("HTTPS://localhost/zou.bin"));
+    }
+
+    #[test]
+    fn file_url_should_return_its_local_path() {
+        assert_eq!(
+            Some(PathBuf::from("/tmp/zou.bin")),
+            get_local_path("file:///tmp/zou.bin")
+        );
+    }
+
+    #[test]
+    fn http_url_should_not_return_a_local_path() {
+        assert_eq!(None, get_local_path("http://localhost/zou.bin"));
+    }
+
+}
@@ -0,0 +1,344 @@
+//! In-order stream of a remote content: several workers fetch the next chunks ahead, the
+//! chunks are kept in a bounded reorder buffer, and the stream reads them in order.
+//! The workers wait while the buffer is full, so the memory used by the stream is capped.
+
+use authorization::AuthorizationHeaderFactory;
+use backend::{Backend, BackendError, BackendRegistry, BackendResult, RangeRequest, Validators};
+use cargo_helper::RemoteServerInformations;
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{Cursor, Read};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use {Bytes, RangeBytes};
+
+/// Default length of each chunk of a stream (4 MiB)
+pub const DEFAULT_STREAM_CHUNK_SIZE: Bytes = 4 * 1_048_576;
+
+/// Number of attempts to download a chunk, before failing the stream
+const MAX_CHUNK_ATTEMPTS: usize = 3;
+
+/// Describes how a stream fetches the remote content
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamConfig {
+    /// Number of workers, i.e. chunks downloaded at the same time
+    pub workers: usize,
+    /// Length of each chunk
+    pub chunk_size: Bytes,
+    /// Maximum number of chunks kept in memory, including the chunks being downloaded and the
+    /// chunk being read
+    pub max_buffered_chunks: usize,
+}
+
+impl StreamConfig {
+    /// Returns a configuration that uses the given number of workers, each one fetching up to
+    /// two chunks ahead
+    pub fn new(workers: usize) -> StreamConfig {
+        StreamConfig {
+            workers,
+            chunk_size: DEFAULT_STREAM_CHUNK_SIZE,
+            max_buffered_chunks: 2 * max(workers, 1),
+        }
+    }
+
+    /// Returns the maximum number of bytes kept in memory by the stream
+    pub fn get_max_buffered_bytes(&self) -> Bytes {
+        max(self.max_buffered_chunks, 1) as Bytes * max(self.chunk_size, 1)
+    }
+}
+
+/// Everything the workers need to download the chunks of the remote content
+struct ChunkSource {
+    backend: Arc<dyn Backend>,
+    url: String,
+    auth: Option<AuthorizationHeaderFactory>,
+    validators: Validators,
+    content_length: Bytes,
+    chunk_length: Bytes,
+    nb_chunks: u64,
+}
+
+impl ChunkSource {
+    /// Returns the range of the given chunk
+    fn get_range(&self, index: u64) -> RangeBytes {
+        let begin = index * self.chunk_length;
+        RangeBytes(begin, min(begin + self.chunk_length, self.content_length) - 1)
+    }
+
+    /// Download a whole chunk in memory.
+    /// If the connection fails, the remaining part of the chunk is requested again, up to
+    /// `MAX_CHUNK_ATTEMPTS` times - the stream fails at once if the remote content has changed.
+    fn fetch(&self, index: u64) -> BackendResult<Vec<u8>> {
+        let range = self.get_range(index);
+        let mut chunk = Vec::with_capacity(range.length() as usize);
+        let mut attempts = 0;
+
+        while (chunk.len() as Bytes) < range.length() {
+            let request = RangeRequest {
+                url: &self.url,
+                range: RangeBytes(range.0 + chunk.len() as Bytes, range.1),
+                auth: self.auth.as_ref(),
+                partial: true,
+                validators: Some(&self.validators),
+            };
+            let received = chunk.len() as Bytes;
+            // The bytes read before an error are kept in the chunk
+            let error = match self.backend.open_range(&request).and_then(|body| {
+                body.take(request.range.length())
+                    .read_to_end(&mut chunk)
+                    .map_err(BackendError::Io)
+            }) {
+                Ok(_) if chunk.len() as Bytes == range.length() => break,
+                Ok(_) => BackendError::Truncated {
+                    expected: range.length(),
+                    received: chunk.len() as Bytes,
+                },
+                Err(BackendError::ContentChanged) => return Err(BackendError::ContentChanged),
+                Err(err) => err,
+            };
+            // An attempt that made some progress is not a failure
+            if chunk.len() as Bytes == received {
+                attempts += 1;
+            }
+            if attempts >= MAX_CHUNK_ATTEMPTS {
+                return Err(error);
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+/// State shared by the workers and the stream
+struct BufferState {
+    /// The downloaded chunks that have not been read yet, by index
+    chunks: BTreeMap<u64, Vec<u8>>,
+    /// Index of the next chunk to download
+    next_chunk: u64,
+    /// Index of the chunk being read
+    next_read: u64,
+    /// The first error of the workers, if any
+    error: Option<BackendError>,
+    /// True if the stream has been dropped
+    cancelled: bool,
+}
+
+/// The reorder buffer, and a condition variable notified on every change of its state
+struct ReorderBuffer {
+    state: Mutex<BufferState>,
+    changed: Condvar,
+}
+
+/// Download the chunks one after the other, while the reorder buffer is not full
+fn run_worker(source: &ChunkSource, buffer: &ReorderBuffer, max_buffered_chunks: u64) {
+    loop {
+        let index = {
+            let mut state = buffer.state.lock().unwrap();
+            loop {
+                if state.cancelled || state.error.is_some() || state.next_chunk >= source.nb_chunks {
+                    return;
+                }
+                // Back-pressure: wait for the stream to read the oldest chunks
+                if state.next_chunk < state.next_read + max_buffered_chunks {
+                    break;
+                }
+                state = buffer.changed.wait(state).unwrap();
+            }
+            state.next_chunk += 1;
+            state.next_chunk - 1
+        };
+
+        let result = source.fetch(index);
+        let mut state = buffer.state.lock().unwrap();
+        match result {
+            Ok(chunk) => {
+                state.chunks.insert(index, chunk);
+            }
+            Err(err) => {
+                if state.error.is_none() {
+                    state.error = Some(err);
+                }
+            }
+        }
+        buffer.changed.notify_all();
+    }
+}
+
+/// The ways to read the remote content
+enum StreamSource {
+    /// Without ranges support, the body of a single request is read
+    Direct {
+        body: Box<dyn Read + Send>,
+        remaining: Bytes,
+    },
+    /// The chunks are fetched by the workers, and read in order from the reorder buffer
+    Chunked {
+        buffer: Arc<ReorderBuffer>,
+        nb_chunks: u64,
+        chunk: Option<Cursor<Vec<u8>>>,
+    },
+}
+
+/// Stream of a remote content, read in order (see `open_stream`).
+/// The workers stop when the stream is dropped.
+pub struct DownloadStream {
+    source: StreamSource,
+}
+
+impl Read for DownloadStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.source {
+            StreamSource::Direct {
+                ref mut body,
+                ref mut remaining,
+            } => {
+                let n = body.read(buf)?;
+                if n == 0 && *remaining > 0 && !buf.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the connection has been closed too early",
+                    ));
+                }
+                *remaining -= n as Bytes;
+                Ok(n)
+            }
+            StreamSource::Chunked {
+                ref buffer,
+                nb_chunks,
+                ref mut chunk,
+            } => loop {
+                if let Some(ref mut current) = *chunk {
+                    let n = current.read(buf)?;
+                    if n > 0 || buf.is_empty() {
+                        return Ok(n);
+                    }
+                }
+                let mut state = buffer.state.lock().unwrap();
+                // The current chunk has been read: its place in the buffer is free
+                if chunk.take().is_some() {
+                    state.next_read += 1;
+                    buffer.changed.notify_all();
+                }
+                if state.next_read >= nb_chunks {
+                    return Ok(0);
+                }
+                loop {
+                    let next_read = state.next_read;
+                    if let Some(next_chunk) = state.chunks.remove(&next_read) {
+                        *chunk = Some(Cursor::new(next_chunk));
+                        break;
+                    }
+                    if let Some(ref err) = state.error {
+                        return Err(io::Error::other(err.to_string()));
+                    }
+                    state = buffer.changed.wait(state).unwrap();
+                }
+            },
+        }
+    }
+}
+
+impl Drop for DownloadStream {
+    fn drop(&mut self) {
+        if let StreamSource::Chunked { ref buffer, .. } = self.source {
+            buffer.state.lock().unwrap().cancelled = true;
+            buffer.changed.notify_all();
+        }
+    }
+}
+
+/// Function to open an in-order stream of a remote content.
+/// If the remote server accepts ranges, the chunks are fetched ahead by `config.workers`
+/// threads, and at most `config.max_buffered_chunks` chunks are kept in memory; otherwise, the
+/// stream reads the body of a single request.
+/// The validators of the remote content are pinned: the stream fails if the remote content
+/// changes.
+pub fn open_stream(
+    cargo_info: &RemoteServerInformations,
+    registry: &BackendRegistry,
+    config: StreamConfig,
+) -> BackendResult<DownloadStream> {
+    let backend = registry.get(cargo_info.url).ok_or_else(|| {
+        BackendError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no backend is available for {}", cargo_info.url),
+        ))
+    })?;
+    let content_length = cargo_info.file.content_length;
+    let validators = cargo_info.file.get_validators();
+
+    if content_length == 0 {
+        return Ok(DownloadStream {
+            source: StreamSource::Direct {
+                body: Box::new(io::empty()),
+                remaining: 0,
+            },
+        });
+    }
+    if !cargo_info.accept_partialcontent {
+        let request = RangeRequest {
+            url: cargo_info.url,
+            range: RangeBytes(0, content_length - 1),
+            auth: cargo_info.auth_header.as_ref(),
+            partial: false,
+            validators: Some(&validators),
+        };
+        let body = backend.open_range(&request)?.take(content_length);
+        return Ok(DownloadStream {
+            source: StreamSource::Direct {
+                body: Box::new(body),
+                remaining: content_length,
+            },
+        });
+    }
+
+    let chunk_length = max(config.chunk_size, 1);
+    let nb_chunks = content_length.div_ceil(chunk_length);
+    let source = Arc::new(ChunkSource {
+        backend,
+        url: String::from(cargo_info.url),
+        auth: cargo_info.auth_header.clone(),
+        validators,
+        content_length,
+        chunk_length,
+        nb_chunks,
+    });
+    let buffer = Arc::new(ReorderBuffer {
+        state: Mutex::new(BufferState {
+            chunks: BTreeMap::new(),
+            next_chunk: 0,
+            next_read: 0,
+            error: None,
+            cancelled: false,
+        }),
+        changed: Condvar::new(),
+    });
+    let max_buffered_chunks = max(config.max_buffered_chunks, 1) as u64;
+    let workers = max(min(config.workers as u64, nb_chunks), 1);
+    for _ in 0..workers {
+        let (source, buffer) = (source.clone(), buffer.clone());
+        thread::spawn(move || run_worker(&source, &buffer, max_buffered_chunks));
+    }
+
+    Ok(DownloadStream {
+        source: StreamSource::Chunked {
+            buffer,
+            nb_chunks,
+            chunk: None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test_stream {
+
+    use super::{StreamConfig, DEFAULT_STREAM_CHUNK_SIZE};
+
+    #[test]
+    fn stream_memory_should_be_capped() {
+        let config = StreamConfig::new(4);
+        assert_eq!(8, config.max_buffered_chunks);
+        assert_eq!(8 * DEFAULT_STREAM_CHUNK_SIZE, config.get_max_buffered_bytes());
+        assert_eq!(2, StreamConfig::new(0).max_buffered_chunks);
+    }
+}
@@ -0,0 +1,251 @@
+//! Timestamping: the local file keeps the last modification date of the remote content, and
+//! the validators of the remote content (entity tag, last modification date) are stored next to
+//! the local file, in order to download it again only if it has been modified.
+
+use backend::Validators;
+use hyper::header::{EntityTag, HttpDate};
+use std::ffi::OsString;
+use std::fs::{remove_file, File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time;
+use time::Timespec;
+
+/// Suffix of the file that stores the validators of a local file
+pub const VALIDATORS_FILE_SUFFIX: &str = ".validators";
+
+const ETAG_FIELD: &str = "ETag";
+const LAST_MODIFIED_FIELD: &str = "Last-Modified";
+
+/// Returns the HTTP date of the given time, if it can be represented
+pub fn get_http_date(date: SystemTime) -> Option<HttpDate> {
+    let elapsed = date.duration_since(UNIX_EPOCH).ok()?;
+    let timespec = Timespec::new(elapsed.as_secs() as i64, 0);
+    Some(HttpDate(time::at_utc(timespec)))
+}
+
+/// Returns the time of the given HTTP date, if it is after the UNIX epoch
+pub fn get_system_time(date: &HttpDate) -> Option<SystemTime> {
+    let timespec = date.0.to_timespec();
+    if timespec.sec < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(timespec.sec as u64))
+}
+
+/// Returns the path of the file that stores the validators of the given local path
+/// (`<name>.validators`)
+pub fn get_validators_path(path: &Path) -> PathBuf {
+    let mut validators_name = OsString::from(path.file_name().unwrap_or_default());
+    validators_name.push(VALIDATORS_FILE_SUFFIX);
+    path.with_file_name(validators_name)
+}
+
+/// Store the validators of the remote content next to the local path.
+/// If there is no validator, the previous validators file (if any) is removed.
+pub fn save_validators(path: &Path, validators: &Validators) -> io::Result<()> {
+    let validators_path = get_validators_path(path);
+    if validators.is_empty() {
+        return match remove_file(&validators_path) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        };
+    }
+    let mut validators_file = File::create(&validators_path)?;
+    if let Some(ref etag) = validators.etag {
+        writeln!(validators_file, "{}: {}", ETAG_FIELD, etag)?;
+    }
+    if let Some(date) = validators.last_modified.and_then(get_http_date) {
+        writeln!(validators_file, "{}: {}", LAST_MODIFIED_FIELD, date)?;
+    }
+    Ok(())
+}
+
+/// Returns the validators stored next to the local path.
+/// This function returns None if there is no validators file; the unknown or invalid fields
+/// are ignored.
+pub fn load_validators(path: &Path) -> io::Result<Option<Validators>> {
+    let validators_file = match File::open(get_validators_path(path)) {
+        Ok(validators_file) => validators_file,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mut validators = Validators::default();
+    for line in BufReader::new(validators_file).lines() {
+        let line = line?;
+        let (name, value) = match line.find(':') {
+            Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+            None => continue,
+        };
+        if name.eq_ignore_ascii_case(ETAG_FIELD) {
+            validators.etag = value.parse::<EntityTag>().ok();
+        } else if name.eq_ignore_ascii_case(LAST_MODIFIED_FIELD) {
+            validators.last_modified = value
+                .parse::<HttpDate>()
+                .ok()
+                .and_then(|date| get_system_time(&date));
+        }
+    }
+    Ok(Some(validators))
+}
+
+/// Returns the validators of the local file: the stored validators if any, otherwise the last
+/// modification date of the local file
+pub fn get_local_validators(path: &Path) -> io::Result<Validators> {
+    if let Some(validators) = load_validators(path)? {
+        return Ok(validators);
+    }
+    Ok(Validators {
+        etag: None,
+        last_modified: Some(path.metadata()?.modified()?),
+    })
+}
+
+/// Set the last modification date of the local file
+pub fn set_last_modified(path: &Path, last_modified: SystemTime) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_modified(last_modified)
+}
+
+#[cfg(test)]
+mod test_timestamping {
+
+    use super::{get_http_date, get_local_validators, get_system_time, get_validators_path,
+                load_validators, save_validators, set_last_modified};
+    use backend::{Probe, Validators};
+    use hyper::header::EntityTag;
+    use std::env;
+    use std::fs::{remove_file, File};
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn validators_file_should_be_next_to_the_local_path() {
+        assert_eq!(
+            PathBuf::from("/tmp/zou.tar.gz.validators"),
+            get_validators_path(Path::new("/tmp/zou.tar.gz"))
+        );
+    }
+
+    #[test]
+    fn http_date_should_keep_the_seconds() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let http_date = get_http_date(date).unwrap();
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", http_date.to_string());
+        assert_eq!(Some(date), get_system_time(&http_date));
+    }
+
+    #[test]
+    fn validators_should_be_saved_and_loaded() {
+        let path = env::temp_dir().join(format!("zou-validators-{}", process::id()));
+        let validators = Validators {
+            etag: Some(EntityTag::weak(String::from("zou"))),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(784_111_777)),
+        };
+        assert_eq!(None, load_validators(&path).unwrap());
+
+        save_validators(&path, &validators).unwrap();
+        let loaded_validators = load_validators(&path).unwrap();
+        save_validators(&path, &Validators::default()).unwrap();
+        assert_eq!(Some(validators), loaded_validators);
+        assert!(!get_validators_path(&path).exists());
+    }
+
+    #[test]
+    fn local_validators_should_fall_back_to_the_local_date() {
+        let path = env::temp_dir().join(format!("zou-local-validators-{}", process::id()));
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        File::create(&path).unwrap();
+        set_last_modified(&path, date).unwrap();
+        let validators = get_local_validators(&path).unwrap();
+        remove_file(&path).unwrap();
+        assert_eq!(None, validators.etag);
+        assert_eq!(Some(date), validators.last_modified);
+    }
+
+    #[test]
+    fn unmodified_content_should_match_the_validators() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let validators = Validators {
+            etag: Some(EntityTag::strong(String::from("zou"))),
+            last_modified: Some(date),
+        };
+        let mut probe = Probe {
+            etag: Some(EntityTag::weak(String::from("zou"))),
+            last_modified: Some(date + Duration::from_secs(60)),
+            ..Probe::default()
+        };
+        // The entity tags are compared first
+        assert!(validators.is_not_modified(&Validators::from_probe(&probe)));
+        probe.etag = Some(EntityTag::strong(String::from("uoz")));
+        assert!(!validators.is_not_modified(&Validators::from_probe(&probe)));
+        // Then the last modification dates
+        probe.etag = None;
+        assert!(!validators.is_not_modified(&Validators::from_probe(&probe)));
+        probe.last_modified = Some(date + Duration::from_millis(500));
+        assert!(validators.is_not_modified(&Validators::from_probe(&probe)));
+        assert!(!Validators::default().is_not_modified(&Validators::from_probe(&probe)));
+    }
+
+    #[test]
+    fn changed_content_should_not_match_the_validators() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let validators = Validators {
+            etag: Some(EntityTag::strong(String::from("zou"))),
+            last_modified: Some(date),
+        };
+        let mut current = validators.clone();
+        assert!(!validators.is_changed(&current));
+        current.etag = Some(EntityTag::strong(String::from("uoz")));
+        assert!(validators.is_changed(&current));
+        // A newer or an older date is a change
+        current.etag = None;
+        current.last_modified = Some(date - Duration::from_secs(60));
+        assert!(validators.is_changed(&current));
+        // Without comparable validators, the content is assumed unchanged
+        current.last_modified = None;
+        assert!(!validators.is_changed(&current));
+        assert!(!Validators::default().is_changed(&validators));
+    }
+
+    #[test]
+    fn uncomparable_validators_should_not_be_unchanged() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let validators = Validators {
+            etag: Some(EntityTag::weak(String::from("zou"))),
+            last_modified: Some(date),
+        };
+        assert!(validators.is_unchanged(&validators.clone()));
+        // Without comparable validators, the content cannot be assumed unchanged
+        let current = Validators {
+            etag: validators.etag.clone(),
+            last_modified: None,
+        };
+        assert!(!validators.is_unchanged(&current));
+        assert!(!Validators::default().is_unchanged(&validators));
+    }
+
+    #[test]
+    fn weak_etags_should_not_pin_the_content() {
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let validators = Validators {
+            etag: Some(EntityTag::weak(String::from("x"))),
+            last_modified: Some(date),
+        };
+        // The same weak entity tag does not mean the same bytes: the dates are compared
+        let mut current = validators.clone();
+        assert!(!validators.is_changed(&current));
+        current.last_modified = Some(date + Duration::from_secs(60));
+        assert!(validators.is_changed(&current));
+        // A strong entity tag is not comparable with a weak one
+        current.etag = Some(EntityTag::strong(String::from("x")));
+        assert!(validators.is_changed(&current));
+        current.last_modified = None;
+        assert!(!validators.is_changed(&current));
+    }
+}
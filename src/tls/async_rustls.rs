@@ -0,0 +1,185 @@
+//! TLS settings of the asynchronous HTTP backend, which always uses rustls - whatever the TLS
+//! implementation of the other clients.
+
+use rustls_async::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls_async::crypto::ring;
+use rustls_async::version::TLS13;
+use rustls_async::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+                   SupportedProtocolVersion, ALL_VERSIONS};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use std::path::Path;
+use std::sync::Arc;
+use tls::{list_ca_path, read_file, ClientCertificate, TlsConfig, TlsError, TlsVersion};
+use webpki_roots_async::TLS_SERVER_ROOTS;
+
+/// Certificate verifier that accepts every certificate - used only by `--insecure`
+#[derive(Debug)]
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, ::rustls_async::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, ::rustls_async::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, ::rustls_async::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Trust every certificate authority stored in a PEM file.
+/// If `ignore_invalid` is true, files that do not contain any certificate are ignored.
+fn add_ca_file(roots: &mut RootCertStore, ca_file: &Path, ignore_invalid: bool) -> Result<(), TlsError> {
+    let content = read_file(ca_file)?;
+    let certificates = CertificateDer::pem_slice_iter(&content).filter_map(Result::ok);
+    let (valid, _) = roots.add_parsable_certificates(certificates);
+    if valid == 0 && !ignore_invalid {
+        return Err(TlsError::InvalidFile(ca_file.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Returns the PEM client certificate (and its chain) and its private key
+fn get_client_cert(
+    client_cert: &ClientCertificate,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), TlsError> {
+    let (cert, key) = match *client_cert {
+        ClientCertificate::Pem { ref cert, ref key } => (cert, key),
+        ClientCertificate::Pkcs12 { .. } => {
+            return Err(TlsError::Unsupported("PKCS#12 client certificate (with rustls)"))
+        }
+    };
+    let cert_chain: Vec<CertificateDer<'static>> = CertificateDer::pem_slice_iter(&read_file(cert)?)
+        .filter_map(Result::ok)
+        .collect();
+    if cert_chain.is_empty() {
+        return Err(TlsError::InvalidFile(cert.clone()));
+    }
+    let key_der = PrivateKeyDer::from_pem_slice(&read_file(key)?)
+        .map_err(|_| TlsError::InvalidFile(key.clone()))?;
+    Ok((cert_chain, key_der))
+}
+
+/// TLS versions to accept when the minimum version is TLS 1.3
+static TLS13_ONLY: &[&SupportedProtocolVersion] = &[&TLS13];
+
+/// Returns the TLS versions to accept - rustls does not support versions older than TLS 1.2
+fn get_versions(min_version: Option<TlsVersion>) -> &'static [&'static SupportedProtocolVersion] {
+    match min_version {
+        Some(min_version) if min_version > TlsVersion::Tls12 => TLS13_ONLY,
+        _ => ALL_VERSIONS,
+    }
+}
+
+/// Returns the rustls configuration of the asynchronous HTTP backend, using the TLS settings.
+/// The application protocols (ALPN) are set by the HTTPS connector.
+pub fn get_async_tls_config(config: &TlsConfig) -> Result<ClientConfig, TlsError> {
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_protocol_versions(get_versions(config.min_version))?;
+    let builder = if config.insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification))
+    } else {
+        let mut roots = RootCertStore::empty();
+        roots.extend(TLS_SERVER_ROOTS.iter().cloned());
+        if let Some(ref ca_file) = config.ca_file {
+            add_ca_file(&mut roots, ca_file, false)?;
+        }
+        if let Some(ref ca_path) = config.ca_path {
+            for path in list_ca_path(ca_path)? {
+                // Files that are not PEM certificates (hash links, READMEs...) are ignored
+                add_ca_file(&mut roots, &path, true)?;
+            }
+        }
+        builder.with_root_certificates(roots)
+    };
+    match config.client_cert {
+        Some(ref client_cert) => {
+            let (cert_chain, key_der) = get_client_cert(client_cert)?;
+            Ok(builder.with_client_auth_cert(cert_chain, key_der)?)
+        }
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+#[cfg(test)]
+mod test_async_rustls_config {
+
+    use super::{get_async_tls_config, get_versions};
+    use rustls_async::version::{TLS12, TLS13};
+    use std::path::PathBuf;
+    use tls::{ClientCertificate, TlsConfig, TlsError, TlsVersion};
+
+    #[test]
+    fn default_config_should_build_a_client() {
+        assert!(get_async_tls_config(&TlsConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn missing_ca_file_should_be_an_error() {
+        let config = TlsConfig {
+            ca_file: Some(PathBuf::from("/this/file/does/not/exist.pem")),
+            ..TlsConfig::default()
+        };
+        match get_async_tls_config(&config) {
+            Err(TlsError::Io(_, _)) => {}
+            _ => panic!("a missing CA file should be an I/O error"),
+        }
+    }
+
+    #[test]
+    fn pkcs12_should_be_unsupported() {
+        let config = TlsConfig {
+            client_cert: Some(ClientCertificate::Pkcs12 {
+                archive: PathBuf::from("client.p12"),
+                password: String::new(),
+            }),
+            ..TlsConfig::default()
+        };
+        match get_async_tls_config(&config) {
+            Err(TlsError::Unsupported(_)) => {}
+            _ => panic!("PKCS#12 archives should not be supported"),
+        }
+    }
+
+    #[test]
+    fn old_versions_should_fallback_to_tls12() {
+        assert_eq!(2, get_versions(Some(TlsVersion::Tls10)).len());
+        assert_eq!(TLS12.version, get_versions(None)[1].version);
+        assert_eq!(
+            vec![TLS13.version],
+            get_versions(Some(TlsVersion::Tls13))
+                .iter()
+                .map(|version| version.version)
+                .collect::<Vec<_>>()
+        );
+    }
+}
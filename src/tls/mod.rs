@@ -0,0 +1,232 @@
+//! TLS settings, shared by every TLS implementation.
+//! The TLS implementation is selected at build time, using the cargo features `tls-openssl`
+//! (default), `tls-rustls` or `no-tls` - if both `tls-openssl` and `tls-rustls` are enabled,
+//! OpenSSL is used.
+//! The asynchronous HTTP backend (cargo feature `async-http`) always uses rustls.
+
+#[cfg(feature = "tls-openssl")]
+use openssl::error::ErrorStack;
+#[cfg(feature = "tls-rustls")]
+use rustls::TLSError;
+#[cfg(feature = "async-http")]
+use rustls_async;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[cfg(feature = "async-http")]
+mod async_rustls;
+#[cfg(feature = "tls-openssl")]
+mod openssl;
+#[cfg(all(feature = "tls-rustls", not(feature = "tls-openssl")))]
+mod rustls;
+
+#[cfg(feature = "async-http")]
+pub use self::async_rustls::get_async_tls_config;
+#[cfg(feature = "tls-openssl")]
+pub use self::openssl::{get_tls_client, TlsClient};
+#[cfg(all(feature = "tls-rustls", not(feature = "tls-openssl")))]
+pub use self::rustls::{get_tls_client, TlsClient};
+
+#[cfg(all(feature = "no-tls", any(feature = "tls-openssl", feature = "tls-rustls")))]
+compile_error!("the `no-tls` feature cannot be enabled with `tls-openssl` or `tls-rustls`");
+
+/// Returns true if zou has been built with a TLS implementation
+pub fn is_supported() -> bool {
+    cfg!(any(feature = "tls-openssl", feature = "tls-rustls", feature = "async-http"))
+}
+
+/// Supported TLS versions
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+    Tls13,
+}
+
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    fn from_str(version: &str) -> Result<TlsVersion, String> {
+        match version {
+            "1.0" => Ok(TlsVersion::Tls10),
+            "1.1" => Ok(TlsVersion::Tls11),
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(format!("{} is not a supported TLS version", version)),
+        }
+    }
+}
+
+/// Client certificate to present to the remote servers
+#[derive(Clone, Debug)]
+pub enum ClientCertificate {
+    /// PEM certificate (and its chain), with a PEM private key - both can be in the same file
+    Pem { cert: PathBuf, key: PathBuf },
+    /// PKCS#12 archive, that contains the certificate, its chain and the private key
+    Pkcs12 { archive: PathBuf, password: String },
+}
+
+impl ClientCertificate {
+    /// Returns the client certificate stored in `cert`.
+    /// If `key` is not given, `cert` is a PKCS#12 archive, or a PEM file that contains the key.
+    pub fn new(cert: &Path, key: Option<&Path>, password: Option<&str>) -> io::Result<ClientCertificate> {
+        if let Some(key) = key {
+            return Ok(ClientCertificate::Pem {
+                cert: cert.to_path_buf(),
+                key: key.to_path_buf(),
+            });
+        }
+        let mut content = Vec::new();
+        File::open(cert)?.read_to_end(&mut content)?;
+        if content.starts_with(b"-----BEGIN") {
+            return Ok(ClientCertificate::Pem {
+                cert: cert.to_path_buf(),
+                key: cert.to_path_buf(),
+            });
+        }
+        Ok(ClientCertificate::Pkcs12 {
+            archive: cert.to_path_buf(),
+            password: String::from(password.unwrap_or("")),
+        })
+    }
+}
+
+/// TLS settings of the HTTPS clients
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    /// PEM file that contains the certificate authorities to trust
+    pub ca_file: Option<PathBuf>,
+    /// Directory of PEM files that contain the certificate authorities to trust
+    pub ca_path: Option<PathBuf>,
+    /// Certificate to present to the remote servers, if they ask for it
+    pub client_cert: Option<ClientCertificate>,
+    /// If true, the certificates of the remote servers are not verified
+    pub insecure: bool,
+    /// The minimum TLS version to accept
+    pub min_version: Option<TlsVersion>,
+}
+
+/// Some enumeration to display accurate TLS errors
+#[derive(Debug)]
+pub enum TlsError {
+    /// Error throwed when a certificate file can't be read
+    Io(PathBuf, io::Error),
+    /// Error throwed when a certificate file does not contain any valid certificate or key
+    InvalidFile(PathBuf),
+    /// Error throwed when the TLS implementation does not support a setting
+    Unsupported(&'static str),
+    /// Error throwed by OpenSSL
+    #[cfg(feature = "tls-openssl")]
+    Ssl(ErrorStack),
+    /// Error throwed by rustls
+    #[cfg(feature = "tls-rustls")]
+    Rustls(TLSError),
+    /// Error throwed by rustls, in the asynchronous HTTP backend
+    #[cfg(feature = "async-http")]
+    AsyncRustls(rustls_async::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TlsError::Io(ref path, ref err) => write!(f, "cannot read {}: {}", path.display(), err),
+            TlsError::InvalidFile(ref path) => {
+                write!(f, "{} does not contain any valid certificate or key", path.display())
+            }
+            TlsError::Unsupported(ref setting) => write!(f, "{} is not supported", setting),
+            #[cfg(feature = "tls-openssl")]
+            TlsError::Ssl(ref err) => write!(f, "{}", err),
+            #[cfg(feature = "tls-rustls")]
+            TlsError::Rustls(ref err) => write!(f, "{}", err),
+            #[cfg(feature = "async-http")]
+            TlsError::AsyncRustls(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for TlsError {
+    fn description(&self) -> &str {
+        match *self {
+            TlsError::Io(_, _) => "Cannot read a certificate file",
+            TlsError::InvalidFile(_) => "Invalid certificate file",
+            TlsError::Unsupported(_) => "Unsupported TLS setting",
+            #[cfg(feature = "tls-openssl")]
+            TlsError::Ssl(_) => "OpenSSL error",
+            #[cfg(feature = "tls-rustls")]
+            TlsError::Rustls(_) => "rustls error",
+            #[cfg(feature = "async-http")]
+            TlsError::AsyncRustls(_) => "rustls error",
+        }
+    }
+}
+
+#[cfg(feature = "tls-openssl")]
+impl From<ErrorStack> for TlsError {
+    fn from(err: ErrorStack) -> TlsError {
+        TlsError::Ssl(err)
+    }
+}
+
+#[cfg(feature = "tls-rustls")]
+impl From<TLSError> for TlsError {
+    fn from(err: TLSError) -> TlsError {
+        TlsError::Rustls(err)
+    }
+}
+
+#[cfg(feature = "async-http")]
+impl From<rustls_async::Error> for TlsError {
+    fn from(err: rustls_async::Error) -> TlsError {
+        TlsError::AsyncRustls(err)
+    }
+}
+
+/// Read the whole content of a certificate file
+#[cfg(any(feature = "tls-openssl", feature = "tls-rustls", feature = "async-http"))]
+fn read_file(path: &Path) -> Result<Vec<u8>, TlsError> {
+    let mut content = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut content))
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))?;
+    Ok(content)
+}
+
+/// Returns the regular files stored in a directory of certificate authorities
+#[cfg(any(feature = "tls-openssl", feature = "tls-rustls", feature = "async-http"))]
+fn list_ca_path(ca_path: &Path) -> Result<Vec<PathBuf>, TlsError> {
+    let mut files = Vec::new();
+    let entries = ::std::fs::read_dir(ca_path).map_err(|err| TlsError::Io(ca_path.to_path_buf(), err))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|err| TlsError::Io(ca_path.to_path_buf(), err))?
+            .path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test_tls_version {
+
+    use super::TlsVersion;
+
+    #[test]
+    fn unknown_version_should_be_refused() {
+        assert!("1.4".parse::<TlsVersion>().is_err());
+    }
+
+    #[test]
+    fn versions_should_be_ordered() {
+        assert_eq!(Ok(TlsVersion::Tls12), "1.2".parse::<TlsVersion>());
+        assert!(TlsVersion::Tls11 < TlsVersion::Tls12);
+    }
+
+}
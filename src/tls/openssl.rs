@@ -0,0 +1,135 @@
+use hyper_openssl::OpensslClient;
+use openssl::pkcs12::Pkcs12;
+use openssl::ssl::{SslConnectorBuilder, SslContextBuilder, SslMethod, SslOption, SSL_OP_NO_SSLV3,
+                   SSL_OP_NO_TLSV1, SSL_OP_NO_TLSV1_1, SSL_OP_NO_TLSV1_2, SSL_VERIFY_NONE};
+use openssl::x509::{X509, X509_FILETYPE_PEM};
+use std::path::Path;
+use tls::{list_ca_path, read_file, ClientCertificate, TlsConfig, TlsError, TlsVersion};
+
+/// The OpenSSL client, used by the HTTPS connectors
+pub type TlsClient = OpensslClient;
+
+/// Trust every certificate authority stored in the PEM files of a directory
+fn add_ca_path(builder: &mut SslContextBuilder, ca_path: &Path) -> Result<(), TlsError> {
+    for path in list_ca_path(ca_path)? {
+        // Files that are not PEM certificates (hash links, READMEs...) are ignored
+        if let Ok(certs) = X509::stack_from_pem(&read_file(&path)?) {
+            for cert in certs {
+                builder.cert_store_mut().add_cert(cert)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Present a client certificate to the remote servers
+fn set_client_cert(builder: &mut SslContextBuilder, client_cert: &ClientCertificate) -> Result<(), TlsError> {
+    match *client_cert {
+        ClientCertificate::Pem { ref cert, ref key } => {
+            builder.set_certificate_chain_file(cert)?;
+            builder.set_private_key_file(key, X509_FILETYPE_PEM)?;
+        }
+        ClientCertificate::Pkcs12 { ref archive, ref password } => {
+            let parsed = Pkcs12::from_der(&read_file(archive)?)?.parse(password)?;
+            builder.set_certificate(&parsed.cert)?;
+            builder.set_private_key(&parsed.pkey)?;
+            for cert in parsed.chain {
+                builder.add_extra_chain_cert(cert)?;
+            }
+        }
+    }
+    builder.check_private_key()?;
+    Ok(())
+}
+
+/// Returns the OpenSSL options to use, in order to refuse versions lower than `min_version`
+fn get_min_version_options(min_version: TlsVersion) -> SslOption {
+    let mut options = SSL_OP_NO_SSLV3;
+    if min_version > TlsVersion::Tls10 {
+        options |= SSL_OP_NO_TLSV1;
+    }
+    if min_version > TlsVersion::Tls11 {
+        options |= SSL_OP_NO_TLSV1_1;
+    }
+    if min_version > TlsVersion::Tls12 {
+        options |= SSL_OP_NO_TLSV1_2;
+    }
+    options
+}
+
+/// Returns an OpenSSL client, configured using the TLS settings
+pub fn get_tls_client(config: &TlsConfig) -> Result<OpensslClient, TlsError> {
+    let mut connector_builder = SslConnectorBuilder::new(SslMethod::tls())?;
+    {
+        let builder: &mut SslContextBuilder = &mut connector_builder;
+        if let Some(ref ca_file) = config.ca_file {
+            builder.set_ca_file(ca_file)?;
+        }
+        if let Some(ref ca_path) = config.ca_path {
+            add_ca_path(builder, ca_path)?;
+        }
+        if let Some(ref client_cert) = config.client_cert {
+            set_client_cert(builder, client_cert)?;
+        }
+        if let Some(min_version) = config.min_version {
+            builder.set_options(get_min_version_options(min_version));
+        }
+        if config.insecure {
+            builder.set_verify(SSL_VERIFY_NONE);
+        }
+    }
+    let mut client = OpensslClient::from(connector_builder.build());
+    client.danger_disable_hostname_verification(config.insecure);
+    Ok(client)
+}
+
+#[cfg(test)]
+mod test_openssl_version {
+
+    use super::get_min_version_options;
+    use openssl::ssl::{SSL_OP_NO_SSLV3, SSL_OP_NO_TLSV1, SSL_OP_NO_TLSV1_1, SSL_OP_NO_TLSV1_2};
+    use tls::TlsVersion;
+
+    #[test]
+    fn min_version_should_disable_older_versions() {
+        let options = get_min_version_options(TlsVersion::Tls12);
+        assert!(options.contains(SSL_OP_NO_SSLV3 | SSL_OP_NO_TLSV1 | SSL_OP_NO_TLSV1_1));
+        assert!(!options.contains(SSL_OP_NO_TLSV1_2));
+    }
+
+}
+
+#[cfg(test)]
+mod test_openssl_config {
+
+    use super::get_tls_client;
+    use tls::{TlsConfig, TlsError};
+    use std::path::PathBuf;
+
+    #[test]
+    fn default_config_should_build_a_client() {
+        assert!(get_tls_client(&TlsConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn missing_ca_path_should_be_an_error() {
+        let config = TlsConfig {
+            ca_path: Some(PathBuf::from("/this/path/does/not/exist")),
+            ..TlsConfig::default()
+        };
+        match get_tls_client(&config) {
+            Err(TlsError::Io(_, _)) => {}
+            _ => panic!("a missing CA directory should be an I/O error"),
+        }
+    }
+
+    #[test]
+    fn missing_ca_file_should_be_an_error() {
+        let config = TlsConfig {
+            ca_file: Some(PathBuf::from("/this/file/does/not/exist.pem")),
+            ..TlsConfig::default()
+        };
+        assert!(get_tls_client(&config).is_err());
+    }
+
+}
@@ -0,0 +1,218 @@
+use hyper;
+use hyper::net::{HttpStream, NetworkStream, SslClient};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{Certificate, ClientConfig, ClientSession, ProtocolVersion, RootCertStore,
+             ServerCertVerified, ServerCertVerifier, StreamOwned, TLSError};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tls::{list_ca_path, read_file, ClientCertificate, TlsConfig, TlsError, TlsVersion};
+use webpki::DNSNameRef;
+use webpki_roots::TLS_SERVER_ROOTS;
+
+/// The rustls client, used by the HTTPS connectors
+pub struct TlsClient {
+    config: Arc<ClientConfig>,
+}
+
+/// A TLS stream, that can be shared by hyper (like the OpenSSL streams)
+#[derive(Clone)]
+pub struct TlsStream(Arc<Mutex<StreamOwned<ClientSession, HttpStream>>>);
+
+impl TlsStream {
+    fn lock(&self) -> MutexGuard<'_, StreamOwned<ClientSession, HttpStream>> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+impl NetworkStream for TlsStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.lock().sock.peer_addr()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.lock().sock.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.lock().sock.set_write_timeout(dur)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.lock().sock.close(how)
+    }
+}
+
+impl SslClient for TlsClient {
+    type Stream = TlsStream;
+
+    fn wrap_client(&self, stream: HttpStream, host: &str) -> hyper::Result<TlsStream> {
+        let dns_name = DNSNameRef::try_from_ascii_str(host).map_err(|_| {
+            hyper::Error::Ssl(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a valid DNS name", host),
+            )))
+        })?;
+        let session = ClientSession::new(&self.config, dns_name);
+        Ok(TlsStream(Arc::new(Mutex::new(StreamOwned::new(session, stream)))))
+    }
+}
+
+/// Certificate verifier that accepts every certificate - used only by `--insecure`
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Trust every certificate authority stored in a PEM file.
+/// If `ignore_invalid` is true, files that do not contain any certificate are ignored.
+fn add_ca_file(config: &mut ClientConfig, ca_file: &Path, ignore_invalid: bool) -> Result<(), TlsError> {
+    let content = read_file(ca_file)?;
+    match config.root_store.add_pem_file(&mut content.as_slice()) {
+        Ok((valid, _)) if valid > 0 => Ok(()),
+        _ if ignore_invalid => Ok(()),
+        _ => Err(TlsError::InvalidFile(ca_file.to_path_buf())),
+    }
+}
+
+/// Present a PEM client certificate to the remote servers
+fn set_client_cert(config: &mut ClientConfig, client_cert: &ClientCertificate) -> Result<(), TlsError> {
+    let (cert, key) = match *client_cert {
+        ClientCertificate::Pem { ref cert, ref key } => (cert, key),
+        ClientCertificate::Pkcs12 { .. } => {
+            return Err(TlsError::Unsupported("PKCS#12 client certificate (with rustls)"))
+        }
+    };
+    let cert_chain = certs(&mut read_file(cert)?.as_slice())
+        .ok()
+        .and_then(|chain| if chain.is_empty() { None } else { Some(chain) })
+        .ok_or_else(|| TlsError::InvalidFile(cert.clone()))?;
+
+    let key_content = read_file(key)?;
+    let mut keys = pkcs8_private_keys(&mut key_content.as_slice()).unwrap_or_default();
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut key_content.as_slice()).unwrap_or_default();
+    }
+    if keys.is_empty() {
+        return Err(TlsError::InvalidFile(key.clone()));
+    }
+    config.set_single_client_cert(cert_chain, keys.remove(0))?;
+    Ok(())
+}
+
+/// Returns the TLS versions to accept - rustls does not support versions older than TLS 1.2
+fn get_versions(min_version: TlsVersion) -> Vec<ProtocolVersion> {
+    if min_version > TlsVersion::Tls12 {
+        vec![ProtocolVersion::TLSv1_3]
+    } else {
+        vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2]
+    }
+}
+
+/// Returns a rustls client, configured using the TLS settings
+pub fn get_tls_client(config: &TlsConfig) -> Result<TlsClient, TlsError> {
+    let mut client_config = ClientConfig::new();
+    client_config
+        .root_store
+        .add_server_trust_anchors(&TLS_SERVER_ROOTS);
+    if let Some(ref ca_file) = config.ca_file {
+        add_ca_file(&mut client_config, ca_file, false)?;
+    }
+    if let Some(ref ca_path) = config.ca_path {
+        for path in list_ca_path(ca_path)? {
+            // Files that are not PEM certificates (hash links, READMEs...) are ignored
+            add_ca_file(&mut client_config, &path, true)?;
+        }
+    }
+    if let Some(ref client_cert) = config.client_cert {
+        set_client_cert(&mut client_config, client_cert)?;
+    }
+    if let Some(min_version) = config.min_version {
+        client_config.versions = get_versions(min_version);
+    }
+    if config.insecure {
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoVerification));
+    }
+    Ok(TlsClient { config: Arc::new(client_config) })
+}
+
+#[cfg(test)]
+mod test_rustls_config {
+
+    use super::{get_tls_client, get_versions};
+    use rustls::ProtocolVersion;
+    use std::path::PathBuf;
+    use tls::{ClientCertificate, TlsConfig, TlsError, TlsVersion};
+
+    #[test]
+    fn default_config_should_build_a_client() {
+        assert!(get_tls_client(&TlsConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn missing_ca_file_should_be_an_error() {
+        let config = TlsConfig {
+            ca_file: Some(PathBuf::from("/this/file/does/not/exist.pem")),
+            ..TlsConfig::default()
+        };
+        match get_tls_client(&config) {
+            Err(TlsError::Io(_, _)) => {}
+            _ => panic!("a missing CA file should be an I/O error"),
+        }
+    }
+
+    #[test]
+    fn pkcs12_should_be_unsupported() {
+        let config = TlsConfig {
+            client_cert: Some(ClientCertificate::Pkcs12 {
+                archive: PathBuf::from("client.p12"),
+                password: String::new(),
+            }),
+            ..TlsConfig::default()
+        };
+        match get_tls_client(&config) {
+            Err(TlsError::Unsupported(_)) => {}
+            _ => panic!("PKCS#12 archives should not be supported"),
+        }
+    }
+
+    #[test]
+    fn old_versions_should_fallback_to_tls12() {
+        assert_eq!(
+            vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+            get_versions(TlsVersion::Tls10)
+        );
+        assert_eq!(vec![ProtocolVersion::TLSv1_3], get_versions(TlsVersion::Tls13));
+    }
+
+}
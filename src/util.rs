@@ -4,7 +4,7 @@ use std::io::Write;
 /// Deliver a way to interact with the user, using the keyboard
 pub fn prompt_user(prompt: &str) -> String {
     warning!(prompt);
-    io::stdout().flush().expect("Couldn't flush stdout!");
+    io::stderr().flush().expect("Couldn't flush stderr!");
 
     let mut user_input = String::new();
     io::stdin()
@@ -0,0 +1,540 @@
+use std::fs::File;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::ptr;
+use std::sync::Arc;
+
+mod sink;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+
+pub use self::sink::{OutputSink, SeekSink};
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use self::uring::UringFile;
+
+/// Number of contiguous bytes a chunk writer keeps in memory before writing them in the file
+pub const COALESCING_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// Function to write the whole buffer at the given offset of the file, without moving the
+/// cursor of the file - threads can write in the same file at the same time.
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write the whole buffer",
+                ))
+            }
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Function to reserve the blocks of the first `length` bytes of the file, extending the file if
+/// it is shorter (`posix_fallocate`)
+#[cfg(target_os = "linux")]
+fn reserve_blocks(file: &File, length: u64) -> io::Result<()> {
+    // posix_fallocate returns the error, instead of setting errno
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, length as libc::off_t) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn reserve_blocks(_file: &File, _length: u64) -> io::Result<()> {
+    Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+}
+
+/// Returns true if the blocks of the file cannot be reserved on this file system or platform
+#[cfg(unix)]
+fn is_unsupported_reservation(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(errno) => errno == libc::EOPNOTSUPP || errno == libc::ENOSYS,
+        None => false,
+    }
+}
+
+/// Memory mapping of a whole file, shared with the file system
+#[cfg(unix)]
+struct MappedFile {
+    file: File,
+    data: *mut u8,
+    len: usize,
+}
+
+// The chunk writers write in distinct parts of the mapping
+#[cfg(unix)]
+unsafe impl Send for MappedFile {}
+#[cfg(unix)]
+unsafe impl Sync for MappedFile {}
+
+#[cfg(unix)]
+impl MappedFile {
+    /// Map the first `length` bytes of the file in memory - the file must be at least `length`
+    /// bytes long
+    fn new(file: File, length: u64) -> io::Result<MappedFile> {
+        if length == 0 || length > usize::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot map a file of this length in memory",
+            ));
+        }
+        let len = length as usize;
+        let data = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(MappedFile {
+            file,
+            data: data as *mut u8,
+            len,
+        })
+    }
+
+    /// Write the modified pages of the mapping in the file
+    fn sync(&self) -> io::Result<()> {
+        if unsafe { libc::msync(self.data as *mut libc::c_void, self.len, libc::MS_SYNC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        if offset > self.len as u64 || buf.len() > self.len - offset as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot write past the end of the mapped file",
+            ));
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(buf.as_ptr(), self.data.add(offset as usize), buf.len());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.data as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// The ways to write in the output file
+enum Output {
+    /// Positional writes (`pwrite`) in the file
+    Positional(File),
+    /// Copies in a memory mapping of the file
+    #[cfg(unix)]
+    Mapped(MappedFile),
+    /// Asynchronous writes through io_uring
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    Uring(UringFile),
+    /// Writes in a sink given by the caller, instead of a local file
+    Sink(Arc<dyn OutputSink>),
+}
+
+impl Output {
+    /// Returns the local file, if the output is a local file
+    fn file(&self) -> Option<&File> {
+        match *self {
+            Output::Positional(ref file) => Some(file),
+            #[cfg(unix)]
+            Output::Mapped(ref mapped_file) => Some(&mapped_file.file),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Output::Uring(ref uring_file) => Some(uring_file.file()),
+            Output::Sink(_) => None,
+        }
+    }
+}
+
+/// Structure that contains a shared file instance.
+/// The writes do not lock the file: each thread writes at its own offsets, using positional
+/// writes or a memory mapping of the file.
+#[derive(Clone)]
+pub struct OutputFileWriter {
+    output: Arc<Output>,
+}
+
+/// Structure that writes a chunk in the shared file, from the offset of the chunk.
+/// Contiguous writes are gathered in a buffer, and written at once in the file: the buffer is
+/// written when full, when a write is not contiguous, and when the chunk writer is flushed or
+/// dropped.
+/// As the errors cannot be reported when dropping the chunk writer, it has to be flushed
+/// explicitly.
+/// The chunk writer never writes past the end of its chunk: the region of the next chunk is
+/// left untouched.
+pub struct OutputChunkWriter {
+    output: OutputFileWriter,
+    offset: u64,
+    length: u64,
+    buffer: Vec<u8>,
+    /// Offset of the buffer, from the offset of the chunk
+    buffer_offset: u64,
+}
+
+impl OutputFileWriter {
+    /// Write the buffer at the given offset of the file
+    pub fn write(&self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        match *self.output {
+            Output::Positional(ref file) => write_all_at(file, buf, offset),
+            #[cfg(unix)]
+            Output::Mapped(ref mapped_file) => mapped_file.write(offset, buf),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Output::Uring(ref uring_file) => uring_file.write(offset, buf),
+            Output::Sink(ref sink) => sink.write_at(offset, buf),
+        }
+    }
+
+    /// Wait for the writes that are still in progress, and returns the first error of these
+    /// writes - only the io_uring writes are asynchronous
+    pub fn flush(&self) -> io::Result<()> {
+        match *self.output {
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Output::Uring(ref uring_file) => uring_file.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Copy `len` bytes of `source`, starting at `source_offset`, to `offset`, without going
+    /// through the user space.
+    /// This function returns the number of bytes that have been copied.
+    #[cfg(target_os = "linux")]
+    pub fn copy_from(
+        &self,
+        offset: u64,
+        source: &File,
+        source_offset: u64,
+        len: usize,
+    ) -> io::Result<usize> {
+        use libc::copy_file_range;
+
+        let file = self.output.file().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "in-kernel copies need a local output file",
+            )
+        })?;
+        let mut off_in = source_offset as _;
+        let mut off_out = offset as _;
+        let copied = unsafe {
+            copy_file_range(
+                source.as_raw_fd(),
+                &mut off_in,
+                file.as_raw_fd(),
+                &mut off_out,
+                len,
+                0,
+            )
+        };
+        if copied < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(copied as usize)
+    }
+
+    /// Wait for the writes still in progress, and write the content of the file on the disk
+    pub fn sync(&self) -> io::Result<()> {
+        self.flush()?;
+        #[cfg(unix)]
+        {
+            if let Output::Mapped(ref mapped_file) = *self.output {
+                mapped_file.sync()?;
+            }
+        }
+        if let Output::Sink(ref sink) = *self.output {
+            return sink.sync();
+        }
+        match self.output.file() {
+            Some(file) => file.sync_all(),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns a writer for the chunk of `length` bytes that starts at `offset`
+    pub fn get_chunk_writer(&self, offset: u64, length: u64) -> OutputChunkWriter {
+        OutputChunkWriter {
+            output: self.clone(),
+            offset,
+            length,
+            buffer: Vec::new(),
+            buffer_offset: 0,
+        }
+    }
+
+    /// Returns a writer that uses positional writes in the file
+    #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+    pub fn new(file: File) -> OutputFileWriter {
+        OutputFileWriter { output: Arc::new(Output::Positional(file)) }
+    }
+
+    /// Returns a writer that submits the writes asynchronously through io_uring - or that uses
+    /// positional writes in the file, if the kernel does not support io_uring.
+    /// The writes have to be waited for using `flush`.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    pub fn new(file: File) -> OutputFileWriter {
+        let output = match file.try_clone().and_then(UringFile::new) {
+            Ok(uring_file) => Output::Uring(uring_file),
+            Err(_) => Output::Positional(file),
+        };
+        OutputFileWriter { output: Arc::new(output) }
+    }
+
+    /// Returns a writer that copies the content in a memory mapping of the file, of `length`
+    /// bytes.
+    /// The blocks of the file are reserved first (the file is extended to `length` bytes if it
+    /// is shorter): writing in a hole of a sparse mapping kills the process (`SIGBUS`) if the
+    /// disk is full, instead of returning an error. If the blocks cannot be reserved on this
+    /// file system, the writer uses the file as `OutputFileWriter::new`.
+    /// The file must not be truncated during the download.
+    /// This function returns an error if the blocks cannot be reserved (not enough space...),
+    /// or if the file cannot be mapped (empty content, content too big for the address space,
+    /// platform without `mmap`...).
+    #[cfg(unix)]
+    pub fn with_mmap(file: File, length: u64) -> io::Result<OutputFileWriter> {
+        match reserve_blocks(&file, length) {
+            Ok(()) => {}
+            Err(ref err) if is_unsupported_reservation(err) => return Ok(OutputFileWriter::new(file)),
+            Err(err) => return Err(err),
+        }
+        Ok(OutputFileWriter { output: Arc::new(Output::Mapped(MappedFile::new(file, length)?)) })
+    }
+
+    #[cfg(not(unix))]
+    pub fn with_mmap(_file: File, _length: u64) -> io::Result<OutputFileWriter> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "memory mappings are not supported on this platform",
+        ))
+    }
+
+    /// Returns a writer that writes the chunks in the given sink, instead of a local file
+    pub fn with_sink(sink: Arc<dyn OutputSink>) -> OutputFileWriter {
+        OutputFileWriter { output: Arc::new(Output::Sink(sink)) }
+    }
+}
+
+impl OutputChunkWriter {
+    /// Returns an error if `len` bytes at `done_offset` do not fit in the chunk
+    fn check_bounds(&self, done_offset: u64, len: usize) -> io::Result<()> {
+        if done_offset.saturating_add(len as u64) > self.length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the write crosses the end of the chunk",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write the buffer at `done_offset`, from the offset of the chunk.
+    /// The bytes may be kept in memory until the next flush.
+    pub fn write(&mut self, done_offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.check_bounds(done_offset, buf.len())?;
+        let is_contiguous = done_offset == self.buffer_offset + self.buffer.len() as u64;
+        if !is_contiguous || self.buffer.len() + buf.len() > COALESCING_BUFFER_BYTES {
+            self.flush()?;
+        }
+        if buf.len() >= COALESCING_BUFFER_BYTES {
+            return self.output.write(self.offset + done_offset, buf);
+        }
+        if self.buffer.is_empty() {
+            self.buffer_offset = done_offset;
+            self.buffer.reserve(COALESCING_BUFFER_BYTES);
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(())
+    }
+
+    /// Write the bytes kept in memory in the file
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let result = self
+            .output
+            .write(self.offset + self.buffer_offset, &self.buffer);
+        self.buffer.clear();
+        result
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn copy_from(
+        &mut self,
+        done_offset: u64,
+        source: &File,
+        source_offset: u64,
+        len: usize,
+    ) -> io::Result<usize> {
+        self.check_bounds(done_offset, len)?;
+        self.flush()?;
+        self.output
+            .copy_from(self.offset + done_offset, source, source_offset, len)
+    }
+}
+
+impl Drop for OutputChunkWriter {
+    fn drop(&mut self) {
+        // The errors are reported by the explicit flushes
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test_write {
+
+    use super::{OutputFileWriter, COALESCING_BUFFER_BYTES};
+    use std::env;
+    use std::fs::{remove_file, File, OpenOptions};
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn create_file(name: &str, len: u64) -> (PathBuf, File) {
+        let path = env::temp_dir().join(format!("zou-{}-{}", name, process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.set_len(len).unwrap();
+        (path, file)
+    }
+
+    fn read_file(path: &PathBuf) -> Vec<u8> {
+        let mut content = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut content).unwrap();
+        remove_file(path).unwrap();
+        content
+    }
+
+    #[test]
+    fn contiguous_writes_should_be_kept_until_flushed() {
+        let (path, file) = create_file("coalescing", 8);
+        let out_file = OutputFileWriter::new(file);
+        let mut chunk_writer = out_file.get_chunk_writer(2, 4);
+        chunk_writer.write(0, b"ab").unwrap();
+        chunk_writer.write(2, b"cd").unwrap();
+        let mut other_file = File::open(&path).unwrap();
+        let mut content = Vec::new();
+        other_file.read_to_end(&mut content).unwrap();
+        assert_eq!(vec![0; 8], content);
+
+        chunk_writer.flush().unwrap();
+        drop(chunk_writer);
+        out_file.flush().unwrap();
+        assert_eq!(b"\0\0abcd\0\0".to_vec(), read_file(&path));
+    }
+
+    #[test]
+    fn writes_should_be_flushed_when_not_contiguous_or_dropped() {
+        let (path, file) = create_file("not_contiguous", COALESCING_BUFFER_BYTES as u64 + 4);
+        let out_file = OutputFileWriter::new(file);
+        {
+            let mut chunk_writer = out_file.get_chunk_writer(0, COALESCING_BUFFER_BYTES as u64 + 4);
+            chunk_writer.write(2, b"cd").unwrap();
+            chunk_writer.write(0, b"ab").unwrap();
+            chunk_writer
+                .write(4, &vec![1; COALESCING_BUFFER_BYTES])
+                .unwrap();
+        }
+        out_file.flush().unwrap();
+        let content = read_file(&path);
+        assert_eq!(b"abcd", &content[0..4]);
+        assert!(content[4..].iter().all(|byte| *byte == 1));
+    }
+
+    #[test]
+    fn writes_should_not_cross_the_end_of_the_chunk() {
+        let (path, file) = create_file("bounds", 6);
+        let out_file = OutputFileWriter::new(file);
+        {
+            let mut chunk_writer = out_file.get_chunk_writer(0, 3);
+            chunk_writer.write(0, b"ab").unwrap();
+            assert!(chunk_writer.write(2, b"cd").is_err());
+            chunk_writer.write(2, b"c").unwrap();
+            chunk_writer.flush().unwrap();
+        }
+        out_file.flush().unwrap();
+        assert_eq!(b"abc\0\0\0".to_vec(), read_file(&path));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mapped_file_should_be_written() {
+        let (path, file) = create_file("mmap", 6);
+        {
+            let out_file = OutputFileWriter::with_mmap(file, 6).unwrap();
+            out_file.write(3, b"def").unwrap();
+            out_file.get_chunk_writer(0, 3).write(0, b"abc").unwrap();
+            assert!(out_file.write(4, b"too long").is_err());
+        }
+        assert_eq!(b"abcdef".to_vec(), read_file(&path));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn short_file_should_be_extended_before_mapping() {
+        // e.g. a file that has not been allocated (--file-allocation=none)
+        let (path, file) = create_file("short_mmap", 2);
+        {
+            let out_file = OutputFileWriter::with_mmap(file, 6).unwrap();
+            out_file.get_chunk_writer(3, 3).write(0, b"def").unwrap();
+            out_file.get_chunk_writer(0, 3).write(0, b"abc").unwrap();
+        }
+        assert_eq!(b"abcdef".to_vec(), read_file(&path));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn blocks_of_the_mapped_file_should_be_reserved() {
+        use std::os::unix::fs::MetadataExt;
+
+        // A sparse file, as allocated by --file-allocation=trunc
+        let (path, file) = create_file("sparse_mmap", 1 << 20);
+        assert!(file.metadata().unwrap().blocks() * 512 < 1 << 20);
+        let out_file = OutputFileWriter::with_mmap(file.try_clone().unwrap(), 1 << 20).unwrap();
+        assert!(file.metadata().unwrap().blocks() * 512 >= 1 << 20);
+        drop(out_file);
+        remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn empty_content_should_not_be_mapped() {
+        let (path, file) = create_file("empty_mmap", 0);
+        assert!(OutputFileWriter::with_mmap(file, 0).is_err());
+        remove_file(&path).unwrap();
+    }
+}
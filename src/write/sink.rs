@@ -0,0 +1,103 @@
+//! Outputs that are not local files: any `Write + Seek` writer given by the caller, e.g. a
+//! buffer in memory.
+
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use write::OutputFileWriter;
+
+/// Trait that represents the output of a download: the chunks are written at their own
+/// offsets, by several threads at the same time.
+/// `OutputFileWriter` writes in a local file, and can write in any other sink (see
+/// `OutputFileWriter::with_sink`).
+pub trait OutputSink: Send + Sync {
+    /// Write the whole buffer at the given offset of the output
+    fn write_at(&self, offset: u64, buf: &[u8]) -> io::Result<()>;
+
+    /// Wait for the writes still in progress, and write the content of the output on its
+    /// storage
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl OutputSink for OutputFileWriter {
+    fn write_at(&self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.write(offset, buf)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        OutputFileWriter::sync(self)
+    }
+}
+
+/// Sink that writes in a `Write + Seek` writer.
+/// The writer has a single cursor, so the writes of the threads are serialized by a lock.
+pub struct SeekSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Seek + Send> SeekSink<W> {
+    /// Returns a sink that writes in the given writer
+    pub fn new(writer: W) -> SeekSink<W> {
+        SeekSink { writer: Mutex::new(writer) }
+    }
+
+    /// Returns the writer
+    pub fn into_inner(self) -> W {
+        match self.writer.into_inner() {
+            Ok(writer) => writer,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl<W: Write + Seek + Send> OutputSink for SeekSink<W> {
+    fn write_at(&self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.seek(SeekFrom::Start(offset))?;
+        writer.write_all(buf)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+#[cfg(test)]
+mod test_sink {
+
+    use super::{OutputSink, SeekSink};
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use write::OutputFileWriter;
+
+    #[test]
+    fn chunks_should_be_written_in_the_sink() {
+        let sink = Arc::new(SeekSink::new(Cursor::new(Vec::new())));
+        let out_file = OutputFileWriter::with_sink(sink.clone());
+        {
+            let mut chunk_writer = out_file.get_chunk_writer(3, 3);
+            chunk_writer.write(0, b"def").unwrap();
+            chunk_writer.flush().unwrap();
+        }
+        out_file.get_chunk_writer(0, 3).write(0, b"abc").unwrap();
+        out_file.sync().unwrap();
+        drop(out_file);
+
+        let sink = Arc::try_unwrap(sink).ok().unwrap();
+        assert_eq!(b"abcdef".to_vec(), sink.into_inner().into_inner());
+    }
+
+    #[test]
+    fn sink_should_not_copy_from_a_file() {
+        let sink = Arc::new(SeekSink::new(Cursor::new(Vec::new())));
+        let out_file = OutputFileWriter::with_sink(sink);
+        assert!(out_file.write_at(0, b"zou").is_ok());
+        #[cfg(target_os = "linux")]
+        {
+            let source = ::std::fs::File::open("Cargo.toml").unwrap();
+            assert!(out_file.copy_from(0, &source, 0, 3).is_err());
+        }
+    }
+}
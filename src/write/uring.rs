@@ -0,0 +1,360 @@
+//! Asynchronous writes in the output file, through io_uring (Linux only, `io-uring` feature).
+//! The bytes to write are copied in buffers registered in the ring, and a dedicated thread
+//! submits the writes and reaps their completions: the download threads do not wait for the
+//! disk, unless every registered buffer is in use.
+
+use io_uring::{opcode, types, IoUring};
+use std::cmp::min;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use super::COALESCING_BUFFER_BYTES;
+
+/// Number of buffers registered in the ring, i.e. writes in flight at the same time
+const URING_BUFFERS: usize = 8;
+
+/// Buffers registered in the ring.
+/// A buffer is used either by a single writer that fills it, or by the ring that writes it.
+struct Buffers {
+    data: Vec<*mut [u8]>,
+}
+
+unsafe impl Send for Buffers {}
+unsafe impl Sync for Buffers {}
+
+impl Buffers {
+    fn new() -> Buffers {
+        Buffers {
+            data: (0..URING_BUFFERS)
+                .map(|_| Box::into_raw(vec![0; COALESCING_BUFFER_BYTES].into_boxed_slice()))
+                .collect(),
+        }
+    }
+
+    fn get(&self, index: usize) -> *mut u8 {
+        self.data[index] as *mut u8
+    }
+
+    fn iovecs(&self) -> Vec<libc::iovec> {
+        self.data
+            .iter()
+            .map(|buffer| libc::iovec {
+                iov_base: *buffer as *mut libc::c_void,
+                iov_len: COALESCING_BUFFER_BYTES,
+            })
+            .collect()
+    }
+}
+
+impl Drop for Buffers {
+    fn drop(&mut self) {
+        for buffer in &self.data {
+            unsafe {
+                drop(Box::from_raw(*buffer));
+            }
+        }
+    }
+}
+
+/// A write to submit: a registered buffer, the number of bytes to write, and the offset in the
+/// file
+struct WriteRequest {
+    buffer: usize,
+    len: usize,
+    offset: u64,
+    /// Number of bytes already written
+    done: usize,
+}
+
+/// State shared by the writers and the submission thread
+struct State {
+    free_buffers: Vec<usize>,
+    /// The first error of the writes - once a write failed, every write fails
+    error: Option<(io::ErrorKind, String)>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    buffer_released: Condvar,
+}
+
+impl Shared {
+    fn release(&self, buffer: usize, error: Option<io::Error>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = error {
+            if state.error.is_none() {
+                state.error = Some((error.kind(), error.to_string()));
+            }
+        }
+        state.free_buffers.push(buffer);
+        self.buffer_released.notify_all();
+    }
+}
+
+/// Returns the error recorded in the state, if any
+fn get_error(state: &State) -> io::Result<()> {
+    match state.error {
+        Some((kind, ref message)) => Err(io::Error::new(kind, message.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Output file written through io_uring
+pub struct UringFile {
+    file: File,
+    buffers: Arc<Buffers>,
+    shared: Arc<Shared>,
+    sender: Mutex<Option<Sender<WriteRequest>>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl UringFile {
+    /// Returns an output file written through io_uring.
+    /// This function returns an error if the kernel does not support io_uring, or refuses to
+    /// register the buffers.
+    pub fn new(file: File) -> io::Result<UringFile> {
+        let ring = IoUring::new(URING_BUFFERS as u32)?;
+        let buffers = Arc::new(Buffers::new());
+        unsafe {
+            ring.submitter().register_buffers(&buffers.iovecs())?;
+        }
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                free_buffers: (0..URING_BUFFERS).collect(),
+                error: None,
+            }),
+            buffer_released: Condvar::new(),
+        });
+        let (sender, receiver) = channel();
+        let thread = {
+            let (fd, buffers, shared) = (file.as_raw_fd(), buffers.clone(), shared.clone());
+            thread::spawn(move || submit_writes(ring, fd, &buffers, &shared, &receiver))
+        };
+        Ok(UringFile {
+            file,
+            buffers,
+            shared,
+            sender: Mutex::new(Some(sender)),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Wait for a free registered buffer - this function returns an error if a previous write
+    /// failed
+    fn acquire_buffer(&self) -> io::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            get_error(&state)?;
+            if let Some(buffer) = state.free_buffers.pop() {
+                return Ok(buffer);
+            }
+            state = self.shared.buffer_released.wait(state).unwrap();
+        }
+    }
+
+    /// Submit the write of the buffer at the given offset of the file.
+    /// The write may fail later: the error is sent back by the next writes, and by `flush`.
+    pub fn write(&self, mut offset: u64, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let buffer = self.acquire_buffer()?;
+            let len = min(buf.len(), COALESCING_BUFFER_BYTES);
+            unsafe {
+                ptr::copy_nonoverlapping(buf.as_ptr(), self.buffers.get(buffer), len);
+            }
+            let request = WriteRequest {
+                buffer,
+                len,
+                offset,
+                done: 0,
+            };
+            let is_sent = match *self.sender.lock().unwrap() {
+                Some(ref sender) => sender.send(request).is_ok(),
+                None => false,
+            };
+            if !is_sent {
+                return Err(io::Error::other("the io_uring thread has stopped"));
+            }
+            buf = &buf[len..];
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Wait for every submitted write, and returns the first error of the writes
+    pub fn flush(&self) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        while state.error.is_none() && state.free_buffers.len() < URING_BUFFERS {
+            state = self.shared.buffer_released.wait(state).unwrap();
+        }
+        get_error(&state)
+    }
+}
+
+impl Drop for UringFile {
+    fn drop(&mut self) {
+        // The thread submits the remaining writes, then stops: the file can be closed
+        self.sender.lock().unwrap().take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Push the write of the remaining bytes of the request in the submission queue
+fn push_write(ring: &mut IoUring, fd: RawFd, buffers: &Buffers, request: &WriteRequest) -> io::Result<()> {
+    let entry = opcode::WriteFixed::new(
+        types::Fd(fd),
+        unsafe { buffers.get(request.buffer).add(request.done) },
+        (request.len - request.done) as u32,
+        request.buffer as u16,
+    ).offset(request.offset + request.done as u64)
+        .build()
+        .user_data(request.buffer as u64);
+    // There are never more writes in flight than entries in the queue
+    unsafe { ring.submission().push(&entry) }
+        .map_err(|_| io::Error::other("the io_uring submission queue is full"))
+}
+
+/// Loop of the submission thread: submit the writes sent by the writers, and release their
+/// buffers once written - until every writer is gone and every write is done
+fn submit_writes(
+    mut ring: IoUring,
+    fd: RawFd,
+    buffers: &Buffers,
+    shared: &Shared,
+    receiver: &Receiver<WriteRequest>,
+) {
+    let mut in_flight: Vec<Option<WriteRequest>> = (0..URING_BUFFERS).map(|_| None).collect();
+    let mut pending = 0;
+    let mut is_disconnected = false;
+
+    loop {
+        // Wait for a new write only if there is nothing to wait for in the ring
+        let mut requests = Vec::new();
+        if pending == 0 {
+            if is_disconnected {
+                return;
+            }
+            match receiver.recv() {
+                Ok(request) => requests.push(request),
+                Err(_) => return,
+            }
+        }
+        while !is_disconnected {
+            match receiver.try_recv() {
+                Ok(request) => requests.push(request),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => is_disconnected = true,
+            }
+        }
+        for request in requests {
+            let buffer = request.buffer;
+            match push_write(&mut ring, fd, buffers, &request) {
+                Ok(()) => {
+                    in_flight[buffer] = Some(request);
+                    pending += 1;
+                }
+                Err(err) => shared.release(buffer, Some(err)),
+            }
+        }
+
+        if pending == 0 {
+            continue;
+        }
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                // The writes in flight cannot be followed anymore
+                for (buffer, request) in in_flight.iter_mut().enumerate() {
+                    if request.take().is_some() {
+                        shared.release(buffer, Some(io::Error::new(err.kind(), err.to_string())));
+                    }
+                }
+                return;
+            }
+        }
+
+        let completions: Vec<(usize, i32)> = ring
+            .completion()
+            .map(|entry| (entry.user_data() as usize, entry.result()))
+            .collect();
+        for (buffer, result) in completions {
+            let mut request = match in_flight[buffer].take() {
+                Some(request) => request,
+                None => continue,
+            };
+            pending -= 1;
+            let error = if result < 0 {
+                Some(io::Error::from_raw_os_error(-result))
+            } else if result == 0 {
+                Some(io::Error::new(io::ErrorKind::WriteZero, "failed to write the whole buffer"))
+            } else {
+                request.done += result as usize;
+                None
+            };
+            // Short write: write the remaining bytes
+            if error.is_none() && request.done < request.len {
+                match push_write(&mut ring, fd, buffers, &request) {
+                    Ok(()) => {
+                        in_flight[buffer] = Some(request);
+                        pending += 1;
+                    }
+                    Err(err) => shared.release(buffer, Some(err)),
+                }
+                continue;
+            }
+            shared.release(buffer, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_uring {
+
+    use super::UringFile;
+    use std::env;
+    use std::fs::{remove_file, File, OpenOptions};
+    use std::io::Read;
+    use std::process;
+
+    #[test]
+    fn writes_should_be_done_after_flush() {
+        let path = env::temp_dir().join(format!("zou-uring-{}", process::id()));
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let content: Vec<u8> = (0..3_000_000).map(|i| (i % 251) as u8).collect();
+        let uring_file = match UringFile::new(file) {
+            Ok(uring_file) => uring_file,
+            // io_uring is not supported by the kernel
+            Err(_) => {
+                remove_file(&path).unwrap();
+                return;
+            }
+        };
+        uring_file.write(1_000_000, &content[1_000_000..]).unwrap();
+        uring_file.write(0, &content[0..1_000_000]).unwrap();
+        uring_file.flush().unwrap();
+
+        let mut written_content = Vec::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_end(&mut written_content)
+            .unwrap();
+        remove_file(&path).unwrap();
+        assert!(content == written_content);
+    }
+}
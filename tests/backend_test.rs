@@ -0,0 +1,250 @@
+extern crate libzou;
+
+#[cfg(test)]
+mod test_backend {
+    use libzou::authorization::AuthorizationHeaderFactory;
+    use libzou::backend::{Backend, BackendRegistry, BackendResult, Capabilities, Probe,
+                          RangeRequest};
+    use libzou::cargo_helper::get_remote_server_informations;
+    use libzou::download::{download_chunks, SplitConfig};
+    use libzou::memory::{download_to_vec, MemoryError};
+    use libzou::stream::{open_stream, StreamConfig};
+    use libzou::tls;
+    use libzou::write::OutputFileWriter;
+    use libzou::URL;
+    use std::fs::{remove_file, File};
+    use std::io;
+    use std::io::{Cursor, Read};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Backend that serves the same in-memory content for every URL
+    struct MemoryBackend {
+        content: Vec<u8>,
+    }
+
+    impl Backend for MemoryBackend {
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                ranges: true,
+                multiplexing: false,
+            }
+        }
+
+        fn probe(&self, _: URL, _: Option<&AuthorizationHeaderFactory>) -> BackendResult<Probe> {
+            Ok(Probe {
+                content_length: Some(self.content.len() as u64),
+                ..Probe::default()
+            })
+        }
+
+        fn open_range(&self, request: &RangeRequest) -> BackendResult<Box<dyn Read + Send>> {
+            let range = (request.range.0 as usize)..(request.range.1 as usize + 1);
+            Ok(Box::new(Cursor::new(self.content[range].to_vec())))
+        }
+    }
+
+    /// Backend that sends back garbage after the requested range - the first body fails after
+    /// a few bytes
+    struct SloppyBackend {
+        content: Vec<u8>,
+        has_failed: AtomicBool,
+    }
+
+    /// Reader that fails after the bytes of its content
+    struct FailingReader(Cursor<Vec<u8>>);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection reset")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Backend for SloppyBackend {
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                ranges: true,
+                multiplexing: false,
+            }
+        }
+
+        fn probe(&self, _: URL, _: Option<&AuthorizationHeaderFactory>) -> BackendResult<Probe> {
+            Ok(Probe {
+                content_length: Some(self.content.len() as u64),
+                ..Probe::default()
+            })
+        }
+
+        fn open_range(&self, request: &RangeRequest) -> BackendResult<Box<dyn Read + Send>> {
+            let begin = request.range.0 as usize;
+            if !self.has_failed.swap(true, Ordering::SeqCst) {
+                let end = begin + (request.range.length() / 2) as usize;
+                return Ok(Box::new(FailingReader(Cursor::new(self.content[begin..end].to_vec()))));
+            }
+            let mut body = self.content[begin..(request.range.1 as usize + 1)].to_vec();
+            body.extend_from_slice(&[0xff; 1000]);
+            Ok(Box::new(Cursor::new(body)))
+        }
+    }
+
+    fn memory_registry(content: &[u8]) -> BackendRegistry {
+        let mut registry = BackendRegistry::empty();
+        registry.register(
+            "mem",
+            Arc::new(MemoryBackend { content: content.to_vec() }),
+        );
+        registry
+    }
+
+    #[test]
+    fn unknown_scheme_should_not_have_a_backend() {
+        let registry = memory_registry(b"zou");
+        assert!(registry.get("http://localhost/zou.bin").is_none());
+        assert!(get_remote_server_informations("http://localhost/zou.bin", &registry).is_err());
+    }
+
+    #[test]
+    fn default_registry_should_support_default_schemes() {
+        let registry = BackendRegistry::default();
+        assert!(registry.get("http://localhost/zou.bin").is_some());
+        assert_eq!(tls::is_supported(), registry.get("https://localhost/zou.bin").is_some());
+        assert!(registry.get("file:///tmp/zou.bin").is_some());
+        assert!(registry.get("mem://zou.bin").is_none());
+    }
+
+    #[test]
+    fn download_using_a_custom_backend() {
+        let content: Vec<u8> = (0..500_000).map(|i| (i % 253) as u8).collect();
+        let registry = memory_registry(&content);
+        let output_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("test_files")
+            .join("custom_backend.out");
+
+        let informations = get_remote_server_informations("mem://zou.bin", &registry).unwrap();
+        assert_eq!(content.len() as u64, informations.file.content_length);
+
+        let output_file = File::create(&output_path).unwrap();
+        output_file.set_len(content.len() as u64).unwrap();
+        assert!(download_chunks(
+            informations,
+            OutputFileWriter::new(output_file),
+            3,
+            &registry,
+        ));
+
+        let mut downloaded_content = Vec::new();
+        File::open(&output_path)
+            .unwrap()
+            .read_to_end(&mut downloaded_content)
+            .unwrap();
+        remove_file(&output_path).unwrap();
+        assert!(content == downloaded_content);
+    }
+
+    #[test]
+    fn chunks_should_not_overflow_and_should_be_retried() {
+        let content: Vec<u8> = (0..500_000).map(|i| (i % 253) as u8).collect();
+        let mut registry = BackendRegistry::empty();
+        registry.register(
+            "sloppy",
+            Arc::new(SloppyBackend {
+                content: content.clone(),
+                has_failed: AtomicBool::new(false),
+            }),
+        );
+        let output_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("test_files")
+            .join("sloppy_backend.out");
+
+        let informations = get_remote_server_informations("sloppy://zou.bin", &registry).unwrap();
+        let output_file = File::create(&output_path).unwrap();
+        output_file.set_len(content.len() as u64).unwrap();
+        assert!(download_chunks(
+            informations,
+            OutputFileWriter::new(output_file),
+            4,
+            &registry,
+        ));
+
+        let mut downloaded_content = Vec::new();
+        File::open(&output_path)
+            .unwrap()
+            .read_to_end(&mut downloaded_content)
+            .unwrap();
+        remove_file(&output_path).unwrap();
+        assert!(content == downloaded_content);
+    }
+
+    #[test]
+    fn stream_should_read_the_chunks_in_order() {
+        let content: Vec<u8> = (0..500_000).map(|i| (i % 253) as u8).collect();
+        let registry = memory_registry(&content);
+        let informations = get_remote_server_informations("mem://zou.bin", &registry).unwrap();
+        let config = StreamConfig {
+            workers: 4,
+            chunk_size: 7_000,
+            max_buffered_chunks: 6,
+        };
+
+        let mut streamed_content = Vec::new();
+        open_stream(&informations, &registry, config)
+            .unwrap()
+            .read_to_end(&mut streamed_content)
+            .unwrap();
+        assert!(content == streamed_content);
+    }
+
+    #[test]
+    fn stream_should_retry_the_chunks() {
+        let content: Vec<u8> = (0..100_000).map(|i| (i % 253) as u8).collect();
+        let mut registry = BackendRegistry::empty();
+        registry.register(
+            "sloppy",
+            Arc::new(SloppyBackend {
+                content: content.clone(),
+                has_failed: AtomicBool::new(false),
+            }),
+        );
+        let informations = get_remote_server_informations("sloppy://zou.bin", &registry).unwrap();
+        let config = StreamConfig {
+            chunk_size: 10_000,
+            ..StreamConfig::new(2)
+        };
+
+        let mut streamed_content = Vec::new();
+        open_stream(&informations, &registry, config)
+            .unwrap()
+            .read_to_end(&mut streamed_content)
+            .unwrap();
+        assert!(content == streamed_content);
+    }
+
+    #[test]
+    fn download_in_memory() {
+        let content: Vec<u8> = (0..300_000).map(|i| (i % 253) as u8).collect();
+        let registry = memory_registry(&content);
+
+        let informations = get_remote_server_informations("mem://zou.bin", &registry).unwrap();
+        let downloaded_content =
+            download_to_vec(informations, SplitConfig::new(3), &registry, 1_000_000).unwrap();
+        assert!(content == downloaded_content);
+
+        let informations = get_remote_server_informations("mem://zou.bin", &registry).unwrap();
+        match download_to_vec(informations, SplitConfig::new(3), &registry, 1_000) {
+            Err(MemoryError::TooLarge {
+                content_length,
+                max_size,
+            }) => {
+                assert_eq!(300_000, content_length);
+                assert_eq!(1_000, max_size);
+            }
+            _ => panic!("the remote content should be too large"),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+extern crate libzou;
+
+mod common;
+
+#[cfg(test)]
+mod test_bench {
+    use common::{serve, test_content};
+    use libzou::backend::BackendRegistry;
+    use libzou::bench::{bench_urls, rank_mirror_urls, BenchError};
+    use libzou::mirror_scores::MirrorScores;
+    use std::time::Duration;
+
+    #[test]
+    fn mirrors_should_be_sorted_and_checked() {
+        let content = test_content(200_000);
+        let primary = serve(content.clone());
+        let mirror = serve(content.clone());
+        let stale_mirror = serve(test_content(100_000));
+
+        let urls = vec![
+            format!("{}/zou.bin", stale_mirror.url),
+            String::from("unknown://mirror/zou.bin"),
+            format!("{}/zou.bin", primary.url),
+            format!("{}/zou.bin", mirror.url),
+        ];
+        let results = bench_urls(
+            urls,
+            Some(content.len() as u64),
+            50_000,
+            &BackendRegistry::default(),
+        );
+
+        assert_eq!(4, results.len());
+        // The valid mirrors come first
+        for result in &results[0..2] {
+            let measures = result.measures.as_ref().unwrap();
+            assert_eq!(50_000, measures.bytes);
+            assert!(measures.ttfb <= measures.duration);
+        }
+        // The failing mirrors come last
+        assert!(results[2..].iter().all(|result| result.measures.is_err()));
+        let stale_result = results
+            .iter()
+            .find(|result| result.url.starts_with(&stale_mirror.url))
+            .unwrap();
+        match stale_result.measures {
+            Err(BenchError::ContentLengthMismatch { expected, found }) => {
+                assert_eq!(200_000, expected);
+                assert_eq!(100_000, found);
+            }
+            _ => panic!("the stale mirror should be detected"),
+        }
+    }
+
+    #[test]
+    fn scored_mirrors_should_not_be_benchmarked_again() {
+        let content = test_content(100_000);
+        let scored_mirror = serve(content.clone());
+        let new_mirror = serve(content.clone());
+        let scored_url = format!("{}/zou.bin", scored_mirror.url);
+        let new_url = format!("{}/zou.bin", new_mirror.url);
+        let registry = BackendRegistry::default();
+
+        // A huge throughput: the scored mirror has to be ranked first
+        let scores = MirrorScores::default();
+        scores.record_throughput(&scored_url, 1 << 40, Duration::from_secs(1));
+        let ranked_urls = rank_mirror_urls(
+            vec![new_url.clone(), scored_url.clone()],
+            content.len() as u64,
+            &registry,
+            &scores,
+            false,
+        );
+        assert_eq!(vec![scored_url.clone(), new_url.clone()], ranked_urls);
+        assert_eq!(0, scored_mirror.connections());
+        assert!(scores.get(&new_url).is_some());
+
+        // Unless the mirrors are benchmarked again
+        rank_mirror_urls(vec![scored_url], content.len() as u64, &registry, &scores, true);
+        assert!(scored_mirror.connections() > 0);
+    }
+}
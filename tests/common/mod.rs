@@ -0,0 +1,200 @@
+//! A minimal HTTP/1.1 server, to test zou without any network access.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// A running test server, that serves the same content for every path
+pub struct TestServer {
+    /// The base URL of the server, without any trailing slash
+    pub url: String,
+    connections: Arc<AtomicUsize>,
+}
+
+impl TestServer {
+    /// Returns the number of TCP connections accepted by the server
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+/// Returns a deterministic content of the given length
+pub fn test_content(length: usize) -> Vec<u8> {
+    (0..length).map(|i| (i % 251) as u8).collect()
+}
+
+/// Start a server on a random local port, that serves `content` (with ranges support)
+pub fn serve(content: Vec<u8>) -> TestServer {
+    serve_truncated(content, usize::MAX)
+}
+
+/// Start a server like `serve`, but the server closes the connection after sending
+/// `max_body_bytes` bytes of any body - it announces the whole length anyway
+pub fn serve_truncated(content: Vec<u8>, max_body_bytes: usize) -> TestServer {
+    start(Response::Content(content, max_body_bytes, true), Vec::new())
+}
+
+/// Start a server like `serve`, but the responses to HEAD requests do not send the length of
+/// the content
+pub fn serve_without_head_length(content: Vec<u8>) -> TestServer {
+    start(Response::Content(content, usize::MAX, false), Vec::new())
+}
+
+/// Start a server like `serve`, that sends the given headers (`Name: value`) in each response
+pub fn serve_with_headers(content: Vec<u8>, headers: Vec<String>) -> TestServer {
+    start(Response::Content(content, usize::MAX, true), headers)
+}
+
+/// Start a server that redirects every request to `location` (302 Found), sending the given
+/// headers (`Name: value`) along with the redirection
+pub fn serve_redirection(location: String, headers: Vec<String>) -> TestServer {
+    start(Response::Redirection(location), headers)
+}
+
+/// What the test server answers to every request
+enum Response {
+    /// The content, with ranges support, the maximum number of bytes sent for any body, and
+    /// true if the length of the content is sent to HEAD requests
+    Content(Vec<u8>, usize, bool),
+    /// A redirection to the given URL
+    Redirection(String),
+}
+
+/// Start the server on a random local port
+fn start(response: Response, headers: Vec<String>) -> TestServer {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let connections = Arc::new(AtomicUsize::new(0));
+    let response = Arc::new((response, headers));
+
+    let accepted = connections.clone();
+    thread::spawn(move || for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        accepted.fetch_add(1, Ordering::SeqCst);
+        let response = response.clone();
+        thread::spawn(move || match response.0 {
+            Response::Content(ref content, max_body_bytes, head_length) => {
+                handle_connection(stream, content, max_body_bytes, head_length, &response.1)
+            }
+            Response::Redirection(ref location) => {
+                handle_redirections(stream, location, &response.1)
+            }
+        });
+    });
+
+    TestServer { url, connections }
+}
+
+/// Parse a `Range: bytes=<begin>-[<end>]` header value
+fn parse_range(value: &str, length: usize) -> Option<(usize, usize)> {
+    let mut bounds = value.trim().trim_start_matches("bytes=").splitn(2, '-');
+    let begin = bounds.next()?.trim().parse().ok()?;
+    let end = match bounds.next()?.trim() {
+        "" => length - 1,
+        end => end.parse().ok()?,
+    };
+    Some((begin, end))
+}
+
+/// Answer the redirection to every request sent through a (kept alive) connection
+fn handle_redirections(stream: TcpStream, location: &str, headers: &[String]) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut line = String::new();
+        // Skip the request line and the headers
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            if line.trim_end().is_empty() {
+                break;
+            }
+        }
+        let mut head = format!(
+            "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\n",
+            location
+        );
+        for header in headers {
+            head += &format!("{}\r\n", header);
+        }
+        head += "\r\n";
+        if writer.write_all(head.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Answer the requests sent through a (kept alive) connection
+fn handle_connection(
+    stream: TcpStream,
+    content: &[u8],
+    max_body_bytes: usize,
+    head_length: bool,
+    headers: &[String],
+) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+        let method = request_line.split(' ').next().unwrap_or("").to_owned();
+
+        let mut range = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let mut header = line.splitn(2, ':');
+            let name = header.next().unwrap_or("").trim().to_lowercase();
+            let value = header.next().unwrap_or("").trim();
+            if name == "range" {
+                range = parse_range(value, content.len());
+            }
+        }
+
+        let (status, body) = match range {
+            Some((begin, end)) => ("206 Partial Content", &content[begin..end + 1]),
+            None => ("200 OK", content),
+        };
+        let mut head = format!("HTTP/1.1 {}\r\nAccept-Ranges: bytes\r\n", status);
+        if method != "HEAD" || head_length {
+            head += &format!("Content-Length: {}\r\n", body.len());
+        }
+        if let Some((begin, end)) = range {
+            head += &format!("Content-Range: bytes {}-{}/{}\r\n", begin, end, content.len());
+        }
+        for header in headers {
+            head += &format!("{}\r\n", header);
+        }
+        head += "\r\n";
+        if writer.write_all(head.as_bytes()).is_err() {
+            return;
+        }
+        if method == "HEAD" {
+            continue;
+        }
+        if body.len() > max_body_bytes {
+            let _ = writer.write_all(&body[..max_body_bytes]);
+            return;
+        }
+        if writer.write_all(body).is_err() {
+            return;
+        }
+    }
+}
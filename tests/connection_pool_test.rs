@@ -0,0 +1,119 @@
+extern crate libzou;
+
+mod common;
+
+#[cfg(test)]
+mod test_connection_pool {
+    use common::{serve, serve_without_head_length, test_content};
+    use libzou::adaptive::ConnectionController;
+    use libzou::backend::BackendRegistry;
+    use libzou::cargo_helper::get_remote_server_informations;
+    use libzou::client::Config;
+    use libzou::download::{download_chunks_from_mirrors, SplitConfig};
+    use libzou::write::OutputFileWriter;
+    use std::fs::{remove_file, File};
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Download the content of a test server, and returns the number of connections it accepted.
+    /// If `adaptive_max_connections` is not 0, the number of connections is adaptive.
+    fn download(
+        name: &str,
+        max_connections_per_host: usize,
+        split: SplitConfig,
+        adaptive_max_connections: usize,
+    ) -> usize {
+        let content = test_content(400_000);
+        let server = serve(content.clone());
+        let url = format!("{}/{}", server.url, name);
+        let output_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("test_files")
+            .join(name);
+
+        let registry = BackendRegistry::with_http_config(Config {
+            max_connections_per_host,
+            ..Config::default()
+        }).unwrap();
+        let informations = get_remote_server_informations(&url, &registry).unwrap();
+        let output_file = File::create(&output_path).unwrap();
+        output_file.set_len(informations.file.content_length).unwrap();
+        let out_file = OutputFileWriter::new(output_file);
+        let controller = if adaptive_max_connections == 0 {
+            None
+        } else {
+            Some(Arc::new(ConnectionController::new(adaptive_max_connections)))
+        };
+        assert!(download_chunks_from_mirrors(
+            informations,
+            Vec::new(),
+            out_file,
+            split,
+            &registry,
+            None,
+            controller,
+        ));
+
+        let mut downloaded_content = Vec::new();
+        File::open(&output_path)
+            .unwrap()
+            .read_to_end(&mut downloaded_content)
+            .unwrap();
+        remove_file(&output_path).unwrap();
+        assert!(content == downloaded_content);
+        server.connections()
+    }
+
+    #[test]
+    fn chunks_should_reuse_a_single_connection() {
+        assert_eq!(1, download("single_connection.out", 1, SplitConfig::new(4), 0));
+    }
+
+    #[test]
+    fn connections_per_host_should_be_limited() {
+        assert!(download("two_connections.out", 2, SplitConfig::new(4), 0) <= 2);
+    }
+
+    #[test]
+    fn adaptive_download_should_respect_the_maximum_connections() {
+        assert!(download("adaptive_connections.out", 0, SplitConfig::new(8), 3) <= 3);
+    }
+
+    #[test]
+    fn workers_should_download_every_chunk_of_the_queue() {
+        // 40 chunks, downloaded by 2 workers
+        let split = SplitConfig {
+            chunk_size: Some(10_000),
+            ..SplitConfig::new(2)
+        };
+        assert!(download("small_chunks.out", 0, split, 0) <= 2);
+    }
+
+    #[test]
+    fn probe_without_head_length_should_not_wait_for_a_connection() {
+        let content = test_content(10_000);
+        let server = serve_without_head_length(content.clone());
+        let url = format!("{}/no_head_length", server.url);
+        let (sender, receiver) = channel();
+        // The HEAD response is not enough, so a second request is sent: it must reuse the
+        // single connection allowed for the host
+        thread::spawn(move || {
+            let registry = BackendRegistry::with_http_config(Config {
+                max_connections_per_host: 1,
+                ..Config::default()
+            }).unwrap();
+            let content_length = get_remote_server_informations(&url, &registry)
+                .ok()
+                .map(|informations| informations.file.content_length);
+            sender.send(content_length).unwrap();
+        });
+        assert_eq!(
+            Some(content.len() as u64),
+            receiver.recv_timeout(Duration::from_secs(10)).unwrap()
+        );
+    }
+}
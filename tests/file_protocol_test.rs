@@ -0,0 +1,161 @@
+extern crate libzou;
+
+#[cfg(test)]
+mod test_file_protocol {
+    use libzou::backend::BackendRegistry;
+    use libzou::cargo_helper::get_remote_server_informations;
+    use libzou::download::{download_chunks, download_chunks_from_mirrors, SplitConfig};
+    use libzou::write::OutputFileWriter;
+    use std::fs::{remove_file, File, OpenOptions};
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn test_file_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("test_files")
+            .join(name)
+    }
+
+    fn create_source_file(path: &PathBuf, length: usize) -> Vec<u8> {
+        let content: Vec<u8> = (0..length).map(|i| (i % 251) as u8).collect();
+        File::create(path).unwrap().write_all(&content).unwrap();
+        content
+    }
+
+    /// Copy a local file, using positional writes or a memory mapping of the output file
+    fn copy_using_chunks(name: &str, length: usize, nb_chunks: u64, mmap: bool) {
+        let source_path = test_file_path(&format!("{}.src", name));
+        let output_path = test_file_path(&format!("{}.out", name));
+        let content = create_source_file(&source_path, length);
+        let url = format!("file://{}", source_path.to_str().unwrap());
+
+        let registry = BackendRegistry::default();
+        let informations = get_remote_server_informations(&url, &registry).unwrap();
+        assert_eq!(length as u64, informations.file.content_length);
+
+        let output_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&output_path)
+            .unwrap();
+        output_file.set_len(length as u64).unwrap();
+        let out_file = if mmap {
+            OutputFileWriter::with_mmap(output_file, length as u64).unwrap()
+        } else {
+            OutputFileWriter::new(output_file)
+        };
+        assert!(download_chunks(
+            informations,
+            out_file,
+            nb_chunks,
+            &registry,
+        ));
+
+        let mut copied_content = Vec::new();
+        File::open(&output_path)
+            .unwrap()
+            .read_to_end(&mut copied_content)
+            .unwrap();
+        remove_file(&source_path).unwrap();
+        remove_file(&output_path).unwrap();
+        assert!(content == copied_content);
+    }
+
+    #[test]
+    fn copy_a_local_file_using_one_chunk() {
+        copy_using_chunks("one_chunk", 100_000, 1, false);
+    }
+
+    #[test]
+    fn copy_a_local_file_using_many_chunks() {
+        copy_using_chunks("many_chunks", 3_000_000, 4, false);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_a_local_file_in_a_memory_mapping() {
+        copy_using_chunks("mmap_chunks", 3_000_000, 4, true);
+    }
+
+    #[test]
+    fn resumed_copy_should_keep_the_local_bytes() {
+        let source_path = test_file_path("resume.src");
+        let output_path = test_file_path("resume.out");
+        let content = create_source_file(&source_path, 500_000);
+        let url = format!("file://{}", source_path.to_str().unwrap());
+        // The local bytes differ from the remote ones, to check they are not downloaded again
+        File::create(&output_path)
+            .unwrap()
+            .write_all(&vec![0xff; 100_000])
+            .unwrap();
+
+        let registry = BackendRegistry::default();
+        let informations = get_remote_server_informations(&url, &registry).unwrap();
+        let output_file = OpenOptions::new().write(true).open(&output_path).unwrap();
+        output_file.set_len(500_000).unwrap();
+        assert!(download_chunks_from_mirrors(
+            informations,
+            Vec::new(),
+            OutputFileWriter::new(output_file),
+            SplitConfig {
+                resume_from: 100_000,
+                ..SplitConfig::new(4)
+            },
+            &registry,
+            None,
+            None,
+        ));
+
+        let mut copied_content = Vec::new();
+        File::open(&output_path)
+            .unwrap()
+            .read_to_end(&mut copied_content)
+            .unwrap();
+        remove_file(&source_path).unwrap();
+        remove_file(&output_path).unwrap();
+        assert!(copied_content[0..100_000].iter().all(|byte| *byte == 0xff));
+        assert!(content[100_000..] == copied_content[100_000..]);
+    }
+
+    #[test]
+    fn changed_source_should_abort_the_download() {
+        let source_path = test_file_path("changed.src");
+        let output_path = test_file_path("changed.out");
+        create_source_file(&source_path, 100_000);
+        let url = format!("file://{}", source_path.to_str().unwrap());
+
+        let registry = BackendRegistry::default();
+        let informations = get_remote_server_informations(&url, &registry).unwrap();
+        // The source file is modified after the download started
+        let last_modified = informations.file.last_modified.unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open(&source_path)
+            .unwrap()
+            .set_modified(last_modified + Duration::from_secs(60))
+            .unwrap();
+        let output_file = File::create(&output_path).unwrap();
+        let is_downloaded = download_chunks(
+            informations,
+            OutputFileWriter::new(output_file),
+            4,
+            &registry,
+        );
+        remove_file(&source_path).unwrap();
+        remove_file(&output_path).unwrap();
+        assert!(!is_downloaded);
+    }
+
+    #[test]
+    fn missing_local_file_should_return_an_error() {
+        let url = format!(
+            "file://{}",
+            test_file_path("missing.src").to_str().unwrap()
+        );
+        assert!(get_remote_server_informations(&url, &BackendRegistry::default()).is_err());
+    }
+}
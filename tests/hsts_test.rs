@@ -0,0 +1,47 @@
+extern crate hyper;
+extern crate libzou;
+
+mod common;
+
+#[cfg(test)]
+mod test_hsts {
+    use common::{serve, serve_redirection, test_content};
+    use hyper::header::{Headers, StrictTransportSecurity};
+    use hyper::Url;
+    use libzou::backend::BackendRegistry;
+    use libzou::client::Config;
+    use libzou::hsts::HstsStore;
+    use std::sync::Arc;
+
+    /// Probe the content of a test server through a redirection, using the given HSTS store.
+    /// Returns true if the probe succeeded, and the number of connections accepted by the
+    /// server of the content.
+    fn probe_through_redirection(hsts: Option<Arc<HstsStore>>) -> (bool, usize) {
+        let server = serve(test_content(1000));
+        // The redirection targets a domain name, as the IP addresses are never HSTS hosts
+        let location = server.url.replace("127.0.0.1", "localhost") + "/zou.bin";
+        let redirector = serve_redirection(location, Vec::new());
+        let registry = BackendRegistry::with_http_config(Config {
+            hsts,
+            ..Config::default()
+        }).unwrap();
+        let url = format!("{}/zou.bin", redirector.url);
+        let backend = registry.get(&url).unwrap();
+        let is_probed = backend.probe(&url, None).is_ok();
+        (is_probed, server.connections())
+    }
+
+    #[test]
+    fn redirection_to_a_known_host_should_be_upgraded() {
+        // Without HSTS, the redirection is followed in clear text
+        assert_eq!((true, 1), probe_through_redirection(None));
+
+        let hsts = HstsStore::new();
+        let mut headers = Headers::new();
+        headers.set(StrictTransportSecurity::excluding_subdomains(3600));
+        hsts.record(&Url::parse("https://localhost/").unwrap(), &headers);
+        // The redirection is upgraded to HTTPS, that the test server does not speak
+        let (is_probed, _) = probe_through_redirection(Some(Arc::new(hsts)));
+        assert!(!is_probed);
+    }
+}
@@ -0,0 +1,207 @@
+#![cfg(feature = "async-http")]
+
+extern crate http_body_util;
+extern crate hyper1;
+extern crate hyper_util;
+extern crate libzou;
+extern crate rcgen;
+extern crate tokio;
+extern crate tokio_rustls;
+
+mod common;
+
+#[cfg(test)]
+mod test_http2 {
+    use common::test_content;
+    use http_body_util::Full;
+    use hyper1::body::{Bytes, Incoming};
+    use hyper1::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+    use hyper1::server::conn::http2;
+    use hyper1::service::service_fn;
+    use hyper1::{Method, Request, Response, StatusCode};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use libzou::backend::BackendRegistry;
+    use libzou::cargo_helper::get_remote_server_informations;
+    use libzou::client::Config;
+    use libzou::download::{download_chunks_from_mirrors, SplitConfig};
+    use libzou::tls::TlsConfig;
+    use libzou::write::OutputFileWriter;
+    use rcgen::generate_simple_self_signed;
+    use std::convert::Infallible;
+    use std::fs::{remove_file, write, File};
+    use std::future::ready;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use tokio::net::TcpStream;
+    use tokio::runtime::{Builder, Runtime};
+    use tokio_rustls::rustls::crypto::ring::default_provider;
+    use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    /// A running HTTPS server, that only speaks HTTP/2
+    struct Http2Server {
+        url: String,
+        connections: Arc<AtomicUsize>,
+        runtime: Option<Runtime>,
+    }
+
+    impl Http2Server {
+        /// Returns the number of TCP connections accepted by the server
+        fn connections(&self) -> usize {
+            self.connections.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Drop for Http2Server {
+        fn drop(&mut self) {
+            if let Some(runtime) = self.runtime.take() {
+                runtime.shutdown_background();
+            }
+        }
+    }
+
+    /// Returns the response to a request for the content, with ranges support
+    fn respond(content: &[u8], request: &Request<Incoming>) -> Response<Full<Bytes>> {
+        let range = request
+            .headers()
+            .get(RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let mut bounds = value.trim_start_matches("bytes=").splitn(2, '-');
+                let begin: usize = bounds.next()?.parse().ok()?;
+                let end: usize = bounds.next()?.parse().ok()?;
+                Some((begin, end))
+            });
+        let (status, body) = match range {
+            Some((begin, end)) => (StatusCode::PARTIAL_CONTENT, &content[begin..end + 1]),
+            None => (StatusCode::OK, content),
+        };
+        let mut response = Response::builder()
+            .status(status)
+            .header(ACCEPT_RANGES, "bytes")
+            .header(CONTENT_LENGTH, body.len());
+        if let Some((begin, end)) = range {
+            response = response.header(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", begin, end, content.len()),
+            );
+        }
+        let body = if request.method() == Method::HEAD {
+            Bytes::new()
+        } else {
+            Bytes::copy_from_slice(body)
+        };
+        response.body(Full::new(body)).unwrap()
+    }
+
+    /// Start a server on a random local port, that serves `content` through HTTP/2 only - the
+    /// certificate of the server is written to `cert_path`
+    fn serve_http2(content: Vec<u8>, cert_path: &Path) -> Http2Server {
+        let certified = generate_simple_self_signed(vec![String::from("127.0.0.1")]).unwrap();
+        write(cert_path, certified.cert.pem()).unwrap();
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let mut tls_config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], PrivateKeyDer::Pkcs8(key))
+            .unwrap();
+        tls_config.alpn_protocols = vec![b"h2".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+        let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
+        let handle = runtime.handle().clone();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("https://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let content = Arc::new(content);
+
+        let accepted = connections.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let handle = handle.clone();
+            let acceptor = acceptor.clone();
+            let content = content.clone();
+            thread::spawn(move || {
+                let _runtime = handle.enter();
+                stream.set_nonblocking(true).unwrap();
+                let stream = TcpStream::from_std(stream).unwrap();
+                let stream = match handle.block_on(acceptor.accept(stream)) {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                let service = service_fn(move |request: Request<Incoming>| {
+                    ready(Ok::<_, Infallible>(respond(&content, &request)))
+                });
+                let connection = http2::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service);
+                let _ = handle.block_on(connection);
+            });
+        });
+
+        Http2Server {
+            url,
+            connections,
+            runtime: Some(runtime),
+        }
+    }
+
+    #[test]
+    fn chunks_should_be_multiplexed_on_a_single_connection() {
+        let test_files = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("test_files");
+        let cert_path = test_files.join("http2_cert.pem");
+        let output_path = test_files.join("multiplexed.out");
+        let content = test_content(400_000);
+        let server = serve_http2(content.clone(), &cert_path);
+        let url = format!("{}/multiplexed.out", server.url);
+
+        // The number of connections per server is not limited: the streams share a connection
+        // anyway
+        let registry = BackendRegistry::with_http_config(Config {
+            tls: TlsConfig {
+                ca_file: Some(cert_path.clone()),
+                ..TlsConfig::default()
+            },
+            ..Config::default()
+        }).unwrap();
+        let informations = get_remote_server_informations(&url, &registry).unwrap();
+        assert!(informations.multiplexed);
+        let output_file = File::create(&output_path).unwrap();
+        output_file.set_len(informations.file.content_length).unwrap();
+        // 8 chunks, downloaded by 4 threads
+        let split = SplitConfig {
+            chunk_size: Some(50_000),
+            ..SplitConfig::new(4)
+        };
+        assert!(download_chunks_from_mirrors(
+            informations,
+            Vec::new(),
+            OutputFileWriter::new(output_file),
+            split,
+            &registry,
+            None,
+            None,
+        ));
+
+        let mut downloaded_content = Vec::new();
+        File::open(&output_path)
+            .unwrap()
+            .read_to_end(&mut downloaded_content)
+            .unwrap();
+        remove_file(&output_path).unwrap();
+        remove_file(&cert_path).unwrap();
+        assert!(content == downloaded_content);
+        assert_eq!(1, server.connections());
+    }
+}
@@ -25,4 +25,14 @@ mod test_http_versions {
     fn version_20_succeeds() {
         assert!(HttpVersion::Http20.greater_than_http_11())
     }
+
+    #[test]
+    fn version_11_is_not_multiplexed() {
+        assert!(!HttpVersion::Http11.is_multiplexed())
+    }
+
+    #[test]
+    fn version_20_is_multiplexed() {
+        assert!(HttpVersion::Http20.is_multiplexed())
+    }
 }
@@ -0,0 +1,108 @@
+extern crate base64;
+extern crate libzou;
+
+mod common;
+
+#[cfg(test)]
+mod test_metalink {
+    use base64;
+    use common::{serve, serve_redirection, serve_with_headers, test_content};
+    use libzou::backend::BackendRegistry;
+    use libzou::cargo_helper::get_remote_server_informations;
+    use libzou::download::{download_chunks_from_mirrors, SplitConfig};
+    use libzou::metalink::{compute_digest, verify_file, DigestAlgorithm};
+    use libzou::write::OutputFileWriter;
+    use std::fs::{remove_file, File};
+    use std::path::PathBuf;
+
+    fn test_file_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("test_files")
+            .join(name)
+    }
+
+    fn digest_header(content: &[u8]) -> String {
+        format!(
+            "Digest: SHA-256={}",
+            base64::encode(&compute_digest(DigestAlgorithm::Sha256, content).unwrap())
+        )
+    }
+
+    #[test]
+    fn redirector_should_advertise_mirrors_and_digest() {
+        let content = test_content(300_000);
+        let first_mirror = serve(content.clone());
+        let second_mirror = serve(content.clone());
+        let redirector = serve_redirection(
+            format!("{}/metalink.bin", first_mirror.url),
+            vec![
+                format!(
+                    "Link: <{}/metalink.bin>; rel=duplicate; pri=2; geo=fr",
+                    first_mirror.url
+                ),
+                format!("Link: <{}/metalink.bin>; rel=duplicate; pri=1", second_mirror.url),
+                digest_header(&content),
+            ],
+        );
+        let registry = BackendRegistry::default();
+        let url = format!("{}/metalink.bin", redirector.url);
+        let informations = get_remote_server_informations(&url, &registry).unwrap();
+
+        assert_eq!(content.len() as u64, informations.file.content_length);
+        assert_eq!(
+            vec![
+                format!("{}/metalink.bin", second_mirror.url),
+                format!("{}/metalink.bin", first_mirror.url),
+            ],
+            informations.mirrors
+        );
+        assert_eq!(1, informations.file.digests.len());
+        assert_eq!(DigestAlgorithm::Sha256, informations.file.digests[0].algorithm);
+
+        // Download from the redirector and the advertised mirrors, then check the digest
+        let output_path = test_file_path("metalink.bin");
+        let output_file = File::create(&output_path).unwrap();
+        output_file.set_len(content.len() as u64).unwrap();
+        let digests = informations.file.digests.clone();
+        let mirrors = informations.mirrors.clone();
+        assert!(download_chunks_from_mirrors(
+            informations,
+            mirrors,
+            OutputFileWriter::new(output_file),
+            SplitConfig::new(4),
+            &registry,
+            None,
+            None,
+        ));
+        let verification = verify_file(&output_path, &digests);
+        remove_file(&output_path).unwrap();
+        assert_eq!(Some(DigestAlgorithm::Sha256), verification.unwrap());
+    }
+
+    #[test]
+    fn wrong_digest_should_be_detected() {
+        let content = test_content(1000);
+        let server = serve_with_headers(content.clone(), vec![digest_header(b"zou")]);
+        let registry = BackendRegistry::default();
+        let url = format!("{}/wrong_digest.bin", server.url);
+        let informations = get_remote_server_informations(&url, &registry).unwrap();
+
+        let output_path = test_file_path("wrong_digest.bin");
+        let output_file = File::create(&output_path).unwrap();
+        output_file.set_len(content.len() as u64).unwrap();
+        let digests = informations.file.digests.clone();
+        assert!(download_chunks_from_mirrors(
+            informations,
+            Vec::new(),
+            OutputFileWriter::new(output_file),
+            SplitConfig::new(2),
+            &registry,
+            None,
+            None,
+        ));
+        let verification = verify_file(&output_path, &digests);
+        remove_file(&output_path).unwrap();
+        assert!(verification.is_err());
+    }
+}
@@ -0,0 +1,101 @@
+extern crate libzou;
+
+mod common;
+
+#[cfg(test)]
+mod test_mirrors {
+    use common::{serve, serve_truncated, test_content};
+    use libzou::backend::BackendRegistry;
+    use libzou::cargo_helper::get_remote_server_informations;
+    use libzou::download::{download_chunks_from_mirrors, SplitConfig};
+    use libzou::write::OutputFileWriter;
+    use std::fs::{remove_file, File};
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    fn test_file_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("test_files")
+            .join(name)
+    }
+
+    /// Download the content from `url` and the mirrors, and returns the downloaded content
+    fn download(name: &str, url: &str, mirror_urls: Vec<String>, length: u64) -> Option<Vec<u8>> {
+        let registry = BackendRegistry::default();
+        let output_path = test_file_path(name);
+        let informations = get_remote_server_informations(url, &registry).unwrap();
+        assert_eq!(length, informations.file.content_length);
+
+        let output_file = File::create(&output_path).unwrap();
+        output_file.set_len(length).unwrap();
+        let is_downloaded = download_chunks_from_mirrors(
+            informations,
+            mirror_urls,
+            OutputFileWriter::new(output_file),
+            SplitConfig::new(4),
+            &registry,
+            None,
+            None,
+        );
+
+        let mut downloaded_content = Vec::new();
+        File::open(&output_path)
+            .unwrap()
+            .read_to_end(&mut downloaded_content)
+            .unwrap();
+        remove_file(&output_path).unwrap();
+        if is_downloaded {
+            Some(downloaded_content)
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn failing_source_should_be_replaced_by_a_mirror() {
+        let content = test_content(400_000);
+        // The original server closes every connection after sending 10 000 bytes
+        let broken_server = serve_truncated(content.clone(), 10_000);
+        let mirror = serve(content.clone());
+
+        let downloaded_content = download(
+            "failover.bin",
+            &format!("{}/failover.bin", broken_server.url),
+            vec![format!("{}/failover.bin", mirror.url)],
+            content.len() as u64,
+        );
+        assert!(downloaded_content == Some(content));
+    }
+
+    #[test]
+    fn mirror_serving_another_content_should_be_ignored() {
+        let content = test_content(400_000);
+        let mut other_content = content.clone();
+        other_content.reverse();
+        let server = serve(content.clone());
+        let other_mirror = serve(other_content);
+
+        let downloaded_content = download(
+            "inconsistent_mirror.bin",
+            &format!("{}/inconsistent_mirror.bin", server.url),
+            vec![format!("{}/inconsistent_mirror.bin", other_mirror.url)],
+            content.len() as u64,
+        );
+        assert!(downloaded_content == Some(content));
+    }
+
+    #[test]
+    fn download_should_fail_if_every_source_fails() {
+        let content = test_content(400_000);
+        let broken_server = serve_truncated(content.clone(), 10_000);
+
+        let downloaded_content = download(
+            "every_source_fails.bin",
+            &format!("{}/every_source_fails.bin", broken_server.url),
+            Vec::new(),
+            content.len() as u64,
+        );
+        assert!(downloaded_content.is_none());
+    }
+}